@@ -1,201 +1,1670 @@
 //! Represents the CEDA website and provides methods to interact with it.
 
+use crate::datastore::FileProperties;
 use crate::error::AppError as Error;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use futures::stream::StreamExt;
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
 use regex::Regex;
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::header::{
+    HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, LAST_MODIFIED, RETRY_AFTER, USER_AGENT,
+};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
 use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
 use std::env;
-use std::path::Path;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::fs::File;
-use tokio::io::copy;
+use tokio::io::{copy, AsyncReadExt};
+use tokio::sync::{Semaphore, SemaphorePermit};
 use tokio_util::io::StreamReader;
 
+/// Root of the CEDA archive website
+const CEDA_ROOT: &str = "https://data.ceda.ac.uk";
+
+/// `User-Agent` sent to CEDA unless overridden
+pub const DEFAULT_USER_AGENT: &str = concat!("rust-ceda/", env!("CARGO_PKG_VERSION"));
+
+/// Retries of a request after a connection failure or server error, unless the crawl policy
+/// says otherwise
+const DEFAULT_MAX_RETRIES: u32 = 2;
+/// Delay before the first retry, unless the crawl policy says otherwise
+const DEFAULT_BASE_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest `Retry-After` honoured, so a far-off date can't stall a crawl
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Idle connections kept open to each host, so bursts of requests reuse them instead of
+/// reconnecting
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+/// How long an idle connection is kept open for reuse
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Interval of TCP keep-alive probes on open connections
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Query parameter carrying the access token in `AuthMode::QueryParam`
+const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+/// How the access token is sent to CEDA
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMode {
+    /// An `Authorization: Bearer` header
+    #[default]
+    Bearer,
+    /// An `access_token` query parameter on every URL
+    QueryParam,
+}
+
+/// A secret such as an access token, which is never printed by `Debug`
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    /// The secret itself, to be sent to CEDA and never logged
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(<redacted>)")
+    }
+}
+
+/// How hard the client presses CEDA: the requests in flight and per second, and how failed
+/// requests are retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrawlPolicy {
+    /// Maximum requests in flight at once, or `None` for no limit
+    pub concurrency: Option<NonZeroUsize>,
+    /// Maximum requests per second, or `None` for no limit
+    pub rate: Option<NonZeroU32>,
+    /// Retries of a request after a connection failure or server error
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each retry after it
+    pub base_backoff: Duration,
+}
+
+impl Default for CrawlPolicy {
+    fn default() -> Self {
+        Self {
+            concurrency: None,
+            rate: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_backoff: DEFAULT_BASE_BACKOFF,
+        }
+    }
+}
+
+impl CrawlPolicy {
+    /// A conservative policy for a well-behaved crawl: few requests at once, a low rate, and
+    /// patient retries
+    pub fn polite() -> Self {
+        Self {
+            concurrency: NonZeroUsize::new(2),
+            rate: NonZeroU32::new(2),
+            max_retries: 5,
+            base_backoff: Duration::from_secs(2),
+        }
+    }
+
+    /// The delay before a retry, counting retries from 1
+    fn backoff(&self, retry: u32) -> Duration {
+        self.base_backoff * 2u32.saturating_pow(retry.saturating_sub(1))
+    }
+}
+
 /// Represents the CEDA client
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct CedaClient {
     dataset_version: String,
     client: reqwest::Client,
-    root: String,
+    /// Headers sent with every request, kept to rebuild `client`. The access token header is
+    /// marked sensitive.
+    headers: HeaderMap,
+    /// Settings `client` was built with, kept to rebuild it
+    http_settings: HttpSettings,
+    /// Root of the archive that relative links are resolved against
+    pub(crate) root: String,
+    semaphore: Option<Arc<Semaphore>>,
+    limiter: Option<Arc<DefaultDirectRateLimiter>>,
+    policy: CrawlPolicy,
+    cache: Option<PageCache>,
+    /// Time the client may spend fetching pages, shared by its clones
+    fetch_budget: Option<Arc<FetchBudget>>,
+    compress: bool,
+    user_agent: HeaderValue,
+    /// Access token appended to URLs in `AuthMode::QueryParam`
+    query_token: Option<SecretString>,
+    stats: Arc<RequestStats>,
+}
+
+/// Leaves out the HTTP client and headers, which carry the access token
+impl fmt::Debug for CedaClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CedaClient")
+            .field("dataset_version", &self.dataset_version)
+            .field("root", &self.root)
+            .field("http_settings", &self.http_settings)
+            .field("policy", &self.policy)
+            .field("cache", &self.cache)
+            .field("fetch_budget", &self.fetch_budget)
+            .field("compress", &self.compress)
+            .field("user_agent", &self.user_agent)
+            .field("query_token", &self.query_token)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Counts of the requests made by a client and all its clones. Pages served from the cache are
+/// not counted.
+#[derive(Debug, Default)]
+pub struct RequestStats {
+    requests: AtomicU64,
+    /// Requests that needed at least one retry
+    retried: AtomicU64,
+    /// Requests that failed after any retries
+    failed: AtomicU64,
+}
+
+impl fmt::Display for RequestStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests, {} retried, {} failed",
+            with_thousands(self.requests.load(Ordering::Relaxed)),
+            with_thousands(self.retried.load(Ordering::Relaxed)),
+            with_thousands(self.failed.load(Ordering::Relaxed))
+        )
+    }
+}
+
+/// A limit on the time spent fetching pages, e.g. for one station
+#[derive(Debug)]
+struct FetchBudget {
+    limit: Duration,
+    spent: Mutex<Duration>,
+}
+
+impl FetchBudget {
+    /// Run a fetch with whatever is left of the budget, failing with `StationTimeout` if it runs
+    /// out
+    async fn spend<T>(&self, fetch: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        let remaining = self.limit.saturating_sub(*self.spent.lock().unwrap());
+        let started = Instant::now();
+        let result = tokio::time::timeout(remaining, fetch).await;
+        *self.spent.lock().unwrap() += started.elapsed();
+
+        result.unwrap_or(Err(Error::StationTimeout(self.limit)))
+    }
+}
+
+/// An on-disk cache of fetched HTML pages, keyed by URL
+#[derive(Debug, Clone)]
+struct PageCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl PageCache {
+    /// The cache file of a URL, named by the URL's SHA-256, which is the same in every build
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:x}.html", Sha256::digest(url.as_bytes())))
+    }
+
+    /// Get a cached page, if present and younger than the TTL
+    async fn get(&self, url: &str) -> Option<String> {
+        let path = self.path(url);
+        let modified = tokio::fs::metadata(&path).await.ok()?.modified().ok()?;
+        if modified.elapsed().ok()? > self.ttl {
+            return None;
+        }
+
+        tokio::fs::read_to_string(&path).await.ok()
+    }
+
+    /// Cache a page. Failing to write the cache is not an error.
+    async fn put(&self, url: &str, body: &str) {
+        if tokio::fs::create_dir_all(&self.dir).await.is_ok() {
+            let _ = tokio::fs::write(self.path(url), body).await;
+        }
+    }
+}
+
+/// A link to a data file, with the details parsed from its filename
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DataFileLink {
+    pub url: String,
+    pub county_name: String,
+    pub station_id: u32,
+    pub year: u32,
+}
+
+/// A link to a county page, which lists the county's stations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountyLink(pub String);
+
+impl CountyLink {
+    /// The county name, e.g. `antrim`
+    pub fn county_name(&self) -> &str {
+        county_name(&self.0)
+    }
+}
+
+/// A link to a station page, which links to the station's data folders and capability file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StationLink(pub String);
+
+/// A link to one of a station's `qc-version-1` data folders, which lists its data files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DataFolderLink(pub String);
+
+impl DataFolderLink {
+    /// The link to the station page the folder is under, e.g. `.../antrim/01448_portglenone/`
+    /// from `.../antrim/01448_portglenone/qc-version-1/`
+    pub fn station_link(&self) -> StationLink {
+        let segments: Vec<&str> = self.0.split('/').collect();
+        let version_index = segments
+            .iter()
+            .position(|segment| segment.starts_with("dataset-version-"));
+        match version_index {
+            Some(index) if segments.len() > index + 3 => {
+                StationLink(format!("{}/", segments[..=index + 2].join("/")))
+            }
+            _ => StationLink(self.0.clone()),
+        }
+    }
+}
+
+/// What downloading a file did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Download {
+    /// The file was already in the directory, so nothing was written
+    Existing,
+    /// The file was downloaded, transferring this many bytes
+    Downloaded(u64),
+}
+
+impl DataFileLink {
+    /// Parse a data file link from its URL, or `None` if the filename is not a MIDAS data filename
+    ///
+    /// e.g. `midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv`
+    pub fn parse(url: &str) -> Option<Self> {
+        let filename = csv_filename(url).ok()?;
+        let parts: Vec<&str> = filename.split('_').collect();
+        if parts.len() != 8 {
+            return None;
+        }
+
+        let county_name = parts[3].to_string();
+        let station_id: u32 = parts[4].parse().ok()?;
+        let year: u32 = parts[7].strip_suffix(".csv")?.parse().ok()?;
+
+        Some(Self {
+            url: url.to_string(),
+            county_name,
+            station_id,
+            year,
+        })
+    }
+
+    /// The properties of the linked data file, from its filename
+    pub fn properties(&self) -> Result<FileProperties, Error> {
+        FileProperties::new(PathBuf::from(csv_filename(&self.url)?))
+    }
 }
 
 impl CedaClient {
     /// Create a new instance of the CEDA client
     ///
     /// dataset_version: The version of the dataset to use e.g. "202407"
+    #[allow(dead_code)]
     pub fn new(dataset_version: &str) -> Result<Self, Error> {
+        CedaClient::with_access_token(dataset_version, &CedaClient::get_access_token()?)
+    }
+
+    /// Create a new instance of the CEDA client with the given access token
+    #[allow(dead_code)]
+    pub fn with_access_token(dataset_version: &str, access_token: &str) -> Result<Self, Error> {
+        CedaClient::with_auth(dataset_version, access_token, AuthMode::default())
+    }
+
+    /// Create a new instance of the CEDA client sending the access token as `auth_mode` requires
+    pub fn with_auth(
+        dataset_version: &str,
+        access_token: &str,
+        auth_mode: AuthMode,
+    ) -> Result<Self, Error> {
         let dataset_version = dataset_version.to_string();
-        let access_token = CedaClient::get_access_token();
 
         let mut headers = HeaderMap::new();
-        let auth_value = format!("Bearer {}", access_token);
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&auth_value).map_err(|_| Error::GenericError)?,
-        );
+        let query_token = match auth_mode {
+            AuthMode::Bearer => {
+                let auth_value = format!("Bearer {}", access_token);
+                let mut auth_value =
+                    HeaderValue::from_str(&auth_value).map_err(|_| Error::GenericError)?;
+                auth_value.set_sensitive(true);
+                headers.insert(AUTHORIZATION, auth_value);
+                None
+            }
+            AuthMode::QueryParam => Some(SecretString::new(access_token.to_string())),
+        };
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .map_err(|_| Error::GenericError)?;
+        let http_settings = HttpSettings::default();
+        let client = http_client(&headers, http_settings)?;
 
-        let root = "https://data.ceda.ac.uk".to_string();
+        let root = CEDA_ROOT.to_string();
 
         Ok(Self {
             dataset_version,
             client,
+            headers,
+            http_settings,
             root,
+            semaphore: None,
+            limiter: None,
+            policy: CrawlPolicy::default(),
+            cache: None,
+            fetch_budget: None,
+            compress: false,
+            user_agent: HeaderValue::from_static(DEFAULT_USER_AGENT),
+            query_token,
+            stats: Arc::default(),
         })
     }
 
+    /// Create a copy of the client for another dataset version
+    pub fn with_dataset_version(&self, dataset_version: &str) -> Self {
+        Self {
+            dataset_version: dataset_version.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// Limit the number of requests in flight at once across all clones of the client
+    pub fn with_concurrency(mut self, concurrency: NonZeroUsize) -> Self {
+        self.semaphore = Some(Arc::new(Semaphore::new(concurrency.get())));
+        self.policy.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Pace requests to at most `rate` per second across all clones of the client
+    pub fn with_rate(mut self, rate: NonZeroU32) -> Self {
+        self.limiter = Some(Arc::new(RateLimiter::direct(Quota::per_second(rate))));
+        self.policy.rate = Some(rate);
+        self
+    }
+
+    /// Crawl by the given policy, replacing any limits already set
+    pub fn with_policy(mut self, policy: CrawlPolicy) -> Self {
+        self.semaphore = None;
+        self.limiter = None;
+        self.policy = policy;
+        let client = match policy.concurrency {
+            Some(concurrency) => self.with_concurrency(concurrency),
+            None => self,
+        };
+        match policy.rate {
+            Some(rate) => client.with_rate(rate),
+            None => client,
+        }
+    }
+
+    /// Serve pages from an on-disk cache in `dir` while they are younger than `ttl`
+    pub fn with_cache(mut self, dir: PathBuf, ttl: Duration) -> Self {
+        self.cache = Some(PageCache { dir, ttl });
+        self
+    }
+
+    /// A clone of the client that gives up fetching pages with `StationTimeout` once it has spent
+    /// longer than the limit fetching them. Only the requests themselves count, not waiting for a
+    /// request slot or the rate limiter.
+    pub fn with_fetch_budget(&self, limit: Duration) -> Self {
+        Self {
+            fetch_budget: Some(Arc::new(FetchBudget {
+                limit,
+                spent: Mutex::new(Duration::ZERO),
+            })),
+            ..self.clone()
+        }
+    }
+
+    /// Identify the client to CEDA with the given `User-Agent` header
+    pub fn with_user_agent(mut self, user_agent: &str) -> Result<Self, Error> {
+        self.user_agent = HeaderValue::from_str(user_agent)
+            .map_err(|_| Error::ConfigError(format!("invalid user agent: {}", user_agent)))?;
+        Ok(self)
+    }
+
+    /// Keep up to `max_idle_per_host` idle connections open to each host for `idle_timeout`
+    pub fn with_pool(
+        mut self,
+        max_idle_per_host: usize,
+        idle_timeout: Duration,
+    ) -> Result<Self, Error> {
+        self.http_settings.max_idle_per_host = max_idle_per_host;
+        self.http_settings.idle_timeout = idle_timeout;
+        self.client = http_client(&self.headers, self.http_settings)?;
+        Ok(self)
+    }
+
+    /// Accept any TLS certificate, even an invalid or self-signed one. This is unsafe: anyone
+    /// between the client and the server can read and change the traffic, including the access
+    /// token. Only for testing and TLS-intercepting proxies.
+    pub fn with_invalid_certs_accepted(mut self) -> Result<Self, Error> {
+        self.http_settings.accept_invalid_certs = true;
+        self.client = http_client(&self.headers, self.http_settings)?;
+        Ok(self)
+    }
+
+    /// Store downloaded data files gzipped, as `<file>.csv.gz`
+    pub fn with_compression(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// Build a request identifying the client, with the access token in the URL if required
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent);
+
+        match &self.query_token {
+            Some(token) => request.query(&[(ACCESS_TOKEN_PARAM, token.expose())]),
+            None => request,
+        }
+    }
+
+    /// Send a request, retrying connection failures and server errors, and counting it in the
+    /// request stats. Responses other than success are errors. A retry waits for the server's
+    /// `Retry-After`, or else backs off, with jitter, and then for the rate limit like any other
+    /// request.
+    async fn send(&self, method: Method, url: &str) -> Result<Response, Error> {
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+
+        let mut attempt = 1;
+        loop {
+            let result = self.request(method.clone(), url).send().await;
+            let retryable = match &result {
+                Ok(res) => {
+                    res.status().is_server_error() || res.status() == StatusCode::TOO_MANY_REQUESTS
+                }
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+            if retryable && attempt <= self.policy.max_retries {
+                if attempt == 1 {
+                    self.stats.retried.fetch_add(1, Ordering::Relaxed);
+                }
+                let delay = result
+                    .as_ref()
+                    .ok()
+                    .and_then(|res| retry_after(res.headers()))
+                    .unwrap_or_else(|| self.policy.backoff(attempt));
+                tokio::time::sleep(with_jitter(delay)).await;
+                self.throttle().await;
+                attempt += 1;
+                continue;
+            }
+
+            return match result {
+                Ok(res) if res.status().is_success() => Ok(res),
+                Ok(res) => {
+                    self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                    Err(Error::DocumentFetchError(res.status().to_string()))
+                }
+                Err(e) => {
+                    self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                    Err(fetch_error(url, e))
+                }
+            };
+        }
+    }
+
+    /// The counts of requests made by this client and its clones
+    pub fn request_stats(&self) -> Arc<RequestStats> {
+        self.stats.clone()
+    }
+
+    /// The dataset version the client crawls e.g. "202407"
+    pub fn dataset_version(&self) -> &str {
+        &self.dataset_version
+    }
+
+    /// Wait until a request is allowed if the rate is limited
+    async fn throttle(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.until_ready().await;
+        }
+    }
+
+    /// Wait for a request slot if the concurrency is limited
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.semaphore {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        }
+    }
+
     /// Get the document from a URL
     async fn get_document(&self, url: &str) -> Result<Html, Error> {
-        let res = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .map_err(|_| Error::GenericError)?;
-        if !res.status().is_success() {
-            return Err(Error::GenericError);
+        if let Some(cache) = &self.cache {
+            if let Some(body) = cache.get(url).await {
+                return Ok(Html::parse_document(&body));
+            }
         }
 
-        let body = res.text().await.map_err(|_| Error::GenericError)?;
+        let _permit = self.acquire().await;
+        self.throttle().await;
+        let fetch = async {
+            let res = self.send(Method::GET, url).await?;
+            res.text().await.map_err(|_| Error::GenericError)
+        };
+        let body = match &self.fetch_budget {
+            Some(budget) => budget.spend(fetch).await?,
+            None => fetch.await?,
+        };
+        if let Some(cache) = &self.cache {
+            cache.put(url, &body).await;
+        }
         let document = Html::parse_document(&body);
 
         Ok(document)
     }
 
-    /// Get all links to regions from the root page
-    pub async fn get_county_links(&self) -> Result<Vec<String>, Error> {
+    /// Get the published dataset versions, e.g. "202407", oldest first
+    pub async fn get_dataset_versions(&self) -> Result<Vec<String>, Error> {
         let url = format!(
-            "{}{}{}/",
-            self.root,
-            "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-",
-            self.dataset_version
+            "{}{}",
+            self.root, "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/"
         );
-        let document = self.get_document(&url).await.unwrap();
+        let document = self.get_document(&url).await?;
         let selector = Selector::parse("#results a").unwrap();
 
-        let re_start = Regex::new(r"^/badc").unwrap();
-        let re_end = Regex::new(r"change_log_station_files$").unwrap();
-
-        let links: Vec<String> = document
+        let mut versions: Vec<String> = document
             .select(&selector)
             .filter_map(|element| element.value().attr("href"))
-            .map(|href| href.to_string())
-            .filter(|link| re_start.is_match(link) && !re_end.is_match(link))
+            .filter_map(dataset_version)
+            .map(|version| version.to_string())
             .collect();
+        versions.sort();
+        versions.dedup();
 
-        Ok(links)
+        Ok(versions)
+    }
+
+    /// URL of the page for the client's dataset version, which lists the counties
+    fn dataset_version_url(&self) -> String {
+        format!(
+            "{}{}{}/",
+            self.root,
+            "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-",
+            self.dataset_version
+        )
+    }
+
+    /// Check CEDA accepts the access token, with a single uncached request to the dataset
+    /// version page. Returns the URL checked.
+    pub async fn check_access_token(&self) -> Result<String, Error> {
+        let url = self.dataset_version_url();
+        let res = self
+            .request(Method::HEAD, &url)
+            .send()
+            .await
+            .map_err(|e| fetch_error(&url, e))?;
+
+        match res.status() {
+            status if status.is_success() => Ok(url),
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                Err(Error::AccessTokenRejected(status.to_string()))
+            }
+            status => Err(Error::DocumentFetchError(format!(
+                "{} from {}",
+                status, url
+            ))),
+        }
+    }
+
+    /// Get all links to regions from the root page
+    pub async fn get_county_links(&self) -> Result<Vec<CountyLink>, Error> {
+        let url = self.dataset_version_url();
+        let document = self.get_document(&url).await?;
+        let links = extract_county_links(&document, &url)?;
+
+        Ok(links.into_iter().map(CountyLink).collect())
     }
 
     /// Get all station links from a region page
-    pub async fn get_station_links(&self, region_link: &str) -> Result<Vec<String>, Error> {
-        let url = format!("{}{}", self.root, region_link);
-        let document = self.get_document(&url).await.unwrap();
+    pub async fn get_station_links(
+        &self,
+        county_link: &CountyLink,
+    ) -> Result<Vec<StationLink>, Error> {
+        let url = format!("{}{}", self.root, county_link.0);
+        let document = self
+            .get_document(&url)
+            .await
+            .map_err(|e| Error::DocumentFetchError(e.to_string()))?;
         let selector = Selector::parse("#content-main > div.row > div > table a").unwrap();
 
-        let links: Vec<String> = document
+        let links: Vec<StationLink> = document
             .select(&selector)
             .filter_map(|element| element.value().attr("href"))
-            .map(|href| href.to_string())
+            .map(|href| StationLink(href.to_string()))
             .collect();
 
         Ok(links)
     }
 
-    /// Get the data folder link for a station
-    pub async fn get_data_folder_link(&self, station_link: &str) -> Result<String, Error> {
-        let url = format!("{}{}", self.root, station_link);
+    /// Get the data folder links for a station, one for each of its qc-version-1 folders. Most
+    /// stations have one, but some have a folder for each instrument group.
+    pub async fn get_data_folder_links(
+        &self,
+        station_link: &StationLink,
+    ) -> Result<Vec<DataFolderLink>, Error> {
+        let url = format!("{}{}", self.root, station_link.0);
         let document = self.get_document(&url).await.map_err(|e| Error::DocumentFetchError(e.to_string()))?;
 
-        let link = extract_qc_version_1_link(&document.html()).ok_or(Error::QCV1NotFound)?;
+        let links = extract_qc_version_1_links(&document.html());
+        if links.is_empty() {
+            return Err(Error::QCV1NotFound);
+        }
+
+        Ok(links.into_iter().map(DataFolderLink).collect())
+    }
+
+    /// Get the link to a station's capability file, which lists what it measures and when
+    pub async fn get_capability_link(&self, station_link: &StationLink) -> Result<String, Error> {
+        let url = format!("{}{}", self.root, station_link.0);
+        let document = self.get_document(&url).await?;
 
-        Ok(link)
+        extract_capability_link(&document).ok_or(Error::CapabilityNotFound(url))
     }
 
     /// Get the data file links for a data folder
-    pub async fn get_data_file_links(&self, data_folder_link: &str) -> Result<Vec<String>, Error> {
-        let url = format!("{}{}", self.root, data_folder_link);
-        let document = self.get_document(&url).await.unwrap();
+    pub async fn get_data_file_links(
+        &self,
+        data_folder_link: &DataFolderLink,
+    ) -> Result<Vec<DataFileLink>, Error> {
+        let mut url = format!("{}{}", self.root, data_folder_link.0);
+        let mut visited = HashSet::new();
         let selector = Selector::parse("#results a").unwrap();
+        let mut data_file_links: Vec<DataFileLink> = Vec::new();
 
-        // Get the links to the data files
-        let data_file_links: Vec<String> = document
-            .select(&selector)
-            .filter_map(|element| element.value().attr("href"))
-            .map(|href| href.to_string())
-            .collect();
+        // Follow the "next page" links until the last page of the listing
+        loop {
+            let document = self.get_document(&url).await?;
+            data_file_links.extend(
+                document
+                    .select(&selector)
+                    .filter_map(|element| element.value().attr("href"))
+                    .filter_map(DataFileLink::parse),
+            );
+            visited.insert(url.clone());
+
+            match next_page_link(&document, &url) {
+                Some(next_url) if !visited.contains(&next_url) => url = next_url,
+                _ => break,
+            }
+        }
 
         Ok(data_file_links)
     }
 
+    /// Get the last modified time of a URL from its headers, if the server reports one
+    pub async fn last_modified(&self, url: &str) -> Result<Option<DateTime<Utc>>, Error> {
+        let _permit = self.acquire().await;
+        self.throttle().await;
+        let res = self.send(Method::HEAD, url).await?;
 
-    /// Download a CSV file to the specified directory
-    pub async fn download_csv(&self, url: &str, dir: &Path) -> Result<(), Error> {
-        let res = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .map_err(|_| Error::GenericError)?;
-        if !res.status().is_success() {
-            return Err(Error::GenericError);
-        }
+        let last_modified = res
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(|date_time| date_time.with_timezone(&Utc));
 
-        let filename = url.split('/').last().unwrap();
+        Ok(last_modified)
+    }
 
-        // remove all after '.csv'
-        let filename = match filename.find(".csv") {
-            Some(pos) => &filename[..pos + 4],
-            None => filename,
-        };
+    /// Resolve a link against the archive root, leaving absolute URLs as they are
+    fn absolute_url(&self, link: &str) -> Result<String, Error> {
+        let root = Url::parse(&self.root).map_err(|_| Error::InvalidUrl(self.root.clone()))?;
+
+        root.join(link)
+            .map(String::from)
+            .map_err(|_| Error::InvalidUrl(link.to_string()))
+    }
+
+    /// Download a CSV file to the specified directory, unless it is already there. A relative URL
+    /// is resolved against the archive root. An HTML page served in its place, e.g. a login or
+    /// error page, is an error and is not saved.
+    pub async fn download_csv(&self, url: &str, dir: &Path) -> Result<Download, Error> {
+        let url = &self.absolute_url(url)?;
+        let _permit = self.acquire().await;
+        self.throttle().await;
+        let res = self.send(Method::GET, url).await?;
+        if let Some(content_type) = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|content_type| is_html_content_type(content_type))
+        {
+            return Err(Error::UnexpectedContentType(content_type.to_string()));
+        }
 
-        // skip if file already exists
-        if dir.join(filename).exists() {
-            return Ok(());
+        let filename = csv_filename(url)?;
+
+        // skip if file already exists, compressed or not
+        let gz_filename = format!("{}.gz", filename);
+        if dir.join(&filename).exists() || dir.join(&gz_filename).exists() {
+            return Ok(Download::Existing);
         }
 
-        let file_path = dir.join(filename);
-        let mut file = File::create(&file_path)
+        // Download to a partial file so an interrupted download is never mistaken for a data file.
+        // The partial file is removed however the download ends, including being cancelled.
+        let part_file = PartFile::new(dir.join(format!("{}.part", filename)));
+        let mut file = File::create(part_file.path())
             .await
             .map_err(|_| Error::GenericError)?;
         let stream = res
             .bytes_stream()
-            .map(|result| result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
+            .map(|result| result.map_err(std::io::Error::other));
         let mut stream_reader = StreamReader::new(stream);
 
-        copy(&mut stream_reader, &mut file)
+        let bytes = copy(&mut stream_reader, &mut file)
             .await
             .map_err(|_| Error::GenericError)?;
 
-        Ok(())
+        // Servers don't always label an HTML page as such, so check the body too
+        let mut head = [0; 512];
+        let head_len = match File::open(part_file.path()).await {
+            Ok(mut part_file) => part_file.read(&mut head).await.unwrap_or_default(),
+            Err(_) => 0,
+        };
+        if looks_like_html(&head[..head_len]) {
+            return Err(Error::UnexpectedContentType("HTML page".to_string()));
+        }
+
+        if self.compress {
+            let file_path = dir.join(&gz_filename);
+            let compressed =
+                tokio::task::spawn_blocking(move || gzip(part_file.path(), &file_path))
+                    .await
+                    .map_err(|_| Error::GenericError)?;
+            compressed.map_err(|_| Error::GenericError)?;
+        } else {
+            tokio::fs::rename(part_file.path(), dir.join(&filename))
+                .await
+                .map_err(|_| Error::GenericError)?;
+        }
+
+        Ok(Download::Downloaded(bytes))
     }
 
-    fn get_access_token() -> String {
+    /// Get the access token from the `CEDA_ACCESS_TOKEN` environment variable
+    pub fn get_access_token() -> Result<String, Error> {
         dotenv::dotenv().ok();
-        env::var("CEDA_ACCESS_TOKEN").expect("CEDA_ACCESS_TOKEN must be set")
+        required_access_token(env::var("CEDA_ACCESS_TOKEN").ok())
+    }
+}
+
+/// A partial download, deleted when dropped unless it has already been moved into place
+struct PartFile(PathBuf);
+
+impl PartFile {
+    fn new(path: PathBuf) -> Self {
+        Self(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
     }
 }
 
-fn extract_qc_version_1_link(html: &str) -> Option<String> {
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Settings of the HTTP client
+#[derive(Debug, Clone, Copy)]
+struct HttpSettings {
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+    /// Skip TLS certificate verification
+    accept_invalid_certs: bool,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+/// Build an HTTP client sending `headers`, with its connection pool sized for bursts of requests
+fn http_client(headers: &HeaderMap, settings: HttpSettings) -> Result<reqwest::Client, Error> {
+    reqwest::Client::builder()
+        .default_headers(headers.clone())
+        .pool_max_idle_per_host(settings.max_idle_per_host)
+        .pool_idle_timeout(settings.idle_timeout)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .danger_accept_invalid_certs(settings.accept_invalid_certs)
+        .build()
+        .map_err(|_| Error::GenericError)
+}
+
+/// Check whether a `Content-Type` is an HTML page rather than a data file
+fn is_html_content_type(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    matches!(media_type.as_str(), "text/html" | "application/xhtml+xml")
+}
+
+/// Check whether the start of a body is an HTML document
+fn looks_like_html(head: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(head)
+        .trim_start()
+        .to_ascii_lowercase();
+
+    head.starts_with("<!doctype html") || head.starts_with("<html")
+}
+
+/// Format a count with commas between the thousands, e.g. `12,304`
+fn with_thousands(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    formatted
+}
+
+/// Gzip `source` to `dest`, writing through a partial file so a failed write leaves no data file
+fn gzip(source: &Path, dest: &Path) -> std::io::Result<()> {
+    let part_path = dest.with_extension("gz.part");
+    let result = (|| {
+        let mut input = std::fs::File::open(source)?;
+        let mut encoder =
+            GzEncoder::new(std::fs::File::create(&part_path)?, Compression::default());
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+        std::fs::rename(&part_path, dest)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part_path);
+    }
+
+    result
+}
+
+/// Get the county name from a county link, e.g. `antrim` from `.../dataset-version-202407/antrim`
+pub fn county_name(county_link: &str) -> &str {
+    county_link
+        .split('/')
+        .rfind(|segment| !segment.is_empty())
+        .unwrap_or(county_link)
+}
+
+/// Get the dataset version from a link, e.g. `202407` from `.../dataset-version-202407/`
+pub fn dataset_version(link: &str) -> Option<&str> {
+    link.split('/')
+        .find_map(|segment| segment.strip_prefix("dataset-version-"))
+        .filter(|version| !version.is_empty())
+}
+
+/// Get the name of a CSV file from its download URL: the last non-empty path segment, without
+/// any query or fragment. Relative links are resolved against the CEDA archive.
+pub fn csv_filename(url: &str) -> Result<String, Error> {
+    let base = Url::parse(CEDA_ROOT).map_err(|_| Error::InvalidUrl(CEDA_ROOT.to_string()))?;
+    let parsed = Url::options()
+        .base_url(Some(&base))
+        .parse(url)
+        .map_err(|_| Error::InvalidUrl(url.to_string()))?;
+
+    parsed
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .map(|segment| segment.to_string())
+        .ok_or_else(|| Error::InvalidUrl(url.to_string()))
+}
+
+/// The delay a server asks for before a retry in a `Retry-After` header, in seconds or as a
+/// date, up to `MAX_RETRY_AFTER`
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            // A date already past means retry now
+            (date.with_timezone(&Utc) - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Lengthen a retry delay by a random fraction of up to a half, so requests that failed together
+/// don't all retry together
+fn with_jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+
+    delay + delay.mul_f64(random as f64 / u64::MAX as f64 / 2.0)
+}
+
+/// An access token that is set and not blank
+fn required_access_token(access_token: Option<String>) -> Result<String, Error> {
+    access_token
+        .filter(|access_token| !access_token.trim().is_empty())
+        .ok_or(Error::MissingAccessToken)
+}
+
+/// A failed request as an error naming the URL requested, without the URL reqwest sent, which
+/// carries the access token in `AuthMode::QueryParam`
+fn fetch_error(url: &str, e: reqwest::Error) -> Error {
+    Error::DocumentFetchError(format!("{}: {}", url, e.without_url()))
+}
+
+/// Get the absolute URL of the next page of a paginated listing, if there is one
+fn next_page_link(document: &Html, url: &str) -> Option<String> {
+    let selector = Selector::parse(r#"a[rel="next"]"#).unwrap();
+    let href = document.select(&selector).next()?.value().attr("href")?;
+
+    Url::parse(url).ok()?.join(href).ok().map(String::from)
+}
+
+/// Extract the county links from the dataset version page, failing if the page has no results
+/// links at all, which means the markup has changed
+fn extract_county_links(document: &Html, url: &str) -> Result<Vec<String>, Error> {
+    let selector_str = "#results a";
+    let selector = Selector::parse(selector_str).unwrap();
+
+    let re_start = Regex::new(r"^/badc").unwrap();
+    let re_end = Regex::new(r"change_log_station_files$").unwrap();
+
+    let hrefs: Vec<&str> = document
+        .select(&selector)
+        .filter_map(|element| element.value().attr("href"))
+        .collect();
+    if hrefs.is_empty() {
+        return Err(Error::NoLinksFound {
+            url: url.to_string(),
+            selector: selector_str.to_string(),
+        });
+    }
+
+    let links: Vec<String> = hrefs
+        .into_iter()
+        .filter(|link| re_start.is_match(link) && !re_end.is_match(link))
+        .map(|href| href.to_string())
+        .collect();
+
+    Ok(links)
+}
+
+/// Extract the link to the capability CSV from a station page
+fn extract_capability_link(document: &Html) -> Option<String> {
+    let selector = Selector::parse("#results a").unwrap();
+    let is_capability = |href: &&str| {
+        csv_filename(href).is_ok_and(|filename| filename.ends_with("_capability.csv"))
+    };
+
+    document
+        .select(&selector)
+        .filter_map(|element| element.value().attr("href"))
+        .find(is_capability)
+        .map(|href| href.to_string())
+}
+
+fn extract_qc_version_1_links(html: &str) -> Vec<String> {
     let document = Html::parse_document(html);
     let selector = Selector::parse("#results a").unwrap();
 
+    let mut links: Vec<String> = Vec::new();
     for element in document.select(&selector) {
         if element.text().any(|text| text == "qc-version-1") {
-            return element.value().attr("href").map(|href| href.to_string());
+            if let Some(href) = element.value().attr("href") {
+                if !links.iter().any(|link| link == href) {
+                    links.push(href.to_string());
+                }
+            }
         }
     }
 
-    None
+    links
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn it_gets_county_name() {
+        let county_link =
+            "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/";
+
+        assert_eq!(county_name(county_link), "antrim");
+    }
+
+    #[test]
+    fn it_gets_station_link_of_data_folder() {
+        let station_link =
+            "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/01448_portglenone/";
+        let data_folder_link = DataFolderLink(format!("{}qc-version-1/", station_link));
+
+        assert_eq!(
+            data_folder_link.station_link(),
+            StationLink(station_link.to_string())
+        );
+    }
+
+    #[test]
+    fn it_gets_dataset_version() {
+        let link = "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/";
+
+        assert_eq!(dataset_version(link), Some("202407"));
+        assert_eq!(dataset_version("/badc/ukmo-midas-open/data/"), None);
+    }
+
+    #[test]
+    fn it_gets_csv_filename() {
+        let filename =
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+        let url = format!("https://dap.ceda.ac.uk/badc/{}?download=1#top", filename);
+
+        assert_eq!(csv_filename(&url).unwrap(), filename);
+        assert_eq!(
+            csv_filename(&format!("/badc/qc-version-1/{}/", filename)).unwrap(),
+            filename
+        );
+        assert!(matches!(
+            csv_filename("https://dap.ceda.ac.uk/"),
+            Err(Error::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn it_parses_data_file_links() {
+        let url = "https://dap.ceda.ac.uk/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/01448_portglenone/qc-version-1/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv?download=1";
+
+        let data_file_link = DataFileLink::parse(url).unwrap();
+
+        assert_eq!(data_file_link.url, url);
+        assert_eq!(data_file_link.county_name, "antrim");
+        assert_eq!(data_file_link.station_id, 1448);
+        assert_eq!(data_file_link.year, 1994);
+        assert_eq!(
+            DataFileLink::parse("https://data.ceda.ac.uk/badc/?sort=name"),
+            None
+        );
+    }
+
+    #[test]
+    fn it_fails_when_no_county_links_match() {
+        let document = Html::parse_document("<html><body><div id=\"content\"></div></body></html>");
+
+        let result = extract_county_links(&document, "https://data.ceda.ac.uk/badc/");
+
+        assert!(matches!(result, Err(Error::NoLinksFound { .. })));
+    }
+
+    #[test]
+    fn it_extracts_county_links() {
+        let document = Html::parse_document(
+            r#"<div id="results">
+                <a href="/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim">antrim</a>
+                <a href="/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/change_log_station_files">changes</a>
+            </div>"#,
+        );
+
+        let links = extract_county_links(&document, "https://data.ceda.ac.uk/badc/").unwrap();
+
+        assert_eq!(
+            links,
+            vec!["/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim"]
+        );
+    }
+
+    #[test]
+    fn it_extracts_capability_link() {
+        let capability = "https://dap.ceda.ac.uk/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/01448_portglenone/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_capability.csv?download=1";
+        let document = Html::parse_document(&format!(
+            r#"<div id="results">
+                <a href="/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/01448_portglenone/qc-version-1">qc-version-1</a>
+                <a href="{}">capability</a>
+            </div>"#,
+            capability
+        ));
+
+        assert_eq!(
+            extract_capability_link(&document),
+            Some(capability.to_string())
+        );
+        assert_eq!(
+            extract_capability_link(&Html::parse_document("<div id=\"results\"></div>")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn it_follows_listing_pages() {
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let folder_link = "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/01448_portglenone/qc-version-1/";
+        let url = format!("{}{}", CEDA_ROOT, folder_link);
+        let cache = test_cache("listing-pages", Duration::from_secs(60));
+        let dir = cache.dir.clone();
+        for (page_url, fixture) in [
+            (url.clone(), "data_file_listing_page_1.html"),
+            (format!("{}?page=2", url), "data_file_listing_page_2.html"),
+        ] {
+            let body = std::fs::read_to_string(fixtures.join(fixture)).unwrap();
+            cache.put(&page_url, &body).await;
+        }
+        let client = CedaClient::with_access_token("202407", "token")
+            .unwrap()
+            .with_cache(cache.dir, cache.ttl);
+
+        let links = client
+            .get_data_file_links(&DataFolderLink(folder_link.to_string()))
+            .await
+            .unwrap();
+        let years: Vec<u32> = links.iter().map(|link| link.year).collect();
+
+        assert_eq!(years, vec![1994, 1995, 1996]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_follows_every_qc_version_1_folder() {
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let station_link = "/badc/ukmo-midas-open/data/uk-hourly-weather-obs/dataset-version-202407/antrim/01448_portglenone/";
+        let cache = test_cache("qc-folders", Duration::from_secs(60));
+        let dir = cache.dir.clone();
+        let first = format!("{}qc-version-1/", station_link);
+        let second = format!("{}anemometer/qc-version-1/", station_link);
+        for (link, fixture) in [
+            (station_link.to_string(), "station_page_two_qc_folders.html"),
+            (first.clone(), "data_file_listing_page_1.html"),
+            (format!("{}?page=2", first), "data_file_listing_page_2.html"),
+            (second.clone(), "data_file_listing_second_folder.html"),
+        ] {
+            let body = std::fs::read_to_string(fixtures.join(fixture)).unwrap();
+            cache.put(&format!("{}{}", CEDA_ROOT, link), &body).await;
+        }
+        let client = CedaClient::with_access_token("202407", "token")
+            .unwrap()
+            .with_cache(cache.dir, cache.ttl);
+
+        let folder_links = client
+            .get_data_folder_links(&StationLink(station_link.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            folder_links,
+            vec![DataFolderLink(first), DataFolderLink(second)]
+        );
+
+        let mut years = Vec::new();
+        for folder_link in &folder_links {
+            let links = client.get_data_file_links(folder_link).await.unwrap();
+            years.extend(links.iter().map(|link| link.year));
+        }
+        assert_eq!(years, vec![1994, 1995, 1996, 2001]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_caches_pages() {
+        let cache = test_cache("page-cache", Duration::from_secs(60));
+        let url = "https://data.ceda.ac.uk/badc/ukmo-midas-open/data/uk-hourly-weather-obs/";
+
+        cache.put(url, "<html></html>").await;
+
+        assert_eq!(cache.get(url).await, Some("<html></html>".to_string()));
+        assert_eq!(cache.get("https://data.ceda.ac.uk/other").await, None);
+
+        assert_eq!(
+            cache.path(url).file_name().unwrap(),
+            "24016cb6c9d908142f46f9bdcf042fb7d97a64a53ad1394e17140876a0515a9b.html"
+        );
+
+        let expired = PageCache {
+            ttl: Duration::ZERO,
+            ..cache
+        };
+        assert_eq!(expired.get(url).await, None);
+
+        std::fs::remove_dir_all(&expired.dir).unwrap();
+    }
+
+    /// A page cache in a new directory of its own, so tests and concurrent test runs never share
+    /// pages
+    fn test_cache(name: &str, ttl: Duration) -> PageCache {
+        let dir = std::env::temp_dir().join(format!("rust-ceda-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        PageCache { dir, ttl }
+    }
+
+    #[test]
+    fn it_sets_user_agent() {
+        let client = CedaClient::with_access_token("202407", "token").unwrap();
+        assert_eq!(client.user_agent, DEFAULT_USER_AGENT);
+
+        let client = client.with_user_agent("my-scraper/1.0").unwrap();
+        assert_eq!(client.user_agent, "my-scraper/1.0");
+
+        assert!(CedaClient::with_access_token("202407", "token")
+            .unwrap()
+            .with_user_agent("bad\nagent")
+            .is_err());
+    }
+
+    #[test]
+    fn it_verifies_certs_unless_told_not_to() {
+        let client = CedaClient::with_access_token("202407", "token").unwrap();
+        assert!(!client.http_settings.accept_invalid_certs);
+
+        let client = client
+            .with_invalid_certs_accepted()
+            .unwrap()
+            .with_pool(4, Duration::from_secs(10))
+            .unwrap();
+        assert!(client.http_settings.accept_invalid_certs);
+        assert_eq!(client.http_settings.max_idle_per_host, 4);
+    }
+
+    #[test]
+    fn it_detects_html() {
+        assert!(is_html_content_type("text/html; charset=utf-8"));
+        assert!(is_html_content_type("TEXT/HTML"));
+        assert!(!is_html_content_type("text/csv"));
+        assert!(!is_html_content_type("text/plain; charset=utf-8"));
+
+        assert!(looks_like_html(b"\n  <!DOCTYPE html>\n<html>"));
+        assert!(looks_like_html(b"<html lang=\"en\">"));
+        assert!(!looks_like_html(b"Conventions,G,BADC-CSV,1\n"));
+        assert!(!looks_like_html(b""));
+    }
+
+    #[test]
+    fn it_formats_request_stats() {
+        let stats = RequestStats::default();
+        stats.requests.store(12_304, Ordering::Relaxed);
+        stats.retried.store(87, Ordering::Relaxed);
+        stats.failed.store(3, Ordering::Relaxed);
+
+        assert_eq!(stats.to_string(), "12,304 requests, 87 retried, 3 failed");
+        assert_eq!(with_thousands(0), "0");
+        assert_eq!(with_thousands(1_000_000), "1,000,000");
+    }
+
+    #[tokio::test]
+    async fn it_retries_server_errors() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        // Answer the first request with a server error and the rest with success
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for status in ["503 Service Unavailable", "200 OK", "404 Not Found"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let client = CedaClient::with_access_token("202407", "token").unwrap();
+
+        assert!(client.last_modified(&url).await.is_ok());
+        assert!(client.last_modified(&url).await.is_err());
+        assert_eq!(
+            client.request_stats().to_string(),
+            "2 requests, 1 retried, 1 failed"
+        );
+    }
+
+    #[test]
+    fn it_reads_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(retry_after(&headers), Some(MAX_RETRY_AFTER));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn it_adds_up_to_half_again_as_jitter() {
+        let delay = Duration::from_secs(2);
+
+        for _ in 0..100 {
+            let jittered = with_jitter(delay);
+            assert!(jittered >= delay && jittered <= Duration::from_secs(3));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_retry_after() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for status in ["429 Too Many Requests\r\nRetry-After: 1", "200 OK"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let client = CedaClient::with_access_token("202407", "token")
+            .unwrap()
+            .with_policy(CrawlPolicy {
+                base_backoff: Duration::from_millis(1),
+                ..Default::default()
+            });
+
+        let started = Instant::now();
+        assert!(client.last_modified(&url).await.is_ok());
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        let policy = CrawlPolicy {
+            base_backoff: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let delays: Vec<Duration> = (1..=4).map(|retry| policy.backoff(retry)).collect();
+
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reuses_connections() {
+        use std::sync::atomic::AtomicUsize;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        // Answer every request on a connection, keeping it open
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut request = [0; 1024];
+                    while socket.read(&mut request).await.unwrap_or(0) > 0 {
+                        let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        let client = CedaClient::with_access_token("202407", "token")
+            .unwrap()
+            .with_pool(4, Duration::from_secs(30))
+            .unwrap();
+
+        for _ in 0..3 {
+            assert!(client.last_modified(&url).await.is_ok());
+        }
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn it_reports_rejected_access_token() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for status in ["200 OK", "401 Unauthorized", "404 Not Found"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let mut client = CedaClient::with_access_token("202407", "token").unwrap();
+        client.root = root;
+
+        assert!(client.check_access_token().await.is_ok());
+        assert!(matches!(
+            client.check_access_token().await,
+            Err(Error::AccessTokenRejected(_))
+        ));
+        assert!(matches!(
+            client.check_access_token().await,
+            Err(Error::DocumentFetchError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_times_out_once_the_fetch_budget_is_spent() {
+        let budget = FetchBudget {
+            limit: Duration::from_millis(100),
+            spent: Mutex::new(Duration::ZERO),
+        };
+        let fetch = || async {
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            Ok(())
+        };
+
+        assert!(budget.spend(fetch()).await.is_ok());
+        assert!(matches!(
+            budget.spend(fetch()).await,
+            Err(Error::StationTimeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_does_not_count_waiting_for_a_request_slot() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        let mut client = CedaClient::with_access_token("202407", "token")
+            .unwrap()
+            .with_concurrency(NonZeroUsize::MIN);
+        client.root = root;
+        let station_client = client.with_fetch_budget(Duration::from_millis(100));
+
+        // Another request holds the only slot for longer than the budget
+        let permit = client.acquire().await;
+        let url = format!("{}/antrim/", client.root);
+        let fetch = tokio::spawn(async move { station_client.get_document(&url).await.is_ok() });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        drop(permit);
+
+        assert!(fetch.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn it_fails_station_links_of_missing_county() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let response =
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        let mut client = CedaClient::with_access_token("202407", "token").unwrap();
+        client.root = root;
+
+        let county_link = CountyLink("/antrim".to_string());
+        assert!(matches!(
+            client.get_station_links(&county_link).await,
+            Err(Error::DocumentFetchError(_))
+        ));
+    }
+
+    #[test]
+    fn it_sends_token_as_query_param() {
+        let url = "https://data.ceda.ac.uk/badc/";
+
+        let client = CedaClient::with_auth("202407", "token", AuthMode::QueryParam).unwrap();
+        let request = client.request(Method::GET, url).build().unwrap();
+        assert_eq!(request.url().query(), Some("access_token=token"));
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+
+        let client = CedaClient::with_access_token("202407", "token").unwrap();
+        let request = client.request(Method::GET, url).build().unwrap();
+        assert_eq!(request.url().query(), None);
+    }
+
+    #[test]
+    fn it_redacts_access_token() {
+        let secret = "s3cr3t-t0ken";
+
+        for auth_mode in [AuthMode::Bearer, AuthMode::QueryParam] {
+            let client = CedaClient::with_auth("202407", secret, auth_mode).unwrap();
+            let debug = format!("{:?}", client);
+            assert!(debug.contains("202407"));
+            assert!(!debug.contains(secret), "{}", debug);
+            assert!(!format!("{:?}", client.headers).contains(secret));
+        }
+        assert_eq!(
+            format!("{:?}", SecretString::new(secret.to_string())),
+            "SecretString(<redacted>)"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_redacts_access_token_from_request_errors() {
+        let secret = "s3cr3t-t0ken";
+        // Nothing listens on a port once its listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut client = CedaClient::with_auth("202407", secret, AuthMode::QueryParam)
+            .unwrap()
+            .with_policy(CrawlPolicy {
+                max_retries: 0,
+                ..Default::default()
+            });
+        client.root = root.clone();
+
+        let errors = [
+            client.check_access_token().await.unwrap_err(),
+            client
+                .last_modified(&format!("{}/badc/", root))
+                .await
+                .unwrap_err(),
+        ];
+
+        for error in errors {
+            let message = error.to_string();
+            assert!(message.contains(&root), "{}", message);
+            assert!(!message.contains(secret), "{}", message);
+        }
+    }
+
+    #[test]
+    fn it_fails_without_an_access_token() {
+        assert!(matches!(
+            required_access_token(None),
+            Err(Error::MissingAccessToken)
+        ));
+        assert!(matches!(
+            required_access_token(Some(" ".to_string())),
+            Err(Error::MissingAccessToken)
+        ));
+        assert_eq!(
+            required_access_token(Some("token".to_string())).unwrap(),
+            "token"
+        );
+    }
+
     #[test]
     #[ignore]
     fn test_new() {
@@ -217,9 +1686,9 @@ mod tests {
     async fn it_gets_station_links() {
         let client = CedaClient::new("202407").unwrap();
         let region_links = client.get_county_links().await.unwrap();
-        let station_link = region_links.iter().take(1).next().unwrap();
+        let county_link = region_links.iter().take(1).next().unwrap();
 
-        let station_links = client.get_station_links(station_link).await.unwrap();
+        let station_links = client.get_station_links(county_link).await.unwrap();
 
         assert!(!station_links.is_empty());
     }
@@ -230,11 +1699,15 @@ mod tests {
     async fn it_gets_datalinks() {
         let client = CedaClient::new("202407").unwrap();
         let region_links = client.get_county_links().await.unwrap();
-        let station_link = region_links.iter().take(1).next().unwrap();
-        let station_links = client.get_station_links(station_link).await.unwrap();
-        let data_link = station_links.iter().take(1).next().unwrap();
+        let county_link = region_links.iter().take(1).next().unwrap();
+        let station_links = client.get_station_links(county_link).await.unwrap();
+        let station_link = station_links.iter().take(1).next().unwrap();
+        let data_folder_links = client.get_data_folder_links(station_link).await.unwrap();
 
-        let data_links = client.get_data_file_links(data_link).await.unwrap();
+        let data_links = client
+            .get_data_file_links(&data_folder_links[0])
+            .await
+            .unwrap();
 
         assert!(!data_links.is_empty());
     }
@@ -1,12 +1,33 @@
 //! A struct for reading CEDA weather data CSV files.
 
+use crate::datastore::FileProperties;
 use crate::error;
-use chrono::{DateTime, NaiveDateTime, Utc};
-use csv::{Reader, StringRecord, Writer};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use csv::{Reader, StringRecord, StringRecordsIntoIter};
 use error::AppError as Error;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Names of the observation id column across MIDAS versions, in order of preference
+const ID_COLUMNS: [&str; 3] = ["id", "src_id", "ob_id"];
+
+/// Values used for missing observations
+const MISSING_VALUES: [&str; 3] = ["", "NA", "na"];
+
+/// Date and time formats used across MIDAS versions, in order of preference. `%.f` also
+/// accepts times without fractional seconds.
+const DATE_TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%d/%m/%Y %H:%M:%S",
+];
 
 /// Represents a reader for processing CEDA weather data CSV files.
 #[derive(Debug)]
@@ -15,59 +36,459 @@ pub struct CedaCsvReader {
     pub historic_county_name: String,
     pub observation_station: String,
     pub location: Location,
-    pub height: u32,
+    /// Height above mean sea level in metres, which can be negative
+    pub height: f32,
     pub _date_valid: DateValid,
+    /// Observation domain from the metadata header e.g. `WMHOUR`, when the file declares one
+    #[allow(dead_code)]
+    pub met_domain_name: Option<String>,
     pub observations: Vec<Observation>,
 }
 
 /// The location of a weather station.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Location {
     pub lat: f32,
     pub lon: f32,
 }
 
+/// Semi-major and semi-minor axes in metres of the WGS84 ellipsoid
+const WGS84: (f64, f64) = (6_378_137.0, 6_356_752.314_245);
+
+/// Semi-major and semi-minor axes in metres of the Airy 1830 ellipsoid, used by OSGB36
+const AIRY_1830: (f64, f64) = (6_377_563.396, 6_356_256.909);
+
+/// Helmert transform from WGS84 to OSGB36: translations in metres, scale in parts per million
+/// and rotations in arc seconds
+const WGS84_TO_OSGB36: [f64; 7] = [
+    -446.448, 125.157, -542.060, 20.4894, -0.1502, -0.2470, -0.8421,
+];
+
+impl Location {
+    /// Convert to British National Grid easting and northing in metres, transforming from WGS84
+    /// to OSGB36 with the standard Helmert transform, which is accurate to about 5 m
+    #[allow(dead_code)]
+    pub fn to_osgb(&self) -> (f64, f64) {
+        let (lat, lon) = (
+            f64::from(self.lat).to_radians(),
+            f64::from(self.lon).to_radians(),
+        );
+        let (x, y, z) = to_cartesian(lat, lon, WGS84);
+        let (x, y, z) = helmert(x, y, z, WGS84_TO_OSGB36);
+        let (lat, lon) = from_cartesian(x, y, z, AIRY_1830);
+
+        project_national_grid(lat, lon)
+    }
+}
+
+/// Geocentric cartesian coordinates of a point at zero height on an ellipsoid
+fn to_cartesian(lat: f64, lon: f64, (a, b): (f64, f64)) -> (f64, f64, f64) {
+    let e2 = 1.0 - (b * b) / (a * a);
+    let nu = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+
+    (
+        nu * lat.cos() * lon.cos(),
+        nu * lat.cos() * lon.sin(),
+        (1.0 - e2) * nu * lat.sin(),
+    )
+}
+
+/// Apply a seven parameter Helmert transform to cartesian coordinates
+fn helmert(x: f64, y: f64, z: f64, [tx, ty, tz, s, rx, ry, rz]: [f64; 7]) -> (f64, f64, f64) {
+    let scale = 1.0 + s / 1e6;
+    let (rx, ry, rz) = (
+        (rx / 3600.0).to_radians(),
+        (ry / 3600.0).to_radians(),
+        (rz / 3600.0).to_radians(),
+    );
+
+    (
+        tx + x * scale - y * rz + z * ry,
+        ty + x * rz + y * scale - z * rx,
+        tz - x * ry + y * rx + z * scale,
+    )
+}
+
+/// Latitude and longitude in radians on an ellipsoid of cartesian coordinates, ignoring height
+fn from_cartesian(x: f64, y: f64, z: f64, (a, b): (f64, f64)) -> (f64, f64) {
+    let e2 = 1.0 - (b * b) / (a * a);
+    let p = (x * x + y * y).sqrt();
+
+    let mut lat = z.atan2(p * (1.0 - e2));
+    loop {
+        let nu = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+        let next = (z + e2 * nu * lat.sin()).atan2(p);
+        if (next - lat).abs() < 1e-12 {
+            return (next, y.atan2(x));
+        }
+        lat = next;
+    }
+}
+
+/// Project OSGB36 latitude and longitude in radians onto the National Grid, with the transverse
+/// Mercator formulas published by Ordnance Survey
+fn project_national_grid(lat: f64, lon: f64) -> (f64, f64) {
+    const F0: f64 = 0.999_601_271_7;
+    const E0: f64 = 400_000.0;
+    const N0: f64 = -100_000.0;
+    let (lat0, lon0) = (49f64.to_radians(), (-2f64).to_radians());
+    let (a, b) = AIRY_1830;
+
+    let e2 = 1.0 - (b * b) / (a * a);
+    let n = (a - b) / (a + b);
+    let (sin, cos, tan) = (lat.sin(), lat.cos(), lat.tan());
+    let nu = a * F0 / (1.0 - e2 * sin * sin).sqrt();
+    let rho = a * F0 * (1.0 - e2) / (1.0 - e2 * sin * sin).powf(1.5);
+    let eta2 = nu / rho - 1.0;
+
+    let (dlat, slat) = (lat - lat0, lat + lat0);
+    let m = b
+        * F0
+        * ((1.0 + n + 1.25 * n.powi(2) + 1.25 * n.powi(3)) * dlat
+            - (3.0 * n + 3.0 * n.powi(2) + 21.0 / 8.0 * n.powi(3)) * dlat.sin() * slat.cos()
+            + (15.0 / 8.0 * n.powi(2) + 15.0 / 8.0 * n.powi(3))
+                * (2.0 * dlat).sin()
+                * (2.0 * slat).cos()
+            - 35.0 / 24.0 * n.powi(3) * (3.0 * dlat).sin() * (3.0 * slat).cos());
+
+    let i = m + N0;
+    let ii = nu / 2.0 * sin * cos;
+    let iii = nu / 24.0 * sin * cos.powi(3) * (5.0 - tan.powi(2) + 9.0 * eta2);
+    let iiia = nu / 720.0 * sin * cos.powi(5) * (61.0 - 58.0 * tan.powi(2) + tan.powi(4));
+    let iv = nu * cos;
+    let v = nu / 6.0 * cos.powi(3) * (nu / rho - tan.powi(2));
+    let vi = nu / 120.0
+        * cos.powi(5)
+        * (5.0 - 18.0 * tan.powi(2) + tan.powi(4) + 14.0 * eta2 - 58.0 * tan.powi(2) * eta2);
+
+    let dlon = lon - lon0;
+    let northing = i + ii * dlon.powi(2) + iii * dlon.powi(4) + iiia * dlon.powi(6);
+    let easting = E0 + iv * dlon + v * dlon.powi(3) + vi * dlon.powi(5);
+
+    (easting, northing)
+}
+
 /// The valid date range for the weather data.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DateValid {
+    #[serde(rename = "from")]
     pub _from: DateTime<Utc>,
+    #[serde(rename = "to")]
     pub _to: DateTime<Utc>,
 }
 
 /// A weather observation.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Observation {
     pub date_time: NaiveDateTime,
+    #[serde(rename = "id")]
     pub _id: u32,
     pub wind: WindObservation,
+    /// Observation domain e.g. `SYNOP`, from the row or else the file's metadata header
+    pub met_domain_name: Option<String>,
+    /// Type of the id the observation was reported under e.g. `DCNN`
+    pub id_type: Option<String>,
+    /// Version of the observation, where a later version supersedes an earlier one for the
+    /// same time
+    pub version_num: Option<u32>,
+    /// Dataset version of the file the observation was read from e.g. `202407`, where a later
+    /// dataset version supersedes an earlier one
+    pub dataset_version: Option<String>,
+    /// The unmodeled columns of the row, when captured, with missing values as null
+    pub extra: Option<Map<String, Value>>,
 }
 
 /// A wind observation.
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct WindObservation {
     pub speed: Option<f32>,
     pub direction: Option<f32>,
     pub unit_id: Option<u32>,
     pub opr_type: Option<u32>,
+    pub gust_speed: Option<f32>,
+    pub gust_direction: Option<f32>,
+    pub gust_time: Option<NaiveTime>,
+}
+
+impl Observation {
+    /// Whether the observation has any measured value. Only wind is modeled so far.
+    pub fn has_any_data(&self) -> bool {
+        !self.wind.is_empty()
+    }
+}
+
+impl WindObservation {
+    /// Whether no wind or gust value was measured. The unit and operation type describe how
+    /// values were measured, so don't count.
+    pub fn is_empty(&self) -> bool {
+        self.speed.is_none()
+            && self.direction.is_none()
+            && self.gust_speed.is_none()
+            && self.gust_direction.is_none()
+            && self.gust_time.is_none()
+    }
+}
+
+impl fmt::Display for Observation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.date_time.format("%Y-%m-%d %H:%M"),
+            self.wind
+        )
+    }
+}
+
+impl fmt::Display for WindObservation {
+    /// The values present, e.g. `wind=4.0 dir=170 gust=9.0 gust_time=12:30`, or `no wind`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values: Vec<String> = [
+            self.speed.map(|speed| format!("wind={:.1}", speed)),
+            self.direction.map(|direction| format!("dir={}", direction)),
+            self.gust_speed.map(|speed| format!("gust={:.1}", speed)),
+            self.gust_direction
+                .map(|direction| format!("gust_dir={}", direction)),
+            self.gust_time
+                .map(|time| format!("gust_time={}", time.format("%H:%M"))),
+            self.unit_id.map(|unit_id| format!("unit={}", unit_id)),
+            self.opr_type.map(|opr_type| format!("opr={}", opr_type)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if values.is_empty() {
+            write!(f, "no wind")
+        } else {
+            write!(f, "{}", values.join(" "))
+        }
+    }
+}
+
+/// Type of station operation, recorded in `src_opr_type` using WMO code table 1860
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OprType {
+    /// Manned, with weather reported
+    Manned,
+    /// Manned, with weather omitted as there was no significant phenomenon
+    MannedNoSignificantWeather,
+    /// Manned, with weather omitted as it was not observed
+    MannedNotObserved,
+    /// Automatic, with weather reported using code tables 4677 and 4561
+    Automatic,
+    /// Automatic, with weather omitted as there was no significant phenomenon
+    AutomaticNoSignificantWeather,
+    /// Automatic, with weather omitted as it was not observed
+    AutomaticNotObserved,
+    /// Automatic, with weather reported using code tables 4680 and 4531
+    AutomaticWmoWeather,
+    /// A code not in the table
+    Unknown(u32),
+}
+
+impl OprType {
+    /// The code as stored in the data files and database
+    #[allow(dead_code)]
+    pub fn code(&self) -> u32 {
+        match self {
+            OprType::Manned => 1,
+            OprType::MannedNoSignificantWeather => 2,
+            OprType::MannedNotObserved => 3,
+            OprType::Automatic => 4,
+            OprType::AutomaticNoSignificantWeather => 5,
+            OprType::AutomaticNotObserved => 6,
+            OprType::AutomaticWmoWeather => 7,
+            OprType::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<u32> for OprType {
+    fn from(code: u32) -> Self {
+        match code {
+            1 => OprType::Manned,
+            2 => OprType::MannedNoSignificantWeather,
+            3 => OprType::MannedNotObserved,
+            4 => OprType::Automatic,
+            5 => OprType::AutomaticNoSignificantWeather,
+            6 => OprType::AutomaticNotObserved,
+            7 => OprType::AutomaticWmoWeather,
+            code => OprType::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for OprType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OprType::Manned => write!(f, "manned"),
+            OprType::MannedNoSignificantWeather => write!(f, "manned, no significant weather"),
+            OprType::MannedNotObserved => write!(f, "manned, weather not observed"),
+            OprType::Automatic => write!(f, "automatic"),
+            OprType::AutomaticNoSignificantWeather => {
+                write!(f, "automatic, no significant weather")
+            }
+            OprType::AutomaticNotObserved => write!(f, "automatic, weather not observed"),
+            OprType::AutomaticWmoWeather => write!(f, "automatic, WMO weather codes"),
+            OprType::Unknown(code) => write!(f, "unknown ({})", code),
+        }
+    }
+}
+
+/// How physically impossible observation values are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ValidationPolicy {
+    /// Replace impossible values with `None`
+    #[default]
+    Clamp,
+    /// Fail the file on impossible values
+    Reject,
+    /// Keep values as reported
+    Keep,
 }
 
 impl CedaCsvReader {
     /// Create a parsed weather data object from a CSV file.
+    #[allow(dead_code)]
     pub fn new(path: PathBuf) -> Result<Self, Error> {
-        let file = File::open(&path).map_err(|_| Error::FileNotFound)?;
-        let reader = BufReader::new(file);
-        let lines = reader
-            .lines()
-            .collect::<Result<Vec<String>, _>>()
-            .map_err(|_| Error::FileReadError)?;
-
-        let midas_station_id = CedaCsvReader::parse_midas_station_id(&lines)?;
-        let historic_county_name = CedaCsvReader::parse_historic_county_name(&lines)?;
-        let observation_station = CedaCsvReader::parse_observation_station(&lines)?;
-        let location = CedaCsvReader::parse_location(&lines)?;
-        let height = CedaCsvReader::parse_height(&lines)?;
-        let date_valid = CedaCsvReader::parse_date_valid(&lines)?;
-        let observations = CedaCsvReader::parse_observations(&lines)?;
+        CedaCsvReader::with_policy(path, ValidationPolicy::default())
+    }
+
+    /// Create a parsed weather data object from a CSV file, validating observations with the given policy.
+    pub fn with_policy(path: PathBuf, policy: ValidationPolicy) -> Result<Self, Error> {
+        CedaCsvReader::with_options(path, policy, false)
+    }
+
+    /// Create a parsed weather data object from a CSV file, optionally capturing the columns that
+    /// are not modeled into each observation's `extra` map.
+    pub fn with_options(
+        path: PathBuf,
+        policy: ValidationPolicy,
+        capture_extra: bool,
+    ) -> Result<Self, Error> {
+        let reader = open_data_file(&path)?;
+        let lines = decoded_lines(reader).collect::<Result<Vec<String>, _>>()?;
+
+        let mut observations = CedaCsvReader::parse_observations(&lines, policy, capture_extra)?;
+        let dataset_version = dataset_version(&path);
+        for observation in &mut observations {
+            observation.dataset_version = dataset_version.clone();
+        }
+        let reader = CedaCsvReader::from_metadata(&lines, observations)?;
+
+        if policy == ValidationPolicy::Reject {
+            if let Some(observation) = reader.out_of_range_observations().first() {
+                return Err(reader.out_of_range_error(observation));
+            }
+        }
+
+        Ok(reader)
+    }
+
+    /// Observations dated outside the file's declared `date_valid` range, which suggest a
+    /// mislabeled file or stray rows from an adjacent period
+    pub fn out_of_range_observations(&self) -> Vec<&Observation> {
+        self.observations
+            .iter()
+            .filter(|observation| !self.is_in_range(observation))
+            .collect()
+    }
+
+    /// Whether an observation is dated within the file's declared `date_valid` range
+    pub fn is_in_range(&self, observation: &Observation) -> bool {
+        let from = self._date_valid._from.naive_utc();
+        let to = self._date_valid._to.naive_utc();
+
+        (from..=to).contains(&observation.date_time)
+    }
+
+    /// The error failing a file for an observation dated outside its valid dates
+    pub fn out_of_range_error(&self, observation: &Observation) -> Error {
+        Error::ObservationOutOfRange {
+            date_time: observation.date_time.to_string(),
+            from: self._date_valid._from.naive_utc().to_string(),
+            to: self._date_valid._to.naive_utc().to_string(),
+        }
+    }
+
+    /// Lazily parse the observations of a CSV file, reading rows as they are consumed rather than
+    /// holding the whole file.
+    pub fn observations_iter(
+        path: PathBuf,
+        policy: ValidationPolicy,
+        capture_extra: bool,
+    ) -> Result<impl Iterator<Item = Result<Observation, Error>> + Send, Error> {
+        let mut lines = decoded_lines(open_data_file(&path)?);
+
+        // Read the metadata lines up to the header row
+        let mut metadata = Vec::new();
+        let mut finder = HeaderFinder::default();
+        let header = loop {
+            let line = lines
+                .next()
+                .ok_or_else(|| Error::ColumnNotFound("ob_time".to_string()))??;
+            if finder.is_header(&line) {
+                break line;
+            }
+            metadata.push(line);
+        };
+        let met_domain_name =
+            CedaCsvReader::find_metadata_value(&metadata, "met_domain_name").map(str::to_string);
+        let dataset_version = dataset_version(&path);
+
+        let data_lines = std::iter::once(Ok(header)).chain(lines).take_while(
+            |line| !matches!(line, Ok(line) if line.split(',').next() == Some("end data")),
+        );
+        let rdr = Reader::from_reader(LinesReader::new(data_lines));
+        let observations = ObservationIter::new(rdr, policy, capture_extra)?;
+
+        Ok(observations.map(move |observation| {
+            let mut observation = observation?;
+            observation.dataset_version = dataset_version.clone();
+            if let Some(met_domain_name) = &met_domain_name {
+                observation
+                    .met_domain_name
+                    .get_or_insert_with(|| met_domain_name.clone());
+            }
+            Ok(observation)
+        }))
+    }
+
+    /// Parse only the station metadata header of a CSV file, leaving the observations empty.
+    pub fn read_metadata_only(path: PathBuf) -> Result<Self, Error> {
+        let reader = open_data_file(&path)?;
+
+        // The metadata ends at the `data` line that introduces the observations
+        let mut lines = Vec::new();
+        for line in decoded_lines(reader) {
+            let line = line?;
+            if line == "data" {
+                break;
+            }
+            lines.push(line);
+        }
+
+        CedaCsvReader::from_metadata(&lines, Vec::new())
+    }
+
+    fn from_metadata(lines: &[String], mut observations: Vec<Observation>) -> Result<Self, Error> {
+        let midas_station_id = CedaCsvReader::parse_midas_station_id(lines)?;
+        let historic_county_name = CedaCsvReader::parse_historic_county_name(lines)?;
+        let observation_station = CedaCsvReader::parse_observation_station(lines)?;
+        let location = CedaCsvReader::parse_location(lines)?;
+        let height = CedaCsvReader::parse_height(lines)?;
+        let date_valid = CedaCsvReader::parse_date_valid(lines)?;
+        let met_domain_name =
+            CedaCsvReader::find_metadata_value(lines, "met_domain_name").map(str::to_string);
+
+        if let Some(met_domain_name) = &met_domain_name {
+            for observation in &mut observations {
+                observation
+                    .met_domain_name
+                    .get_or_insert_with(|| met_domain_name.clone());
+            }
+        }
 
         Ok(Self {
             midas_station_id,
@@ -76,10 +497,49 @@ impl CedaCsvReader {
             location,
             height,
             _date_valid: date_valid,
+            met_domain_name,
             observations,
         })
     }
 
+    /// Find the value of a metadata line by its key, wherever it is in the header, e.g. `WMHOUR`
+    /// from `met_domain_name,G,WMHOUR`
+    fn find_metadata_value<'a>(lines: &'a [String], key: &str) -> Option<&'a str> {
+        lines
+            .iter()
+            .take_while(|line| line.as_str() != "data")
+            .find_map(|line| {
+                let mut parts = line.split(',');
+                (parts.next() == Some(key))
+                    .then(|| parts.nth(1))
+                    .flatten()
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+            })
+    }
+
+    /// Number of observations parsed from the file
+    pub fn observation_count(&self) -> usize {
+        self.observations.len()
+    }
+
+    /// Read the observation column names from a CSV file without parsing the observations.
+    #[allow(dead_code)]
+    pub fn columns(path: PathBuf) -> Result<Vec<String>, Error> {
+        let reader = open_data_file(&path)?;
+
+        let mut finder = HeaderFinder::default();
+        for line in decoded_lines(reader) {
+            let line = line?;
+
+            if finder.is_header(&line) {
+                return Ok(line.split(',').map(|s| s.to_string()).collect());
+            }
+        }
+
+        Err(Error::ColumnNotFound("ob_time".to_string()))
+    }
+
     fn parse_observation_station(lines: &[String]) -> Result<String, Error> {
         let parts: Vec<String> = lines[10].split(',').map(|s| s.to_string()).collect();
 
@@ -131,7 +591,7 @@ impl CedaCsvReader {
         Ok(Location { lat, lon })
     }
 
-    fn parse_height(lines: &[String]) -> Result<u32, Error> {
+    fn parse_height(lines: &[String]) -> Result<f32, Error> {
         let parts: Vec<String> = lines[15].split(',').map(|s| s.to_string()).collect();
 
         if parts[0] != "height" {
@@ -139,7 +599,8 @@ impl CedaCsvReader {
         }
 
         let height = parts[2]
-            .parse::<u32>()
+            .trim()
+            .parse::<f32>()
             .map_err(|_| Error::CsvHeightParsingError)?;
 
         Ok(height)
@@ -152,8 +613,8 @@ impl CedaCsvReader {
             return Err(Error::CsvDateValidMissingError);
         }
 
-        let date_from_naivedate = NaiveDateTime::parse_from_str(&parts[2], "%Y-%m-%d %H:%M:%S")?;
-        let date_to_naivedate = NaiveDateTime::parse_from_str(&parts[3], "%Y-%m-%d %H:%M:%S")?;
+        let date_from_naivedate = parse_date_time(&parts[2])?;
+        let date_to_naivedate = parse_date_time(&parts[3])?;
 
         Ok(DateValid {
             _from: DateTime::<Utc>::from_naive_utc_and_offset(date_from_naivedate, Utc),
@@ -162,45 +623,18 @@ impl CedaCsvReader {
     }
 
     // Parse the observations from the CSV data
-    fn parse_observations(lines: &[String]) -> Result<Vec<Observation>, Error> {
+    fn parse_observations(
+        lines: &[String],
+        policy: ValidationPolicy,
+        capture_extra: bool,
+    ) -> Result<Vec<Observation>, Error> {
         // Read the CSV data to a string
         let csv_data = CedaCsvReader::vec_to_csv(lines)?;
 
         // Process the CSV data
-        let mut rdr = Reader::from_reader(csv_data.as_bytes());
-        let headers = rdr.headers().unwrap().clone();
-
-        let date_time_index = CedaCsvReader::get_column_index(&headers, "ob_time")?;
-        let id_index = CedaCsvReader::get_column_index(&headers, "id")?;
-        let wind_speed_index = CedaCsvReader::get_column_index(&headers, "wind_speed")?;
-        let wind_direction_index = CedaCsvReader::get_column_index(&headers, "wind_direction")?;
-        let wind_speed_unit_id_index =
-            CedaCsvReader::get_column_index(&headers, "wind_speed_unit_id")?;
-        let src_opr_type_index = CedaCsvReader::get_column_index(&headers, "src_opr_type")?;
-
-        let mut observations = Vec::new();
-        for result in rdr.records() {
-            let record = result.unwrap();
-            let date_time =
-                NaiveDateTime::parse_from_str(&record[date_time_index], "%Y-%m-%d %H:%M:%S")?;
-            let id = record[id_index].parse::<u32>().unwrap();
-            let wind = Self::parse_wind(
-                wind_speed_index,
-                wind_direction_index,
-                wind_speed_unit_id_index,
-                src_opr_type_index,
-                record,
-            );
-
-            let observation = Observation {
-                date_time,
-                _id: id,
-                wind,
-            };
-            observations.push(observation);
-        }
+        let rdr = Reader::from_reader(csv_data.as_bytes());
 
-        Ok(observations)
+        ObservationIter::new(rdr, policy, capture_extra)?.collect()
     }
 
     fn get_column_index(headers: &StringRecord, column_name: &str) -> Result<usize, Error> {
@@ -210,64 +644,415 @@ impl CedaCsvReader {
             .ok_or_else(|| Error::ColumnNotFound(column_name.to_string()))
     }
 
+    /// Get the index of the first of the candidate columns present in the headers.
+    fn get_first_column_index(headers: &StringRecord, candidates: &[&str]) -> Result<usize, Error> {
+        candidates
+            .iter()
+            .find_map(|column_name| headers.iter().position(|h| h == *column_name))
+            .ok_or_else(|| Error::ColumnNotFound(candidates.join(" or ")))
+    }
+
     fn parse_wind(
-        wind_speed_index: usize,
-        wind_direction_index: usize,
-        wind_speed_unit_id_index: usize,
-        src_opr_type_index: usize,
-        record: StringRecord,
-    ) -> WindObservation {
-        let wind_speed = record[wind_speed_index].parse::<f32>().ok();
-        let wind_direction = record[wind_direction_index].parse::<f32>().ok();
-        let wind_speed_unit_id = record[wind_speed_unit_id_index].parse::<u32>().ok();
-        let src_opr_type = record[src_opr_type_index].parse::<u32>().ok();
-
-        WindObservation {
-            speed: wind_speed,
-            direction: wind_direction,
-            unit_id: wind_speed_unit_id,
-            opr_type: src_opr_type,
-        }
+        columns: &WindColumns,
+        record: &StringRecord,
+        policy: ValidationPolicy,
+    ) -> Result<WindObservation, Error> {
+        let optional_field = |index: Option<usize>| index.and_then(|index| record.get(index));
+
+        // Negative speeds and directions outside a full circle are sensor errors
+        let is_speed = |speed: f32| speed >= 0.0;
+        let is_direction = |direction: f32| (0.0..=360.0).contains(&direction);
+
+        let speed = validate(&record[columns.speed], "wind_speed", is_speed, policy)?;
+        let direction = validate(
+            &record[columns.direction],
+            "wind_direction",
+            is_direction,
+            policy,
+        )?;
+        let unit_id = parse_optional(&record[columns.unit_id], "wind_speed_unit_id", policy)?;
+        let opr_type = parse_optional(&record[columns.opr_type], "src_opr_type", policy)?;
+
+        let gust_speed = match optional_field(columns.gust_speed) {
+            Some(value) => validate(value, "max_gust_speed", is_speed, policy)?,
+            None => None,
+        };
+        let gust_direction = match optional_field(columns.gust_direction) {
+            Some(value) => validate(value, "max_gust_dir", is_direction, policy)?,
+            None => None,
+        };
+        let gust_time = optional_field(columns.gust_time).and_then(parse_gust_time);
+
+        Ok(WindObservation {
+            speed,
+            direction,
+            unit_id,
+            opr_type,
+            gust_speed,
+            gust_direction,
+            gust_time,
+        })
     }
 
-    // Convert a vector of strings to a CSV string
+    // Extract the data section, from the header row up to the end marker, as a CSV string.
+    // Lines are kept verbatim so quoted fields containing commas are parsed by the CSV reader.
     fn vec_to_csv(lines: &[String]) -> Result<String, Error> {
-        let mut wtr = Writer::from_writer(vec![]);
+        let mut finder = HeaderFinder::default();
+        let data_lines: Vec<&str> = lines
+            .iter()
+            .skip_while(|line| !finder.is_header(line))
+            .take_while(|line| line.split(',').next() != Some("end data"))
+            .map(|line| line.as_str())
+            .collect();
+
+        Ok(data_lines.join("\n"))
+    }
+}
+
+/// Finds the header row of the data section: the first line after the `data` line that names
+/// `ob_time` among its columns, which needn't be first, e.g. `id,ob_time,...`. Metadata lines
+/// can name `ob_time` too, e.g. `long_name,ob_time,...`, so before any `data` line only a row
+/// starting with `ob_time` counts.
+#[derive(Debug, Default)]
+struct HeaderFinder {
+    in_data: bool,
+}
 
-        let mut iter = lines.iter();
+impl HeaderFinder {
+    fn is_header(&mut self, line: &str) -> bool {
+        let mut fields = line.split(',').map(str::trim);
+        if line.trim() == "data" {
+            self.in_data = true;
+            false
+        } else if self.in_data {
+            fields.any(|field| field == "ob_time")
+        } else {
+            fields.next() == Some("ob_time")
+        }
+    }
+}
 
-        // Skip lines until the header row containing "ob_time" is found
-        #[allow(clippy::while_let_on_iterator)]
-        while let Some(line) = iter.next() {
-            let parts = line
-                .split(',')
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>();
+/// Indexes of the wind columns in a record. Gust columns are absent from some files.
+#[derive(Debug, Clone, Copy)]
+struct WindColumns {
+    speed: usize,
+    direction: usize,
+    unit_id: usize,
+    opr_type: usize,
+    gust_speed: Option<usize>,
+    gust_direction: Option<usize>,
+    gust_time: Option<usize>,
+}
 
-            if parts[0] == "ob_time" {
-                wtr.write_record(line.split(',').collect::<Vec<&str>>())
-                    .unwrap();
-                break;
-            }
+/// Read the lines of a data file, dropping a leading byte order mark and replacing bytes that are
+/// not valid UTF-8, so a stray byte in a remark doesn't fail the whole file
+fn decoded_lines(reader: impl BufRead) -> impl Iterator<Item = Result<String, Error>> {
+    reader.split(b'\n').enumerate().map(|(index, line)| {
+        let mut line = line.map_err(|_| Error::FileReadError)?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
         }
+        let line = String::from_utf8_lossy(&line);
+        let line = if index == 0 {
+            line.trim_start_matches('\u{feff}')
+        } else {
+            &line
+        };
 
-        // Write the remaining lines to the CSV writer
-        for line in iter {
-            let parts: Vec<&str> = line.split(',').collect();
-            if parts[0] != "end data" {
-                wtr.write_record(&parts).unwrap();
+        Ok(line.to_string())
+    })
+}
+
+/// Reads decoded lines back as newline-terminated text, so the CSV reader can parse them as they
+/// are read
+struct LinesReader<I> {
+    lines: I,
+    line: Vec<u8>,
+    position: usize,
+}
+
+impl<I> LinesReader<I> {
+    fn new(lines: I) -> Self {
+        Self {
+            lines,
+            line: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<String, Error>>> Read for LinesReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.line.len() {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.line = line.into_bytes();
+                    self.line.push(b'\n');
+                    self.position = 0;
+                }
+                Some(Err(e)) => return Err(io::Error::other(e.to_string())),
+                None => return Ok(0),
             }
         }
 
-        let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        let len = buf.len().min(self.line.len() - self.position);
+        buf[..len].copy_from_slice(&self.line[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Open a data file for reading, decompressing it on the fly when it ends in `.gz`
+fn open_data_file(path: &Path) -> Result<Box<dyn BufRead + Send>, Error> {
+    let file = File::open(path).map_err(|_| Error::FileNotFound)?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Parse an optional measurement, applying the validation policy when it is physically impossible.
+fn validate(
+    value: &str,
+    column: &str,
+    is_valid: impl Fn(f32) -> bool,
+    policy: ValidationPolicy,
+) -> Result<Option<f32>, Error> {
+    let Some(measurement) = parse_optional::<f32>(value, column, policy)? else {
+        return Ok(None);
+    };
+    if is_valid(measurement) {
+        return Ok(Some(measurement));
+    }
+
+    match policy {
+        ValidationPolicy::Clamp => Ok(None),
+        ValidationPolicy::Reject => Err(Error::CsvInvalidValueError(
+            column.to_string(),
+            value.to_string(),
+        )),
+        ValidationPolicy::Keep => Ok(Some(measurement)),
+    }
+}
+
+/// Parse an optional field. Missing values are `None`, as are unparseable values unless the
+/// policy rejects invalid values.
+fn parse_optional<T: FromStr>(
+    value: &str,
+    column: &str,
+    policy: ValidationPolicy,
+) -> Result<Option<T>, Error> {
+    let value = value.trim();
+    if MISSING_VALUES.contains(&value) {
+        return Ok(None);
+    }
+
+    match value.parse::<T>() {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(_) if policy == ValidationPolicy::Reject => Err(Error::CsvFieldParseError {
+            column: column.to_string(),
+            value: value.to_string(),
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parse a date and time in the first of the known formats that fits
+fn parse_date_time(value: &str) -> Result<NaiveDateTime, Error> {
+    let value = value.trim();
+    let mut last_error = None;
+    for format in DATE_TIME_FORMATS {
+        match NaiveDateTime::parse_from_str(value, format) {
+            Ok(date_time) => return Ok(date_time),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(Error::CsvDateParseError(
+        last_error.expect("there are date formats to try"),
+    ))
+}
+
+/// Parse the time of a maximum gust, reported as HHmm
+fn parse_gust_time(value: &str) -> Option<NaiveTime> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    NaiveTime::parse_from_str(&format!("{:0>4}", value), "%H%M").ok()
+}
+
+/// The dataset version of a data file from its filename e.g. `202407` from `..._dv-202407_...`
+fn dataset_version(path: &Path) -> Option<String> {
+    let properties = FileProperties::new(path.to_path_buf()).ok()?;
+
+    Some(
+        properties
+            .updated
+            .strip_prefix("dv-")
+            .unwrap_or(&properties.updated)
+            .to_string(),
+    )
+}
+
+/// Parses observations one row at a time from the data section of a CSV file.
+struct ObservationIter<R: Read> {
+    records: StringRecordsIntoIter<R>,
+    policy: ValidationPolicy,
+    date_time_index: usize,
+    id_index: usize,
+    wind_columns: WindColumns,
+    met_domain_name_index: Option<usize>,
+    id_type_index: Option<usize>,
+    version_num_index: Option<usize>,
+    /// Names and indexes of the unmodeled columns, when they are captured
+    extra_columns: Option<Vec<(String, usize)>>,
+}
+
+impl<R: Read> ObservationIter<R> {
+    fn new(
+        mut rdr: Reader<R>,
+        policy: ValidationPolicy,
+        capture_extra: bool,
+    ) -> Result<Self, Error> {
+        let headers = rdr.headers().map_err(|_| Error::FileReadError)?.clone();
+
+        let date_time_index = CedaCsvReader::get_column_index(&headers, "ob_time")?;
+        let id_index = CedaCsvReader::get_first_column_index(&headers, &ID_COLUMNS)?;
+        let wind_columns = WindColumns {
+            speed: CedaCsvReader::get_column_index(&headers, "wind_speed")?,
+            direction: CedaCsvReader::get_column_index(&headers, "wind_direction")?,
+            unit_id: CedaCsvReader::get_column_index(&headers, "wind_speed_unit_id")?,
+            opr_type: CedaCsvReader::get_column_index(&headers, "src_opr_type")?,
+            gust_speed: CedaCsvReader::get_column_index(&headers, "max_gust_speed").ok(),
+            gust_direction: CedaCsvReader::get_column_index(&headers, "max_gust_dir").ok(),
+            gust_time: CedaCsvReader::get_column_index(&headers, "max_gust_ctime").ok(),
+        };
+        let met_domain_name_index =
+            CedaCsvReader::get_column_index(&headers, "met_domain_name").ok();
+        let id_type_index = CedaCsvReader::get_column_index(&headers, "id_type").ok();
+        let version_num_index = CedaCsvReader::get_column_index(&headers, "version_num").ok();
+
+        let extra_columns = capture_extra.then(|| {
+            let modeled = [
+                Some(date_time_index),
+                Some(id_index),
+                Some(wind_columns.speed),
+                Some(wind_columns.direction),
+                Some(wind_columns.unit_id),
+                Some(wind_columns.opr_type),
+                wind_columns.gust_speed,
+                wind_columns.gust_direction,
+                wind_columns.gust_time,
+                met_domain_name_index,
+                id_type_index,
+                version_num_index,
+            ];
+            headers
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !modeled.contains(&Some(*index)))
+                .map(|(index, name)| (name.to_string(), index))
+                .collect()
+        });
+
+        Ok(Self {
+            date_time_index,
+            id_index,
+            wind_columns,
+            met_domain_name_index,
+            id_type_index,
+            version_num_index,
+            extra_columns,
+            records: rdr.into_records(),
+            policy,
+        })
+    }
 
-        Ok(data)
+    fn parse_record(&self, record: StringRecord) -> Result<Observation, Error> {
+        let date_time = parse_date_time(&record[self.date_time_index])?;
+        let id = parse_field::<u32>(&record, self.id_index, "id")?;
+        let wind = CedaCsvReader::parse_wind(&self.wind_columns, &record, self.policy)?;
+        let met_domain_name =
+            optional_field(&record, self.met_domain_name_index).map(str::to_string);
+        let id_type = optional_field(&record, self.id_type_index).map(str::to_string);
+        let version_num = match self.version_num_index {
+            Some(index) if optional_field(&record, Some(index)).is_some() => {
+                Some(parse_field::<u32>(&record, index, "version_num")?)
+            }
+            _ => None,
+        };
+        let extra = self.extra_columns.as_ref().map(|extra_columns| {
+            extra_columns
+                .iter()
+                .map(|(name, index)| {
+                    let value = match record.get(*index) {
+                        Some(value) if !MISSING_VALUES.contains(&value) => {
+                            Value::String(value.to_string())
+                        }
+                        _ => Value::Null,
+                    };
+                    (name.clone(), value)
+                })
+                .collect()
+        });
+
+        Ok(Observation {
+            date_time,
+            _id: id,
+            wind,
+            met_domain_name,
+            id_type,
+            version_num,
+            dataset_version: None,
+            extra,
+        })
+    }
+}
+
+/// Get the value of an optional column of a record, or `None` when the column is absent or the
+/// value is missing
+fn optional_field(record: &StringRecord, index: Option<usize>) -> Option<&str> {
+    index
+        .and_then(|index| record.get(index))
+        .filter(|value| !MISSING_VALUES.contains(value))
+}
+
+/// Parse a required field of a record, reporting the column and value on failure.
+fn parse_field<T: FromStr>(record: &StringRecord, index: usize, column: &str) -> Result<T, Error> {
+    record[index]
+        .parse::<T>()
+        .map_err(|_| Error::CsvFieldParseError {
+            column: column.to_string(),
+            value: record[index].to_string(),
+        })
+}
+
+impl<R: Read> Iterator for ObservationIter<R> {
+    type Item = Result<Observation, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(_) => return Some(Err(Error::FileReadError)),
+        };
+
+        // The data section ends with an "end data" marker row
+        if record.get(0) == Some("end data") {
+            return None;
+        }
+
+        Some(self.parse_record(record))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::support::MidasCsv;
 
     #[test]
     fn it_creates_new() {
@@ -315,7 +1100,19 @@ mod test {
         let file_path = get_test_file_path();
         let reader = CedaCsvReader::new(file_path).unwrap();
 
-        assert_eq!(reader.height, 64);
+        assert_eq!(reader.height, 64.0);
+    }
+
+    #[test]
+    fn it_parses_negative_and_decimal_heights() {
+        let mut lines = vec![String::new(); 16];
+        for (value, expected) in [("-2", -2.0), ("12.5", 12.5), (" 64 ", 64.0)] {
+            lines[15] = format!("height,G,{},m", value);
+            assert_eq!(CedaCsvReader::parse_height(&lines).unwrap(), expected);
+        }
+
+        lines[15] = "height,G,unknown,m".to_string();
+        assert!(CedaCsvReader::parse_height(&lines).is_err());
     }
 
     #[test]
@@ -330,6 +1127,51 @@ mod test {
         assert_eq!(reader.location, expected_location);
     }
 
+    #[test]
+    fn it_projects_onto_national_grid() {
+        // Worked example from Ordnance Survey's guide to coordinate systems in Great Britain
+        let lat: f64 = 52.0 + 39.0 / 60.0 + 27.2531 / 3600.0;
+        let lon: f64 = 1.0 + 43.0 / 60.0 + 4.5177 / 3600.0;
+
+        let (easting, northing) = project_national_grid(lat.to_radians(), lon.to_radians());
+
+        assert!((easting - 651_409.903).abs() < 0.01, "{}", easting);
+        assert!((northing - 313_177.270).abs() < 0.01, "{}", northing);
+    }
+
+    #[test]
+    fn it_converts_locations_to_osgb() {
+        // Heathrow's MIDAS location and grid reference, both given to about 100 m, and the WGS84
+        // coordinates of Ordnance Survey's worked example
+        let locations = [
+            ("heathrow", 51.479, -0.449, (507_800.0, 176_700.0), 100.0),
+            (
+                "os example",
+                52.0 + 39.0 / 60.0 + 28.8282 / 3600.0,
+                1.0 + 42.0 / 60.0 + 57.8663 / 3600.0,
+                (651_409.903, 313_177.270),
+                5.0,
+            ),
+        ];
+
+        for (name, lat, lon, (expected_easting, expected_northing), tolerance) in locations {
+            let (easting, northing) = Location { lat, lon }.to_osgb();
+
+            assert!(
+                (easting - expected_easting).abs() < tolerance,
+                "{}: {}",
+                name,
+                easting
+            );
+            assert!(
+                (northing - expected_northing).abs() < tolerance,
+                "{}: {}",
+                name,
+                northing
+            );
+        }
+    }
+
     #[test]
     fn it_gets_observation_date() {
         let file_path = get_test_file_path();
@@ -363,11 +1205,508 @@ mod test {
             direction: Some(170.0),
             unit_id: None,
             opr_type: None,
+            ..Default::default()
         };
 
         assert_eq!(observation.wind, expected_wind);
     }
 
+    #[test]
+    fn it_iterates_observations() {
+        let file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_leading-id.csv",
+        );
+        let reader = CedaCsvReader::new(file_path.clone()).unwrap();
+
+        let observations: Vec<Observation> =
+            CedaCsvReader::observations_iter(file_path, ValidationPolicy::default(), false)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+        assert_eq!(observations.len(), reader.observations.len());
+        for (observation, expected) in observations.iter().zip(&reader.observations) {
+            assert_eq!(observation.date_time, expected.date_time);
+            assert_eq!(observation.wind, expected.wind);
+            assert_eq!(observation.met_domain_name, expected.met_domain_name);
+            assert_eq!(observation.dataset_version, expected.dataset_version);
+        }
+    }
+
+    #[test]
+    fn it_reads_src_id_as_observation_id() {
+        let file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+        let reader = CedaCsvReader::new(file_path).unwrap();
+
+        assert_eq!(reader.observations.len(), 3);
+        assert_eq!(reader.observations[0]._id, 1448);
+    }
+
+    #[test]
+    fn it_finds_header_with_ob_time_not_first() {
+        let file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_leading-id.csv",
+        );
+
+        let reader = CedaCsvReader::new(file_path.clone()).unwrap();
+        assert_eq!(reader.observations.len(), 3);
+        assert_eq!(reader.observations[0]._id, 1448);
+        assert_eq!(
+            reader.observations[2].date_time.to_string(),
+            "1994-10-01 02:00:00"
+        );
+
+        let columns = CedaCsvReader::columns(file_path).unwrap();
+        assert_eq!(columns[0], "id");
+        assert_eq!(columns[1], "ob_time");
+    }
+
+    #[test]
+    fn it_reads_gzipped_files() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+        let file_path = std::env::temp_dir().join("rust-ceda-gzipped.csv.gz");
+        let mut encoder = GzEncoder::new(File::create(&file_path).unwrap(), Compression::default());
+        encoder
+            .write_all(&std::fs::read(&fixture).unwrap())
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let reader = CedaCsvReader::new(file_path).unwrap();
+
+        assert_eq!(reader.midas_station_id, 1448);
+        assert_eq!(reader.observations.len(), 3);
+    }
+
+    #[test]
+    fn it_reads_files_with_bom_and_invalid_utf8() {
+        let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+        let contents = std::fs::read_to_string(fixture)
+            .unwrap()
+            .replacen("SYNOP", "SYN\u{0}OP", 1);
+        let mut bytes = b"\xef\xbb\xbf".to_vec();
+        bytes.extend(
+            contents
+                .bytes()
+                .map(|byte| if byte == 0 { 0xff } else { byte }),
+        );
+        let file_path = std::env::temp_dir().join("rust-ceda-bom.csv");
+        std::fs::write(&file_path, bytes).unwrap();
+
+        let reader =
+            CedaCsvReader::with_options(file_path.clone(), ValidationPolicy::default(), true)
+                .unwrap();
+
+        assert_eq!(reader.midas_station_id, 1448);
+        assert_eq!(reader.observations.len(), 3);
+        assert_eq!(
+            reader.observations[0].met_domain_name.as_deref(),
+            Some("SYN\u{fffd}OP")
+        );
+
+        let observations: Vec<Observation> =
+            CedaCsvReader::observations_iter(file_path, ValidationPolicy::default(), true)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(observations.len(), 3);
+        assert_eq!(
+            observations[0].met_domain_name.as_deref(),
+            Some("SYN\u{fffd}OP")
+        );
+    }
+
+    #[test]
+    fn it_captures_extra_columns() {
+        let file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+
+        let reader =
+            CedaCsvReader::with_options(file_path.clone(), ValidationPolicy::default(), true)
+                .unwrap();
+        let extra = reader.observations[0].extra.as_ref().unwrap();
+
+        assert_eq!(extra["air_temperature"], "10.2");
+        assert_eq!(extra["rec_st_ind"], "1011");
+        assert!(!extra.contains_key("wind_speed"));
+        assert!(!extra.contains_key("id_type"));
+        assert!(!extra.contains_key("version_num"));
+        assert!(!extra.contains_key("met_domain_name"));
+        assert!(!extra.contains_key("src_id"));
+
+        let reader = CedaCsvReader::new(file_path).unwrap();
+        assert!(reader.observations[0].extra.is_none());
+    }
+
+    #[test]
+    fn it_finds_out_of_range_observations() {
+        let file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_out-of-range.csv",
+        );
+
+        let reader = CedaCsvReader::new(file_path.clone()).unwrap();
+        let out_of_range = reader.out_of_range_observations();
+        assert_eq!(out_of_range.len(), 1);
+        assert_eq!(out_of_range[0].date_time.to_string(), "1995-01-01 02:00:00");
+
+        let result = CedaCsvReader::with_policy(file_path, ValidationPolicy::Reject);
+        assert!(matches!(result, Err(Error::ObservationOutOfRange { .. })));
+    }
+
+    #[test]
+    fn it_reads_met_domain_name() {
+        let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+        let reader = CedaCsvReader::new(fixture.clone()).unwrap();
+        assert_eq!(reader.met_domain_name, None);
+        assert_eq!(
+            reader.observations[0].met_domain_name.as_deref(),
+            Some("SYNOP")
+        );
+
+        // Declare the domain in the header and leave it out of the first row
+        let contents = std::fs::read_to_string(fixture)
+            .unwrap()
+            .replacen(
+                "coordinate_variable,1,x\n",
+                "coordinate_variable,1,x\nmet_domain_name,G,WMHOUR\n",
+                1,
+            )
+            .replacen("DCNN,SYNOP,", "DCNN,,", 1);
+        let file_path = std::env::temp_dir().join("rust-ceda-met-domain.csv");
+        std::fs::write(&file_path, contents).unwrap();
+
+        let reader = CedaCsvReader::new(file_path).unwrap();
+
+        assert_eq!(reader.met_domain_name.as_deref(), Some("WMHOUR"));
+        assert_eq!(
+            reader.observations[0].met_domain_name.as_deref(),
+            Some("WMHOUR")
+        );
+        assert_eq!(
+            reader.observations[1].met_domain_name.as_deref(),
+            Some("SYNOP")
+        );
+    }
+
+    #[test]
+    fn it_reads_id_type_and_version_num() {
+        let file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+
+        let reader = CedaCsvReader::new(file_path).unwrap();
+
+        assert_eq!(reader.observations[0].id_type.as_deref(), Some("DCNN"));
+        assert_eq!(reader.observations[0].version_num, Some(1));
+    }
+
+    #[test]
+    fn it_parses_generated_files() {
+        let dir = std::env::temp_dir().join("rust-ceda-generated");
+        let midas_csv = MidasCsv::new()
+            .with_rows(100)
+            .with_header_line("met_domain_name,G,WMHOUR");
+
+        for path in [
+            midas_csv.write(&dir),
+            midas_csv.clone().quoted().write_gz(&dir),
+        ] {
+            let reader = CedaCsvReader::new(path).unwrap();
+
+            assert_eq!(reader.observation_count(), 100);
+            assert_eq!(reader.met_domain_name.as_deref(), Some("WMHOUR"));
+            assert_eq!(
+                reader.observations[0].dataset_version.as_deref(),
+                Some("202407")
+            );
+            assert_eq!(reader.observations[5].wind.speed, Some(5.0));
+            assert_eq!(
+                reader.observations[25].date_time.to_string(),
+                "1994-01-02 01:00:00"
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_generated_files_with_bad_rows_or_columns() {
+        let dir = std::env::temp_dir().join("rust-ceda-generated-bad");
+
+        let path = MidasCsv::new()
+            .with_raw_row("yesterday,DCNN,SYNOP,1,1448,1011,4,1,160,3,,,")
+            .write(&dir);
+        assert!(matches!(
+            CedaCsvReader::new(path),
+            Err(Error::CsvDateParseError(_))
+        ));
+
+        let path = MidasCsv::new().without_column("wind_speed").write(&dir);
+        assert!(matches!(
+            CedaCsvReader::new(path),
+            Err(Error::ColumnNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn it_reads_metadata_only() {
+        let file_path = get_test_file_path();
+        let reader = CedaCsvReader::read_metadata_only(file_path).unwrap();
+
+        assert_eq!(reader.midas_station_id, 1448);
+        assert_eq!(reader.observation_station, "portglenone");
+        assert!(reader.observations.is_empty());
+    }
+
+    #[test]
+    fn it_gets_columns() {
+        let file_path = get_test_file_path();
+        let columns = CedaCsvReader::columns(file_path).unwrap();
+
+        assert_eq!(columns[0], "ob_time");
+        assert!(columns.contains(&"wind_speed".to_string()));
+    }
+
+    #[test]
+    fn it_preserves_quoted_fields() {
+        let lines: Vec<String> = [
+            "data",
+            "ob_time,id,remark,wind_speed_unit_id,src_opr_type,wind_direction,wind_speed",
+            "1994-10-01 00:00:00,3915,\"gusty, variable\",4,1,170,4",
+            "end data",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+        let observations =
+            CedaCsvReader::parse_observations(&lines, ValidationPolicy::Keep, false).unwrap();
+
+        assert_eq!(observations[0].wind.direction, Some(170.0));
+        assert_eq!(observations[0].wind.speed, Some(4.0));
+    }
+
+    #[test]
+    fn it_reports_unparseable_fields() {
+        let lines: Vec<String> = [
+            "ob_time,id,wind_speed_unit_id,src_opr_type,wind_direction,wind_speed",
+            "1994-10-01 00:00:00,NA,4,1,170,4",
+            "end data",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+        let result = CedaCsvReader::parse_observations(&lines, ValidationPolicy::Keep, false);
+
+        assert!(matches!(
+            result,
+            Err(Error::CsvFieldParseError { column, value }) if column == "id" && value == "NA"
+        ));
+    }
+
+    #[test]
+    fn it_clamps_invalid_wind() {
+        let record = StringRecord::from(vec!["-1", "400", "", ""]);
+        let wind =
+            CedaCsvReader::parse_wind(&WIND_COLUMNS, &record, ValidationPolicy::Clamp).unwrap();
+
+        assert_eq!(wind.speed, None);
+        assert_eq!(wind.direction, None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_wind() {
+        let record = StringRecord::from(vec!["4", "-10", "", ""]);
+        let result = CedaCsvReader::parse_wind(&WIND_COLUMNS, &record, ValidationPolicy::Reject);
+
+        assert!(matches!(result, Err(Error::CsvInvalidValueError(..))));
+    }
+
+    #[test]
+    fn it_keeps_invalid_wind() {
+        let record = StringRecord::from(vec!["-1", "400", "", ""]);
+        let wind =
+            CedaCsvReader::parse_wind(&WIND_COLUMNS, &record, ValidationPolicy::Keep).unwrap();
+
+        assert_eq!(wind.speed, Some(-1.0));
+        assert_eq!(wind.direction, Some(400.0));
+    }
+
+    #[test]
+    fn it_treats_missing_values_as_none() {
+        for value in ["", "NA", "na", " "] {
+            let record = StringRecord::from(vec![value, value, value, value]);
+            let wind = CedaCsvReader::parse_wind(&WIND_COLUMNS, &record, ValidationPolicy::Reject)
+                .unwrap();
+
+            assert_eq!(wind, WindObservation::default());
+        }
+    }
+
+    #[test]
+    fn it_rejects_unparseable_values() {
+        let record = StringRecord::from(vec!["4.o", "170", "", ""]);
+
+        let clamped =
+            CedaCsvReader::parse_wind(&WIND_COLUMNS, &record, ValidationPolicy::Clamp).unwrap();
+        let rejected = CedaCsvReader::parse_wind(&WIND_COLUMNS, &record, ValidationPolicy::Reject);
+
+        assert_eq!(clamped.speed, None);
+        assert!(matches!(
+            rejected,
+            Err(Error::CsvFieldParseError { column, value }) if column == "wind_speed" && value == "4.o"
+        ));
+    }
+
+    #[test]
+    fn it_serializes_observations() {
+        let observation = Observation {
+            date_time: NaiveDateTime::parse_from_str("1994-10-01 12:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            _id: 7,
+            wind: WindObservation {
+                speed: Some(4.0),
+                direction: Some(180.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let json = serde_json::to_value(&observation).unwrap();
+        assert_eq!(json["id"], 7);
+        assert_eq!(json["date_time"], "1994-10-01T12:00:00");
+        assert_eq!(json["wind"]["speed"], 4.0);
+
+        let parsed: Observation = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.date_time, observation.date_time);
+        assert_eq!(parsed.wind, observation.wind);
+    }
+
+    #[test]
+    fn it_parses_known_date_formats() {
+        let expected =
+            NaiveDateTime::parse_from_str("1994-10-01 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        for value in [
+            "1994-10-01 12:30:00",
+            "1994-10-01 12:30:00.000",
+            "1994-10-01T12:30:00",
+            "1994-10-01 12:30",
+            "01/10/1994 12:30:00",
+        ] {
+            assert_eq!(parse_date_time(value).unwrap(), expected, "{}", value);
+        }
+
+        assert!(matches!(
+            parse_date_time("October 1st"),
+            Err(Error::CsvDateParseError(_))
+        ));
+    }
+
+    #[test]
+    fn it_finds_empty_observations() {
+        let empty = Observation {
+            wind: WindObservation {
+                unit_id: Some(4),
+                opr_type: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(empty.wind.is_empty());
+        assert!(!empty.has_any_data());
+
+        let gust_only = Observation {
+            wind: WindObservation {
+                gust_speed: Some(12.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(!gust_only.wind.is_empty());
+        assert!(gust_only.has_any_data());
+    }
+
+    #[test]
+    fn it_displays_observations() {
+        let observation = Observation {
+            date_time: NaiveDateTime::parse_from_str("1994-10-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            wind: WindObservation {
+                speed: Some(4.0),
+                direction: Some(170.0),
+                gust_speed: Some(9.0),
+                gust_time: NaiveTime::from_hms_opt(12, 30, 0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            observation.to_string(),
+            "1994-10-01 00:00 wind=4.0 dir=170 gust=9.0 gust_time=12:30"
+        );
+        assert_eq!(WindObservation::default().to_string(), "no wind");
+    }
+
+    #[test]
+    fn it_decodes_opr_type() {
+        assert_eq!(OprType::from(1), OprType::Manned);
+        assert_eq!(OprType::from(7), OprType::AutomaticWmoWeather);
+        assert_eq!(OprType::from(9), OprType::Unknown(9));
+        assert_eq!(OprType::from(4).code(), 4);
+        assert_eq!(OprType::from(9).to_string(), "unknown (9)");
+    }
+
+    #[test]
+    fn it_parses_gusts() {
+        let columns = WindColumns {
+            gust_speed: Some(4),
+            gust_direction: Some(5),
+            gust_time: Some(6),
+            ..WIND_COLUMNS
+        };
+        let record = StringRecord::from(vec!["4", "170", "", "", "12", "180", "945"]);
+        let wind = CedaCsvReader::parse_wind(&columns, &record, ValidationPolicy::Clamp).unwrap();
+
+        assert_eq!(wind.gust_speed, Some(12.0));
+        assert_eq!(wind.gust_direction, Some(180.0));
+        assert_eq!(wind.gust_time, NaiveTime::from_hms_opt(9, 45, 0));
+    }
+
+    #[test]
+    fn it_leaves_missing_gusts_empty() {
+        let file_path = get_test_file_path();
+        let reader = CedaCsvReader::new(file_path).unwrap();
+        let wind = &reader.observations[0].wind;
+
+        assert_eq!(wind.gust_speed, None);
+        assert_eq!(wind.gust_direction, None);
+        assert_eq!(wind.gust_time, None);
+    }
+
+    /// Wind columns of a record without gusts
+    const WIND_COLUMNS: WindColumns = WindColumns {
+        speed: 0,
+        direction: 1,
+        unit_id: 2,
+        opr_type: 3,
+        gust_speed: None,
+        gust_direction: None,
+        gust_time: None,
+    };
+
     fn get_test_file_path() -> PathBuf {
         PathBuf::from("/Users/richardlyon/Documents/CEDA/raw/data/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv")
     }
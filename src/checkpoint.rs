@@ -0,0 +1,144 @@
+//! Crawl checkpoints
+//!
+//! Saves the links an update discovers to a JSON file in the datastore as each stage of the
+//! crawl completes, so an interrupted crawl resumes from its last completed stage instead of
+//! fetching every page again.
+
+use crate::ceda_client::{CountyLink, DataFileLink, DataFolderLink, StationLink};
+use crate::error::AppError as Error;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// What a crawl covers, so a checkpoint is only resumed by the same crawl
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrawlScope {
+    pub stations_file: Option<PathBuf>,
+    pub resume_from: Option<String>,
+    /// County the crawl is limited to
+    pub county: Option<String>,
+}
+
+/// The links discovered by the completed stages of a crawl
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(skip)]
+    path: PathBuf,
+    scope: CrawlScope,
+    pub county_links: Option<Vec<CountyLink>>,
+    pub station_links: Option<Vec<StationLink>>,
+    pub data_folder_links: Option<Vec<DataFolderLink>>,
+    pub data_file_links: Option<Vec<DataFileLink>>,
+    /// Stations and data folders that failed in the completed stages
+    pub failed: usize,
+    /// Counties whose station links could not be fetched
+    #[serde(default)]
+    pub failed_counties: usize,
+}
+
+impl Checkpoint {
+    /// Start a checkpoint of a crawl, without any completed stages
+    pub fn new(path: PathBuf, scope: CrawlScope) -> Self {
+        Self {
+            path,
+            scope,
+            ..Default::default()
+        }
+    }
+
+    /// Load the checkpoint saved at a path, or start a new one if there is none or it is of a
+    /// different crawl
+    pub fn load(path: PathBuf, scope: CrawlScope) -> Self {
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Self::new(path, scope);
+        };
+
+        match serde_json::from_str::<Checkpoint>(&contents) {
+            Ok(checkpoint) if checkpoint.scope == scope => Self { path, ..checkpoint },
+            Ok(_) => {
+                println!(
+                    "Ignoring checkpoint {} of a different crawl",
+                    path.display()
+                );
+                Self::new(path, scope)
+            }
+            Err(e) => {
+                println!("Ignoring unreadable checkpoint {}: {}", path.display(), e);
+                Self::new(path, scope)
+            }
+        }
+    }
+
+    /// The last completed stage, if any
+    pub fn last_stage(&self) -> Option<&'static str> {
+        if self.data_file_links.is_some() {
+            Some("data file links")
+        } else if self.data_folder_links.is_some() {
+            Some("data folder links")
+        } else if self.station_links.is_some() {
+            Some("station links")
+        } else if self.county_links.is_some() {
+            Some("county links")
+        } else {
+            None
+        }
+    }
+
+    /// Save the checkpoint. The file is replaced in one step, so a crash never leaves it half
+    /// written.
+    pub fn save(&self) -> Result<(), Error> {
+        let json = serde_json::to_string(self).map_err(|e| Error::FileWriteError(e.to_string()))?;
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::FileWriteError(e.to_string()))?;
+        }
+        std::fs::write(&tmp_path, json)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path))
+            .map_err(|e| Error::FileWriteError(e.to_string()))
+    }
+
+    /// Delete the saved checkpoint once the crawl is complete
+    pub fn remove(&self) -> Result<(), Error> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::FileWriteError(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope() -> CrawlScope {
+        CrawlScope {
+            stations_file: None,
+            resume_from: Some("antrim".to_string()),
+            county: None,
+        }
+    }
+
+    #[test]
+    fn it_resumes_saved_stages() {
+        let path = std::env::temp_dir().join("rust-ceda-checkpoint-test.json");
+        let mut checkpoint = Checkpoint::new(path.clone(), scope());
+        checkpoint.county_links = Some(vec![CountyLink("/antrim".to_string())]);
+        checkpoint.station_links = Some(vec![StationLink("/antrim/01448".to_string())]);
+        checkpoint.failed = 2;
+        checkpoint.save().unwrap();
+
+        let loaded = Checkpoint::load(path.clone(), scope());
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.last_stage(), Some("station links"));
+
+        let other = Checkpoint::load(path.clone(), CrawlScope::default());
+        assert_eq!(other.last_stage(), None);
+
+        checkpoint.remove().unwrap();
+        assert!(!path.exists());
+        assert_eq!(Checkpoint::load(path, scope()).last_stage(), None);
+    }
+}
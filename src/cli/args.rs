@@ -0,0 +1,214 @@
+//! Command line values
+//!
+//! The values of command line options, parsed by clap and converted into the types the rest of
+//! the application works with.
+
+use crate::ceda_client;
+use crate::ceda_csv_reader;
+use crate::cli::command;
+use crate::datastore;
+use crate::export;
+use crate::sink;
+use clap::{Args, ValueEnum};
+use std::ops::RangeInclusive;
+
+/// How the access token is sent to CEDA
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AuthMode {
+    /// An `Authorization: Bearer` header
+    Bearer,
+    /// An `access_token` query parameter on every URL
+    QueryParam,
+}
+
+impl From<AuthMode> for ceda_client::AuthMode {
+    fn from(auth_mode: AuthMode) -> Self {
+        match auth_mode {
+            AuthMode::Bearer => Self::Bearer,
+            AuthMode::QueryParam => Self::QueryParam,
+        }
+    }
+}
+
+/// How physically impossible observation values are handled
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ValidationPolicy {
+    /// Replace impossible values with `None`
+    Clamp,
+    /// Fail the file on impossible values
+    Reject,
+    /// Keep values as reported
+    Keep,
+}
+
+impl From<ValidationPolicy> for ceda_csv_reader::ValidationPolicy {
+    fn from(policy: ValidationPolicy) -> Self {
+        match policy {
+            ValidationPolicy::Clamp => Self::Clamp,
+            ValidationPolicy::Reject => Self::Reject,
+            ValidationPolicy::Keep => Self::Keep,
+        }
+    }
+}
+
+/// How downloaded data files are organised under the raw data directory
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Layout {
+    /// All files in `raw/data`
+    Flat,
+    /// Files in `raw/data/<county>/<station_id>`
+    Nested,
+}
+
+impl From<Layout> for datastore::Layout {
+    fn from(layout: Layout) -> Self {
+        match layout {
+            Layout::Flat => Self::Flat,
+            Layout::Nested => Self::Nested,
+        }
+    }
+}
+
+/// Where processed observations are written
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Output {
+    /// The SQLite database in the datastore
+    Db,
+    /// A single normalized CSV file
+    Csv,
+}
+
+impl From<Output> for sink::Output {
+    fn from(output: Output) -> Self {
+        match output {
+            Output::Db => Self::Db,
+            Output::Csv => Self::Csv,
+        }
+    }
+}
+
+/// File format of an export
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// A Parquet dataset partitioned by station and year
+    Parquet,
+    /// Newline-delimited JSON, one observation per line
+    Ndjson,
+}
+
+impl From<ExportFormat> for export::ExportFormat {
+    fn from(format: ExportFormat) -> Self {
+        match format {
+            ExportFormat::Parquet => Self::Parquet,
+            ExportFormat::Ndjson => Self::Ndjson,
+        }
+    }
+}
+
+/// How a process run treats the observations already in the database
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ProcessMode {
+    /// Delete all data and load from scratch
+    Init,
+    /// Add observations, keeping the newest dataset version and then the newest version of any
+    /// observation already stored
+    Append,
+    /// Reprocess files, replacing stored observations for the same station, time and id type
+    Upsert,
+}
+
+impl From<ProcessMode> for command::ProcessMode {
+    fn from(mode: ProcessMode) -> Self {
+        match mode {
+            ProcessMode::Init => Self::Init,
+            ProcessMode::Append => Self::Append,
+            ProcessMode::Upsert => Self::Upsert,
+        }
+    }
+}
+
+/// How the listed data files are ordered
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SortBy {
+    /// By county, then station and year
+    County,
+    /// By station id, then year
+    Station,
+    /// Most recent year first
+    Year,
+    /// Largest file first
+    Size,
+}
+
+impl From<SortBy> for command::SortBy {
+    fn from(sort_by: SortBy) -> Self {
+        match sort_by {
+            SortBy::County => Self::County,
+            SortBy::Station => Self::Station,
+            SortBy::Year => Self::Year,
+            SortBy::Size => Self::Size,
+        }
+    }
+}
+
+/// Which data files to work on, by the properties in their filenames. Every filter given must
+/// match.
+#[derive(Debug, Default, Clone, PartialEq, Args)]
+pub struct FileFilter {
+    #[arg(long)]
+    /// Only data files of this county e.g. `antrim`
+    pub county: Option<String>,
+    #[arg(long)]
+    /// Only data files of this MIDAS station id
+    pub station: Option<u32>,
+    #[arg(long, value_parser = parse_years)]
+    /// Only data files of this year or range of years e.g. `1994` or `1990-1999`
+    pub year: Option<RangeInclusive<u32>>,
+    #[arg(long)]
+    /// Only data files of this quality control version e.g. `1` or `qcv-1`
+    pub qcv: Option<String>,
+}
+
+impl From<FileFilter> for datastore::FileFilter {
+    fn from(filter: FileFilter) -> Self {
+        Self {
+            county: filter.county,
+            station: filter.station,
+            year: filter.year,
+            qcv: filter.qcv,
+        }
+    }
+}
+
+/// Parse a year e.g. `1994`, or an inclusive range of years e.g. `1990-1999`
+fn parse_years(value: &str) -> Result<RangeInclusive<u32>, String> {
+    let parse = |year: &str| {
+        year.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid year: {}", year))
+    };
+
+    match value.split_once('-') {
+        Some((from, to)) => {
+            let (from, to) = (parse(from)?, parse(to)?);
+            if from > to {
+                return Err(format!("{} is after {}", from, to));
+            }
+            Ok(from..=to)
+        }
+        None => parse(value).map(|year| year..=year),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_years() {
+        assert_eq!(parse_years("1994"), Ok(1994..=1994));
+        assert_eq!(parse_years("1990-1999"), Ok(1990..=1999));
+        assert!(parse_years("1999-1990").is_err());
+        assert!(parse_years("ninety").is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! Archive database command
+//!
+//! Compresses a snapshot of the SQLITE database with zstd for long-term storage, leaving the
+//! live database as it is.
+
+use crate::db::Database;
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use std::path::PathBuf;
+
+pub async fn archive(settings: &Settings, out: Option<PathBuf>, level: i32) -> Result<(), Error> {
+    let db = Database::open_readonly_at(&settings.db_path()).await?;
+    let dir = match out {
+        Some(out) => out,
+        None => db
+            .path()
+            .parent()
+            .map(|parent| parent.to_path_buf())
+            .unwrap_or_default(),
+    };
+
+    let archive_path = db.archive(&dir, level).await?;
+    let size = std::fs::metadata(&archive_path)
+        .map_err(|e| Error::ArchiveError(e.to_string()))?
+        .len();
+
+    println!(
+        "Archived {} to {} ({} bytes)",
+        db.path().display(),
+        archive_path.display(),
+        size
+    );
+
+    Ok(())
+}
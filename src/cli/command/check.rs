@@ -0,0 +1,136 @@
+//! Health check command
+//!
+//! Verifies the datastore and database are present and consistent.
+
+use crate::datastore::DataStore;
+use crate::db::{self, Database};
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use std::path::Path;
+
+/// Fraction by which the raw file count may differ from the processed file count
+const FILE_COUNT_TOLERANCE: f64 = 0.01;
+
+pub async fn check(settings: &Settings) -> Result<(), Error> {
+    let mut failures = 0;
+
+    let Some(data_dir) = settings.data_dir() else {
+        report("DATA_DIR", Err("DATA_DIR is not set".to_string()));
+        return Err(Error::CheckFailed(1));
+    };
+
+    if !report("DATA_DIR", check_data_dir(&data_dir)) {
+        return Err(Error::CheckFailed(1));
+    }
+
+    let datastore = DataStore::with_root(data_dir);
+    let db = match Database::open_readonly_at(&settings.db_path()).await {
+        Ok(db) => {
+            report("Database", Ok("opened".to_string()));
+            db
+        }
+        Err(e) => {
+            report("Database", Err(e.to_string()));
+            return Err(Error::CheckFailed(1));
+        }
+    };
+
+    if !report("Tables", check_schema(&db, "table", &db::TABLES).await) {
+        failures += 1;
+    }
+    if !report("Indexes", check_schema(&db, "index", &db::INDEXES).await) {
+        failures += 1;
+    }
+    if !report("Schema version", check_schema_version(&db).await) {
+        failures += 1;
+    }
+    if !report("Processed files", check_file_count(&datastore, &db).await) {
+        failures += 1;
+    }
+
+    if failures > 0 {
+        return Err(Error::CheckFailed(failures));
+    }
+
+    Ok(())
+}
+
+/// Print the outcome of a check, returning whether it passed
+fn report(check: &str, result: Result<String, String>) -> bool {
+    match result {
+        Ok(message) => {
+            println!("[ OK ] {}: {}", check, message);
+            true
+        }
+        Err(message) => {
+            println!("[FAIL] {}: {}", check, message);
+            false
+        }
+    }
+}
+
+fn check_data_dir(data_dir: &Path) -> Result<String, String> {
+    if !data_dir.is_dir() {
+        return Err(format!("{} does not exist", data_dir.display()));
+    }
+
+    let probe = data_dir.join(".rust-ceda-check");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| format!("{} is not writable: {}", data_dir.display(), e))?;
+
+    Ok(format!("{} is writable", data_dir.display()))
+}
+
+async fn check_schema(
+    db: &Database,
+    object_type: &str,
+    expected: &[&str],
+) -> Result<String, String> {
+    let names = db
+        .schema_names(object_type)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let missing: Vec<&str> = expected
+        .iter()
+        .copied()
+        .filter(|name| !names.iter().any(|n| n == name))
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(format!(
+            "missing {}; run `process --init`",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(format!("{} present", expected.join(", ")))
+}
+
+async fn check_schema_version(db: &Database) -> Result<String, String> {
+    let version = db.schema_version().await.map_err(|e| e.to_string())?;
+
+    if version < db::SCHEMA_VERSION {
+        return Err(format!(
+            "version {} is older than {}; run `process` to migrate it",
+            version,
+            db::SCHEMA_VERSION
+        ));
+    }
+
+    Ok(format!("version {}", version))
+}
+
+async fn check_file_count(datastore: &DataStore, db: &Database) -> Result<String, String> {
+    let raw_count = datastore.list_data_files().len();
+    let processed_count = db.processed_file_count().await.map_err(|e| e.to_string())? as usize;
+
+    let message = format!("{} raw files, {} processed", raw_count, processed_count);
+    let difference = raw_count.abs_diff(processed_count) as f64;
+    if difference > raw_count as f64 * FILE_COUNT_TOLERANCE {
+        return Err(message);
+    }
+
+    Ok(message)
+}
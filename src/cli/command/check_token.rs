@@ -0,0 +1,15 @@
+//! Check token command
+//!
+//! Makes a single authenticated request to CEDA to confirm the access token is accepted.
+
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+
+pub async fn check_token(settings: &Settings) -> Result<(), Error> {
+    let client = settings.client()?;
+
+    let url = client.check_access_token().await?;
+    println!("Access token accepted by {}", url);
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! List counties command
+//!
+//! Prints the county names available in the CEDA dataset, without downloading anything.
+
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+
+pub async fn counties(settings: &Settings) -> Result<(), Error> {
+    let client = settings.client()?;
+
+    let county_links = client.get_county_links().await?;
+    let mut county_names: Vec<&str> = county_links
+        .iter()
+        .map(|county_link| county_link.county_name())
+        .collect();
+    county_names.sort_unstable();
+    county_names.dedup();
+
+    for county_name in &county_names {
+        println!("{}", county_name);
+    }
+    println!("{} counties", county_names.len());
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Download file command
+//!
+//! Downloads a single data file from CEDA by URL, without crawling, e.g. to test the access token
+//! and network against one known file.
+
+use crate::ceda_client::{csv_filename, CedaClient, Download};
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use std::path::Path;
+
+pub async fn download(settings: &Settings, url: &str, out: &Path) -> Result<(), Error> {
+    let client = settings.client()?;
+
+    download_with(&client, url, out).await
+}
+
+/// Download a file by URL, absolute or relative to the CEDA archive, with the given client
+async fn download_with(client: &CedaClient, url: &str, out: &Path) -> Result<(), Error> {
+    let filename = csv_filename(url)?;
+
+    std::fs::create_dir_all(out).map_err(|e| Error::FileWriteError(e.to_string()))?;
+    match client.download_csv(url, out).await? {
+        Download::Downloaded(bytes) => {
+            println!(
+                "Downloaded {} ({} bytes) to {}",
+                filename,
+                bytes,
+                out.display()
+            )
+        }
+        Download::Existing => println!("{} is already in {}", filename, out.display()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_downloads_relative_urls() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        let request_line = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let len = socket.read(&mut request).await.unwrap();
+            let body = "ob_time,wind_speed\n";
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..len])
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        });
+        let mut client = CedaClient::with_access_token("202407", "token").unwrap();
+        client.root = root;
+        let out = std::env::temp_dir().join("rust-ceda-download-relative");
+        let _ = std::fs::remove_dir_all(&out);
+
+        download_with(&client, "/badc/antrim/qcv-1/1994.csv", &out)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            request_line.await.unwrap(),
+            "GET /badc/antrim/qcv-1/1994.csv HTTP/1.1"
+        );
+        assert!(out.join("1994.csv").exists());
+
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+}
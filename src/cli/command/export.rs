@@ -0,0 +1,52 @@
+//! Export observations command
+//!
+//! Writes the observations in the SQLITE database to a Parquet dataset, or as newline-delimited
+//! JSON to a file or stdout.
+
+use crate::db::Database;
+use crate::error::AppError as Error;
+use crate::export::{Columns, ExportFormat};
+use crate::settings::Settings;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+pub async fn export(
+    settings: &Settings,
+    out: Option<PathBuf>,
+    format: ExportFormat,
+    columns: &[String],
+) -> Result<(), Error> {
+    let columns = if columns.is_empty() {
+        Columns::default()
+    } else {
+        Columns::select(columns)?
+    };
+    let db = Database::open_readonly_at(&settings.db_path()).await?;
+
+    match format {
+        ExportFormat::Parquet => {
+            let out = out.ok_or_else(|| {
+                Error::ExportError("--out is required for Parquet exports".to_string())
+            })?;
+            let partitions = db.export_parquet(&out, &columns).await?;
+            println!("Exported {} partitions to {}", partitions, out.display());
+        }
+        ExportFormat::Ndjson => match out {
+            Some(out) => {
+                let file = File::create(&out).map_err(|e| Error::ExportError(e.to_string()))?;
+                let count = db
+                    .export_ndjson(&mut BufWriter::new(file), &columns)
+                    .await?;
+                println!("Exported {} observations to {}", count, out.display());
+            }
+            // Stdout is line buffered, so each observation is flushed as it is written
+            None => {
+                db.export_ndjson(&mut std::io::stdout().lock(), &columns)
+                    .await?;
+            }
+        },
+    }
+
+    Ok(())
+}
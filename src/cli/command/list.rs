@@ -0,0 +1,148 @@
+//! List datafiles command
+//!
+//! Lists the CSV data files held in the datastore.
+
+use crate::datastore::{DataStore, FileProperties};
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use std::cmp::Reverse;
+
+/// How the listed data files are ordered
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    /// By county, then station and year
+    #[default]
+    County,
+    /// By station id, then year
+    Station,
+    /// Most recent year first
+    Year,
+    /// Largest file first
+    Size,
+}
+
+pub async fn list(settings: &Settings, gaps: bool, sort_by: SortBy) -> Result<(), Error> {
+    let datastore = settings.datastore();
+
+    if gaps {
+        list_gaps(&datastore);
+        return Ok(());
+    }
+
+    let data_files = sort_data_files(datastore.list_data_files(), sort_by);
+    for (data_file, size) in &data_files {
+        print!(
+            "{} {:05} {} {}",
+            data_file.county_name, data_file.station_id, data_file.station_name, data_file.year
+        );
+        match size {
+            Some(size) => println!(" {}", size),
+            None => println!(),
+        }
+    }
+    println!("{} data files", data_files.len());
+
+    Ok(())
+}
+
+/// Sort data files listed in county order, pairing each with its size in bytes when sorting by
+/// size
+fn sort_data_files(
+    data_files: Vec<FileProperties>,
+    sort_by: SortBy,
+) -> Vec<(FileProperties, Option<u64>)> {
+    let mut data_files: Vec<(FileProperties, Option<u64>)> = data_files
+        .into_iter()
+        .map(|data_file| {
+            let size = (sort_by == SortBy::Size).then(|| {
+                std::fs::metadata(&data_file.path)
+                    .map(|metadata| metadata.len())
+                    .unwrap_or_default()
+            });
+            (data_file, size)
+        })
+        .collect();
+
+    // The sorts are stable, so ties keep the county order
+    match sort_by {
+        SortBy::County => {}
+        SortBy::Station => data_files.sort_by_key(|(data_file, _)| data_file.station_id),
+        SortBy::Year => data_files.sort_by_key(|(data_file, _)| Reverse(data_file.year)),
+        SortBy::Size => data_files.sort_by_key(|(_, size)| Reverse(*size)),
+    }
+
+    data_files
+}
+
+/// Print the stations that have missing years between their first and last year
+fn list_gaps(datastore: &DataStore) {
+    let coverage = datastore.coverage();
+    let mut stations_with_gaps = 0;
+
+    for (station_id, station_coverage) in &coverage {
+        let missing_years = station_coverage.missing_years();
+        if missing_years.is_empty() {
+            continue;
+        }
+
+        let missing_years: Vec<String> = missing_years.iter().map(|y| y.to_string()).collect();
+        println!(
+            "{:05} ({}-{}): missing {}",
+            station_id,
+            station_coverage.min_year().unwrap_or_default(),
+            station_coverage.max_year().unwrap_or_default(),
+            missing_years.join(", ")
+        );
+        stations_with_gaps += 1;
+    }
+
+    println!(
+        "{} of {} stations have gaps",
+        stations_with_gaps,
+        coverage.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn data_file(county_name: &str, station_id: &str, year: u32) -> FileProperties {
+        let filename = format!(
+            "midas-open_uk-hourly-weather-obs_dv-202407_{}_{}_station_qcv-1_{}.csv",
+            county_name, station_id, year
+        );
+        FileProperties::new(PathBuf::from(filename)).unwrap()
+    }
+
+    #[test]
+    fn it_sorts_data_files() {
+        let data_files = || {
+            vec![
+                data_file("aberdeenshire", "00144", 1997),
+                data_file("antrim", "00099", 1994),
+                data_file("antrim", "00099", 1995),
+            ]
+        };
+        let order = |sorted: Vec<(FileProperties, Option<u64>)>| -> Vec<(u32, u32)> {
+            sorted
+                .iter()
+                .map(|(data_file, _)| (data_file.station_id, data_file.year))
+                .collect()
+        };
+
+        assert_eq!(
+            order(sort_data_files(data_files(), SortBy::County)),
+            vec![(144, 1997), (99, 1994), (99, 1995)]
+        );
+        assert_eq!(
+            order(sort_data_files(data_files(), SortBy::Station)),
+            vec![(99, 1994), (99, 1995), (144, 1997)]
+        );
+        assert_eq!(
+            order(sort_data_files(data_files(), SortBy::Year)),
+            vec![(144, 1997), (99, 1995), (99, 1994)]
+        );
+    }
+}
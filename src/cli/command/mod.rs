@@ -1,5 +1,27 @@
+mod archive;
+mod check;
+mod check_token;
+mod counties;
+mod download;
+mod export;
+mod list;
+mod nearest;
+mod optimize;
 mod process;
+mod prune;
+mod summary;
 mod update;
 
-pub use process::process;
-pub use update::update;
+pub use archive::archive;
+pub use check::check;
+pub use check_token::check_token;
+pub use counties::counties;
+pub use download::download;
+pub use export::export;
+pub use list::{list, SortBy};
+pub use nearest::nearest;
+pub use optimize::optimize;
+pub use process::{process, ProcessMode, ProcessOptions};
+pub use prune::prune;
+pub use summary::summary;
+pub use update::{update, UpdateOptions, UpdateReport};
@@ -0,0 +1,33 @@
+//! Nearest stations command
+//!
+//! Lists the stored stations closest to a coordinate, as CSV or with `--pretty` as a table.
+
+use crate::db::Database;
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use crate::table::Table;
+
+pub async fn nearest(
+    settings: &Settings,
+    lat: f64,
+    lon: f64,
+    count: usize,
+    pretty: bool,
+) -> Result<(), Error> {
+    let db = Database::open_readonly_at(&settings.db_path()).await?;
+
+    let stations = db.nearest_stations(lat, lon, count).await?;
+
+    let mut table = Table::new(&["id", "station", "county", "km"]);
+    for (station, distance) in stations {
+        table.add_row(vec![
+            format!("{:05}", station.midas_station_id),
+            station.observation_station,
+            station.historic_county_name,
+            format!("{:.1}", distance),
+        ]);
+    }
+    print!("{}", table.render(pretty)?);
+
+    Ok(())
+}
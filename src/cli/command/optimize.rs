@@ -0,0 +1,31 @@
+//! Optimize database command
+//!
+//! Vacuums and analyzes the SQLITE database.
+
+use crate::db::Database;
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use std::path::Path;
+
+pub async fn optimize(settings: &Settings) -> Result<(), Error> {
+    let db = Database::new_at(&settings.db_path(), settings.db_pool_size).await?;
+
+    let size_before = file_size(db.path())?;
+    db.optimize().await?;
+    let size_after = file_size(db.path())?;
+
+    println!(
+        "Optimized {}: {} -> {} bytes",
+        db.path().display(),
+        size_before,
+        size_after
+    );
+
+    Ok(())
+}
+
+fn file_size(path: &Path) -> Result<u64, Error> {
+    let metadata = std::fs::metadata(path).map_err(|_| Error::FileNotFound)?;
+
+    Ok(metadata.len())
+}
@@ -1,47 +1,899 @@
 //! Process datafiles command
 //!
-//! Loads the CSV data in the datastore to a SQLITE database.
+//! Loads the CSV data in the datastore to a SQLITE database, or to a single normalized CSV
+//! file with `--output csv`.
+//!
+//! `--mode` chooses how stored observations are treated: `init` deletes them first, `append`
+//! (the default) adds to them, keeping an observation from the newest dataset version and the
+//! newest version within it, and `upsert` reprocesses every file, replacing observations stored
+//! for the same station, time and id type. Loading a later dataset version on top of an earlier
+//! one in `append` mode layers its corrections over the old observations.
+//!
+//! With `--incremental`, files are reparsed and only observations newer than the latest stored
+//! for their station before the run are appended.
+//!
+//! Stations are loaded first from the metadata headers alone, then the observations.
+//! `--skip-empty` leaves out observations without any measured value.
+//!
+//! With `--delete-after-process`, each data file is deleted once its observations are committed,
+//! so the datastore and database needn't be held on disk together. Files that fail are kept.
+//!
+//! Observations are streamed from each file into the sink as they are parsed, so no file is held
+//! in memory whole. Each file is committed in one transaction unless `--commit-every` bounds the
+//! transaction size for very large files. A failure part way through such a file leaves the
+//! observations before the last commit in the database, and the file unprocessed: rerun with
+//! `--incremental` to complete it.
+//!
+//! `--input-dir` processes a directory of data files from elsewhere, in any layout.
+//!
+//! `--county`, `--station`, `--year` and `--qcv` process only the listed data files that match,
+//! the same way `update` filters the files it downloads. Files given with `--file` are processed
+//! as they are.
+//!
+//! `--split-by-county` writes each county to its own database, `weather_<county>.sqlite`, next to
+//! the database file.
+//!
+//! `--validate-only` parses every file, or the files given with `--file`, and reports parse errors,
+//! observation counts and column coverage, without opening the database.
+//!
+//! After a parser change, `--rebuild` reloads the database from the raw files already in the
+//! datastore without touching the network.
 
-use crate::ceda_csv_reader::CedaCsvReader;
-use crate::datastore;
-use crate::db::Database;
+use crate::ceda_csv_reader::{CedaCsvReader, Observation, ValidationPolicy};
+use crate::datastore::{DataStore, FileFilter, FileProperties};
+use crate::db::{self, Database};
 use crate::error::AppError as Error;
+use crate::outcome::Outcome;
+use crate::settings::Settings;
+use crate::sink::{CsvSink, ObservationSink, Output};
+use chrono::NaiveDateTime;
+use futures::future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
-pub async fn process(init: bool) -> Result<(), Error> {
-    let datastore = datastore::DataStore::new();
-    let db = Database::new().await.unwrap();
+/// Attempts made to insert a file before giving up on a transient database error
+const MAX_ATTEMPTS: u32 = 3;
+/// Base delay between attempts, multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Observations parsed from a file and sent to the writer at a time
+const OBSERVATION_CHUNK_SIZE: usize = 1000;
+/// Chunks of a file's observations parsed ahead of the writer
+const OBSERVATION_CHUNKS: usize = 2;
 
-    if init {
-        db.init().await?;
-    }
+/// How a process run treats the observations already in the database
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ProcessMode {
+    /// Delete all data and load from scratch
+    Init,
+    /// Add observations, keeping the newest dataset version and then the newest version of any
+    /// observation already stored
+    #[default]
+    Append,
+    /// Reprocess files, replacing stored observations for the same station, time and id type
+    Upsert,
+}
 
-    let data_files = datastore.list_data_files();
+/// Options for a process run
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    pub mode: ProcessMode,
+    /// Initialise the database and reparse every file in the datastore
+    pub rebuild: bool,
+    /// Skip the confirmation prompt before deleting data
+    pub yes: bool,
+    pub validation: ValidationPolicy,
+    /// Process only these files instead of the datastore
+    pub files: Vec<PathBuf>,
+    /// Process the data files anywhere under this directory instead of the datastore
+    pub input_dir: Option<PathBuf>,
+    /// Maximum number of files parsed at once
+    pub parse_concurrency: usize,
+    /// Maximum number of parsed files waiting to be written
+    pub channel_size: usize,
+    /// Reprocess files, writing only observations newer than those already stored
+    pub incremental: bool,
+    /// Store the columns that are not modeled as JSON alongside each observation
+    pub capture_extra: bool,
+    /// Leave out observations without any measured value
+    pub skip_empty: bool,
+    /// Write at most this many observations for each station, to sample a small database
+    pub limit_per_station: Option<usize>,
+    /// Delete each data file once all of its observations are written
+    pub delete_after_process: bool,
+    /// Commit every this many observations within a file, instead of once per file
+    pub commit_every: Option<NonZeroUsize>,
+    /// Write each county to its own database next to the database file
+    pub split_by_county: bool,
+    /// Parse the files and report on them without writing anything
+    pub validate_only: bool,
+    pub output: Output,
+    /// File to write CSV output to, or stdout
+    pub out: Option<PathBuf>,
+    /// Process only the listed data files that match
+    pub filter: FileFilter,
+}
 
-    for data_file in data_files.into_iter().take(5) {
-        let record = CedaCsvReader::new(data_file.path)?;
+/// Process the given files, or the files in the datastore when none are given.
+pub async fn process(settings: &Settings, options: ProcessOptions) -> Result<Outcome, Error> {
+    let datastore = settings.datastore();
 
-        db.insert_station(
-            record.midas_station_id,
-            &record.historic_county_name,
-            &record.observation_station,
-            record.location.lat,
-            record.location.lon,
-            record.height,
+    let data_file_paths: Vec<PathBuf> = if let Some(input_dir) = &options.input_dir {
+        if !input_dir.is_dir() {
+            return Err(Error::FileNotFound);
+        }
+        matching_paths(
+            DataStore::list_data_files_in(input_dir),
+            &options.filter,
+            usize::MAX,
         )
-        .await?;
-
-        for observation in record.observations {
-            db.insert_observation(
-                record.midas_station_id,
-                observation.date_time,
-                observation.wind.speed,
-                observation.wind.direction,
-                observation.wind.unit_id,
-                observation.wind.opr_type,
+    } else if !options.files.is_empty() {
+        options.files.clone()
+    } else if options.rebuild || options.validate_only {
+        matching_paths(datastore.list_data_files(), &options.filter, usize::MAX)
+    } else {
+        matching_paths(datastore.list_data_files(), &options.filter, 5)
+    };
+
+    if options.validate_only {
+        let validation = validate_files(data_file_paths, &options).await;
+        println!("{}", validation);
+        return Ok(Outcome::from_counts(validation.failed, validation.total));
+    }
+
+    match options.output {
+        Output::Db => {
+            let db_path = settings.db_path();
+            let initialize = options.mode == ProcessMode::Init || options.rebuild;
+            if initialize {
+                let target = if options.split_by_county {
+                    format!(
+                        "the county databases in {}",
+                        county_db_path(&db_path, "*").display()
+                    )
+                } else {
+                    db_path.display().to_string()
+                };
+                let prompt = format!(
+                    "This will delete all data in {} and cannot be undone. Continue?",
+                    target
+                );
+                if !options.yes && !confirm(&prompt) {
+                    println!("Aborted");
+                    return Ok(Outcome::Complete);
+                }
+            }
+            if options.rebuild {
+                println!(
+                    "Rebuilding the database from {} data files",
+                    data_file_paths.len()
+                );
+            }
+
+            let targets = if options.split_by_county {
+                group_by_county(data_file_paths)?
+                    .into_iter()
+                    .map(|(county_name, paths)| (county_db_path(&db_path, &county_name), paths))
+                    .collect()
+            } else {
+                vec![(db_path, data_file_paths)]
+            };
+
+            let (mut failed, mut total) = (0, 0);
+            for (db_path, data_file_paths) in targets {
+                let mut db = Database::new_at(&db_path, settings.db_pool_size).await?;
+                if options.mode == ProcessMode::Upsert {
+                    db = db.with_upsert();
+                }
+                if let Some(commit_every) = options.commit_every {
+                    db = db.with_commit_every(commit_every);
+                }
+                if initialize {
+                    db.init().await?;
+                } else if !db.is_initialized().await? {
+                    return Err(Error::DatabaseNotInitialized);
+                }
+                if options.split_by_county {
+                    println!("Writing to {}", db_path.display());
+                }
+                let (db_failed, db_total) = write_files(&mut db, data_file_paths, &options).await?;
+                failed += db_failed;
+                total += db_total;
+            }
+
+            Ok(Outcome::from_counts(failed, total))
+        }
+        Output::Csv => {
+            if options.split_by_county {
+                return Err(Error::ConfigError(
+                    "--split-by-county needs --output db".to_string(),
+                ));
+            }
+            let mut sink = CsvSink::new(options.out.as_deref())?;
+            let (failed, total) = write_files(&mut sink, data_file_paths, &options).await?;
+            Ok(Outcome::from_counts(failed, total))
+        }
+    }
+}
+
+/// Paths of up to `limit` of the data files that match the filter
+fn matching_paths(
+    data_files: Vec<FileProperties>,
+    filter: &FileFilter,
+    limit: usize,
+) -> Vec<PathBuf> {
+    data_files
+        .into_iter()
+        .filter(|data_file| data_file.matches(filter))
+        .take(limit)
+        .map(|data_file| data_file.path)
+        .collect()
+}
+
+/// The database of a county, `weather_<county>.sqlite` next to the database file
+fn county_db_path(db_path: &Path, county_name: &str) -> PathBuf {
+    db_path.with_file_name(format!("weather_{}.sqlite", county_name))
+}
+
+/// Group data files by the county in their filenames
+fn group_by_county(data_file_paths: Vec<PathBuf>) -> Result<BTreeMap<String, Vec<PathBuf>>, Error> {
+    let mut counties: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for data_file_path in data_file_paths {
+        let county_name = FileProperties::new(data_file_path.clone())?.county_name;
+        counties
+            .entry(county_name)
+            .or_default()
+            .push(data_file_path);
+    }
+
+    Ok(counties)
+}
+
+/// Ask the user a yes/no question on the terminal, defaulting to no
+fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N] ", prompt);
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Parse the files and write them to the sink, skipping files it already holds unless upserting.
+/// In incremental mode every file is reparsed, but only observations newer than the sink held
+/// before the run are written, whatever order the files are written in. Returns the number of
+/// files that failed and the number attempted.
+async fn write_files<S: ObservationSink>(
+    sink: &mut S,
+    data_file_paths: Vec<PathBuf>,
+    options: &ProcessOptions,
+) -> Result<(usize, usize), Error> {
+    let validation = options.validation;
+    let parse_concurrency = options.parse_concurrency;
+    let channel_size = options.channel_size;
+    let incremental = options.incremental;
+    let capture_extra = options.capture_extra;
+    let reprocess = incremental || options.mode == ProcessMode::Upsert;
+
+    let mut pending_file_paths: Vec<PathBuf> = Vec::new();
+    for data_file_path in data_file_paths {
+        if reprocess || !sink.is_processed(&data_file_path).await? {
+            pending_file_paths.push(data_file_path);
+        }
+    }
+
+    let pending_count = pending_file_paths.len();
+
+    // Load the stations from the metadata headers first, so the observation pass only has to
+    // load observations
+    write_stations(sink, &pending_file_paths, parse_concurrency, channel_size).await?;
+
+    // Read each station's cutoff before writing any observations, so a file written ahead of an
+    // earlier year of the same station doesn't hide that year's observations
+    let mut cutoffs: HashMap<u32, Option<NaiveDateTime>> = HashMap::new();
+    if incremental {
+        for data_file_path in &pending_file_paths {
+            if let Ok(data_file) = FileProperties::new(data_file_path.clone()) {
+                if let Entry::Vacant(entry) = cutoffs.entry(data_file.station_id) {
+                    entry.insert(sink.latest_observation(data_file.station_id).await?);
+                }
+            }
+        }
+    }
+
+    // Each file's observations start streaming as its header is read, a few chunks ahead of the
+    // writer
+    let mut parsed_files = spawn_parsers(
+        pending_file_paths,
+        move |path| {
+            let record = CedaCsvReader::read_metadata_only(path.clone())?;
+            Ok((record, stream_observations(path, validation, capture_extra)))
+        },
+        parse_concurrency,
+        channel_size,
+    );
+
+    let multi = MultiProgress::new();
+    let files_pb = multi.add(create_progress_bar(
+        pending_count as u64,
+        "Processing files...".to_string(),
+    ));
+    let mut failed_files: Vec<PathBuf> = Vec::new();
+    let mut written_per_station: HashMap<u32, usize> = HashMap::new();
+
+    while let Some((data_file_path, parsed)) = parsed_files.recv().await {
+        files_pb.inc(1);
+        let (record, observations) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let _ = multi.println(format!("Skipping {}: {}", data_file_path.display(), e));
+                failed_files.push(data_file_path);
+                continue;
+            }
+        };
+
+        let mut cutoff = None;
+        if incremental {
+            let latest = match cutoffs.entry(record.midas_station_id) {
+                Entry::Occupied(entry) => Ok(*entry.get()),
+                // A file not named for its station
+                Entry::Vacant(entry) => sink
+                    .latest_observation(record.midas_station_id)
+                    .await
+                    .map(|latest| *entry.insert(latest)),
+            };
+            match latest {
+                Ok(latest) => cutoff = latest,
+                Err(e) => {
+                    let _ = multi.println(format!("Skipping {}: {}", data_file_path.display(), e));
+                    failed_files.push(data_file_path);
+                    continue;
+                }
+            }
+        }
+
+        let limit = options.limit_per_station.map(|limit| {
+            limit.saturating_sub(
+                written_per_station
+                    .get(&record.midas_station_id)
+                    .copied()
+                    .unwrap_or_default(),
             )
-            .await?;
+        });
+
+        let file_name = data_file_path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let observations_pb = multi.add(create_counter(file_name));
+
+        let mut observations = Some(observations);
+        let mut attempt = 1;
+        loop {
+            observations_pb.reset();
+            // A retry parses the file again from the start
+            let observations = observations.take().unwrap_or_else(|| {
+                stream_observations(data_file_path.clone(), validation, capture_extra)
+            });
+            let mut out_of_range = 0;
+            let selected = select_observations(
+                observations,
+                &record,
+                Selection {
+                    cutoff,
+                    skip_empty: options.skip_empty,
+                    reject_out_of_range: validation == ValidationPolicy::Reject,
+                },
+                &mut out_of_range,
+            );
+            match sink
+                .write_record(&data_file_path, &record, selected, limit, &observations_pb)
+                .await
+            {
+                Ok(written) => {
+                    *written_per_station
+                        .entry(record.midas_station_id)
+                        .or_default() += written;
+                    if out_of_range > 0 {
+                        let _ = multi.println(format!(
+                            "Warning: {} has {} observations outside its valid dates",
+                            data_file_path.display(),
+                            out_of_range
+                        ));
+                    }
+                    if options.delete_after_process {
+                        if let Err(e) = std::fs::remove_file(&data_file_path) {
+                            let _ = multi.println(format!(
+                                "Failed to delete {}: {}",
+                                data_file_path.display(),
+                                e
+                            ));
+                        }
+                    }
+                    break;
+                }
+                Err(e) if db::is_transient(&e) && attempt < MAX_ATTEMPTS => {
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = multi.println(format!("Skipping {}: {}", data_file_path.display(), e));
+                    failed_files.push(data_file_path);
+                    break;
+                }
+            }
+        }
+        observations_pb.finish_and_clear();
+        multi.remove(&observations_pb);
+    }
+
+    files_pb.finish_with_message(format!(
+        "Processed {} files",
+        pending_count - failed_files.len()
+    ));
+
+    if !failed_files.is_empty() {
+        eprintln!("{} files failed to process:", failed_files.len());
+        for failed_file in &failed_files {
+            eprintln!("  {}", failed_file.display());
+        }
+    }
+
+    Ok((failed_files.len(), pending_count))
+}
+
+/// What validating a batch of files found
+#[derive(Debug, Default, PartialEq)]
+struct Validation {
+    failed: usize,
+    total: usize,
+    observations: usize,
+    /// The number of valid files with each column
+    columns: BTreeMap<String, usize>,
+}
+
+impl Validation {
+    /// Count a file that parsed
+    fn add_valid(&mut self, observations: usize, columns: Vec<String>) {
+        self.total += 1;
+        self.observations += observations;
+        for column in columns {
+            *self.columns.entry(column).or_default() += 1;
+        }
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let valid = self.total - self.failed;
+        write!(
+            f,
+            "{} of {} files valid, {} observations",
+            valid, self.total, self.observations
+        )?;
+        for (column, files) in &self.columns {
+            write!(f, "\n  {:<24} {}/{} files", column, files, valid)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse each file, reporting those that fail, and count the observations and columns of the rest
+async fn validate_files(data_file_paths: Vec<PathBuf>, options: &ProcessOptions) -> Validation {
+    let validation_policy = options.validation;
+    let pb = create_progress_bar(
+        data_file_paths.len() as u64,
+        "Validating files...".to_string(),
+    );
+    let mut parsed_files = spawn_parsers(
+        data_file_paths,
+        move |path| {
+            let columns = CedaCsvReader::columns(path.clone())?;
+            let record = CedaCsvReader::with_policy(path, validation_policy)?;
+            Ok((record.observation_count(), columns))
+        },
+        options.parse_concurrency,
+        options.channel_size,
+    );
+
+    let mut validation = Validation::default();
+    while let Some((data_file_path, result)) = parsed_files.recv().await {
+        pb.inc(1);
+        match result {
+            Ok((observations, columns)) => validation.add_valid(observations, columns),
+            Err(e) => {
+                pb.println(format!("Invalid {}: {}", data_file_path.display(), e));
+                validation.failed += 1;
+                validation.total += 1;
+            }
+        }
+    }
+    pb.finish_and_clear();
+
+    validation
+}
+
+/// Which of a file's observations are written
+#[derive(Debug, Clone, Copy)]
+struct Selection {
+    /// Write only observations newer than this, when incremental
+    cutoff: Option<NaiveDateTime>,
+    /// Leave out observations without any measured value
+    skip_empty: bool,
+    /// Fail the file on an observation outside its valid dates
+    reject_out_of_range: bool,
+}
+
+/// Select the observations of a file to write as they stream past, counting those outside the
+/// file's valid dates
+fn select_observations<'a>(
+    observations: impl Stream<Item = Result<Observation, Error>> + 'a,
+    record: &'a CedaCsvReader,
+    selection: Selection,
+    out_of_range: &'a mut usize,
+) -> impl Stream<Item = Result<Observation, Error>> + 'a {
+    observations
+        .map(move |observation| {
+            let observation = observation?;
+            if !record.is_in_range(&observation) {
+                if selection.reject_out_of_range {
+                    return Err(record.out_of_range_error(&observation));
+                }
+                *out_of_range += 1;
+            }
+            Ok(observation)
+        })
+        .try_filter(move |observation| {
+            future::ready(
+                selection
+                    .cutoff
+                    .is_none_or(|cutoff| observation.date_time > cutoff)
+                    && (!selection.skip_empty || observation.has_any_data()),
+            )
+        })
+}
+
+/// Stream the observations of a data file, parsed on a blocking worker that runs at most
+/// `OBSERVATION_CHUNKS` chunks ahead of the reader, so a file is never held whole
+fn stream_observations(
+    path: PathBuf,
+    validation: ValidationPolicy,
+    capture_extra: bool,
+) -> impl Stream<Item = Result<Observation, Error>> + Send {
+    let (tx, rx) = mpsc::channel(OBSERVATION_CHUNKS);
+
+    tokio::task::spawn_blocking(move || {
+        let observations = match CedaCsvReader::observations_iter(path, validation, capture_extra) {
+            Ok(observations) => observations,
+            Err(e) => {
+                let _ = tx.blocking_send(vec![Err(e)]);
+                return;
+            }
+        };
+
+        let mut chunk = Vec::with_capacity(OBSERVATION_CHUNK_SIZE);
+        for observation in observations {
+            let failed = observation.is_err();
+            chunk.push(observation);
+            if failed || chunk.len() == OBSERVATION_CHUNK_SIZE {
+                // Stop once the reader has gone, or at the first error
+                if tx.blocking_send(std::mem::take(&mut chunk)).is_err() || failed {
+                    return;
+                }
+            }
+        }
+        let _ = tx.blocking_send(chunk);
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (stream::iter(chunk), rx))
+    })
+    .flatten()
+}
+
+/// Write the station of each file once, reading only the metadata header of each file. Files with
+/// unreadable headers are skipped here and reported by the observation pass.
+async fn write_stations<S: ObservationSink>(
+    sink: &mut S,
+    data_file_paths: &[PathBuf],
+    parse_concurrency: usize,
+    channel_size: usize,
+) -> Result<(), Error> {
+    let mut headers = spawn_parsers(
+        data_file_paths.to_vec(),
+        CedaCsvReader::read_metadata_only,
+        parse_concurrency,
+        channel_size,
+    );
+
+    let mut written_stations = HashSet::new();
+    while let Some((_, header)) = headers.recv().await {
+        let Ok(header) = header else {
+            continue;
+        };
+        if written_stations.insert(header.midas_station_id) {
+            sink.write_station(&header).await?;
         }
     }
 
     Ok(())
 }
+
+/// Parse files on up to `parse_concurrency` blocking workers, sending the results over a channel
+/// holding at most `channel_size` parsed files, in the order of the paths. Parsing waits while the
+/// channel is full, so memory stays bounded however far the writer falls behind.
+fn spawn_parsers<T, F>(
+    paths: Vec<PathBuf>,
+    parse: F,
+    parse_concurrency: usize,
+    channel_size: usize,
+) -> mpsc::Receiver<(PathBuf, Result<T, Error>)>
+where
+    T: Send + 'static,
+    F: Fn(PathBuf) -> Result<T, Error> + Clone + Send + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel(channel_size);
+
+    tokio::spawn(async move {
+        let mut parsed_files = stream::iter(paths)
+            .map(|path| {
+                let parse = parse.clone();
+                async move {
+                    let parse_path = path.clone();
+                    let parsed = tokio::task::spawn_blocking(move || parse(parse_path))
+                        .await
+                        .map_err(|_| Error::GenericError)
+                        .and_then(|parsed| parsed);
+                    (path, parsed)
+                }
+            })
+            .buffered(parse_concurrency);
+
+        while let Some(parsed_file) = parsed_files.next().await {
+            if tx.send(parsed_file).await.is_err() {
+                // The writer has stopped
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// A progress bar counting up to an unknown total
+fn create_counter(message: String) -> ProgressBar {
+    ProgressBar::no_length().with_message(message).with_style(
+        ProgressStyle::with_template("[{elapsed_precise}] {pos} observations {msg}").unwrap(),
+    )
+}
+
+fn create_progress_bar(size: u64, message: String) -> ProgressBar {
+    ProgressBar::new(size).with_message(message).with_style(
+        ProgressStyle::with_template("[{eta_precise}] {bar:40.cyan/blue} {msg}")
+            .unwrap()
+            .progress_chars("##-"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::support::MidasCsv;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn it_rejects_no_parse_concurrency() {
+        use crate::cli::Cli;
+        use clap::Parser;
+
+        let args = ["rust-ceda", "process", "--parse-concurrency", "0"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn it_rejects_an_empty_channel() {
+        use crate::cli::Cli;
+        use clap::Parser;
+
+        assert!(Cli::try_parse_from(["rust-ceda", "process", "--channel-size", "0"]).is_err());
+        assert!(Cli::try_parse_from(["rust-ceda", "process", "--channel-size", "1"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_bounds_parsed_files_in_flight() {
+        let parsed = Arc::new(AtomicUsize::new(0));
+        let parse_count = parsed.clone();
+        let paths: Vec<PathBuf> = (0..100).map(|i| PathBuf::from(i.to_string())).collect();
+
+        let mut rx = spawn_parsers(
+            paths,
+            move |path| {
+                parse_count.fetch_add(1, Ordering::SeqCst);
+                Ok(path)
+            },
+            2,
+            3,
+        );
+
+        // With nothing receiving, parsing stalls once the channel and workers are full
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(parsed.load(Ordering::SeqCst) <= 3 + 2 + 1);
+
+        let mut received = 0;
+        while rx.recv().await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 100);
+    }
+
+    #[tokio::test]
+    async fn it_sends_parsed_files_in_order() {
+        let paths: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(i.to_string())).collect();
+
+        // Earlier files take longer to parse, so they finish last
+        let mut rx = spawn_parsers(
+            paths.clone(),
+            |path| {
+                let index: u64 = path.to_string_lossy().parse().unwrap();
+                std::thread::sleep(Duration::from_millis(10 * (10 - index)));
+                Ok(path)
+            },
+            4,
+            2,
+        );
+
+        let mut received = Vec::new();
+        while let Some((path, _)) = rx.recv().await {
+            received.push(path);
+        }
+        assert_eq!(received, paths);
+    }
+
+    #[tokio::test]
+    async fn it_validates_files() {
+        let dir = std::env::temp_dir().join("rust-ceda-validate");
+        let paths = vec![
+            MidasCsv::new().write(&dir),
+            MidasCsv::new()
+                .with_year(1995)
+                .with_raw_row("not a row")
+                .write(&dir),
+        ];
+        let options = ProcessOptions {
+            parse_concurrency: 2,
+            channel_size: 2,
+            ..Default::default()
+        };
+
+        let validation = validate_files(paths, &options).await;
+
+        assert_eq!(validation.failed, 1);
+        assert_eq!(validation.total, 2);
+        assert_eq!(validation.observations, 3);
+        assert_eq!(validation.columns.get("wind_speed"), Some(&1));
+        assert!(validation
+            .to_string()
+            .starts_with("1 of 2 files valid, 3 observations\n"));
+    }
+
+    #[tokio::test]
+    async fn it_validates_only_the_given_files() {
+        use crate::cli::Cli;
+        use crate::settings::FileSettings;
+        use clap::Parser;
+
+        let dir = std::env::temp_dir().join("rust-ceda-validate-given");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let bad_file = dir.join("bad.csv");
+        std::fs::write(&bad_file, "not a data file").unwrap();
+        let data_dir = dir.join("data").to_string_lossy().to_string();
+        let cli = Cli::parse_from(["rust-ceda", "--data-dir", &data_dir, "process"]);
+        let settings = Settings::merge(&cli, FileSettings::default());
+        let options = ProcessOptions {
+            validate_only: true,
+            files: vec![bad_file],
+            parse_concurrency: 1,
+            channel_size: 1,
+            ..Default::default()
+        };
+
+        let outcome = process(&settings, options).await.unwrap();
+
+        assert_eq!(outcome, Outcome::Failed { total: 1 });
+    }
+
+    #[tokio::test]
+    async fn it_writes_earlier_years_after_later_ones_incrementally() {
+        let dir = std::env::temp_dir().join("rust-ceda-incremental-order");
+        let _ = std::fs::remove_dir_all(&dir);
+        let paths = vec![
+            MidasCsv::new().with_year(1995).write(&dir),
+            MidasCsv::new().with_year(1994).write(&dir),
+        ];
+        let mut db = Database::new_at(&dir.join("weather.sqlite"), 2)
+            .await
+            .unwrap();
+        db.init().await.unwrap();
+        let options = ProcessOptions {
+            incremental: true,
+            parse_concurrency: 1,
+            channel_size: 1,
+            ..Default::default()
+        };
+
+        let (failed, total) = write_files(&mut db, paths, &options).await.unwrap();
+
+        assert_eq!((failed, total), (0, 2));
+        assert_eq!(db.observation_count_in_year(1448, 1994).await.unwrap(), 3);
+        assert_eq!(db.observation_count_in_year(1448, 1995).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_streams_observations_up_to_the_station_limit() {
+        let dir = std::env::temp_dir().join("rust-ceda-stream-limit");
+        let _ = std::fs::remove_dir_all(&dir);
+        let paths = vec![
+            MidasCsv::new().with_year(1994).with_rows(2500).write(&dir),
+            MidasCsv::new().with_year(1995).with_rows(2500).write(&dir),
+        ];
+        let mut db = Database::new_at(&dir.join("weather.sqlite"), 2)
+            .await
+            .unwrap();
+        db.init().await.unwrap();
+        let options = ProcessOptions {
+            limit_per_station: Some(3000),
+            parse_concurrency: 1,
+            channel_size: 1,
+            ..Default::default()
+        };
+
+        let (failed, total) = write_files(&mut db, paths, &options).await.unwrap();
+
+        assert_eq!((failed, total), (0, 2));
+        assert_eq!(
+            db.observation_count_in_year(1448, 1994).await.unwrap(),
+            2500
+        );
+        assert_eq!(db.observation_count_in_year(1448, 1995).await.unwrap(), 500);
+    }
+
+    #[test]
+    fn it_groups_files_by_county() {
+        let paths: Vec<PathBuf> = [
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv",
+            "midas-open_uk-hourly-weather-obs_dv-202407_devon_01234_exeter_qcv-1_1994.csv",
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1995.csv",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let counties = group_by_county(paths).unwrap();
+
+        let sizes: Vec<(&str, usize)> = counties
+            .iter()
+            .map(|(county_name, paths)| (county_name.as_str(), paths.len()))
+            .collect();
+        assert_eq!(sizes, vec![("antrim", 2), ("devon", 1)]);
+        assert_eq!(
+            county_db_path(Path::new("/data/db/weather.sqlite"), "antrim"),
+            PathBuf::from("/data/db/weather_antrim.sqlite")
+        );
+        assert!(group_by_county(vec![PathBuf::from("notes.txt")]).is_err());
+    }
+}
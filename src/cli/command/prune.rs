@@ -0,0 +1,51 @@
+//! Prune raw data files command
+//!
+//! Deletes downloaded data files that are recorded as processed and whose observations are in
+//! the database, to reclaim disk space. Any other file is kept, including one processed with
+//! observations left out by `--limit-per-station`.
+
+use crate::db::Database;
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+
+pub async fn prune(settings: &Settings, dry_run: bool) -> Result<(), Error> {
+    let datastore = settings.datastore();
+    let db = Database::open_readonly_at(&settings.db_path()).await?;
+
+    let mut pruned = 0;
+    let mut kept = 0;
+    let mut bytes = 0;
+    for data_file in datastore.list_data_files() {
+        let confirmed = db.is_processed(&data_file.path).await?
+            && !db.is_truncated(&data_file.path).await?
+            && db
+                .observation_count_in_year(data_file.station_id, data_file.year)
+                .await?
+                > 0;
+        if !confirmed {
+            kept += 1;
+            continue;
+        }
+
+        let size = std::fs::metadata(&data_file.path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        if dry_run {
+            println!("Would delete {}", data_file.path.display());
+        } else if let Err(e) = std::fs::remove_file(&data_file.path) {
+            eprintln!("Failed to delete {}: {}", data_file.path.display(), e);
+            kept += 1;
+            continue;
+        }
+        pruned += 1;
+        bytes += size;
+    }
+
+    let verb = if dry_run { "Would delete" } else { "Deleted" };
+    println!(
+        "{} {} files ({} bytes), kept {} not confirmed in the database",
+        verb, pruned, bytes, kept
+    );
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Wind summary command
+//!
+//! Prints wind speed statistics and the prevailing wind direction in degrees for a station, as
+//! CSV or with `--pretty` as a table.
+
+use crate::db::Database;
+use crate::error::AppError as Error;
+use crate::settings::Settings;
+use crate::table::Table;
+use chrono::NaiveDate;
+
+pub async fn summary(
+    settings: &Settings,
+    station: u32,
+    from: NaiveDate,
+    to: NaiveDate,
+    pretty: bool,
+) -> Result<(), Error> {
+    let db = Database::open_readonly_at(&settings.db_path()).await?;
+
+    let summary = db.wind_summary(station, from, to).await?;
+
+    let mut table = Table::new(&["statistic", "value"]);
+    table.add_row(vec!["observations".to_string(), summary.count.to_string()]);
+    table.add_row(vec![
+        "min_wind_speed".to_string(),
+        format_value(summary.min_speed),
+    ]);
+    table.add_row(vec![
+        "max_wind_speed".to_string(),
+        format_value(summary.max_speed),
+    ]);
+    table.add_row(vec![
+        "avg_wind_speed".to_string(),
+        format_value(summary.avg_speed),
+    ]);
+    table.add_row(vec![
+        "prevailing_direction".to_string(),
+        summary
+            .prevailing_direction
+            .map(|direction| direction.to_string())
+            .unwrap_or_else(|| "-".to_string()),
+    ]);
+
+    if pretty {
+        println!("Station {:05} from {} to {}", station, from, to);
+    }
+    print!("{}", table.render(pretty)?);
+
+    Ok(())
+}
+
+/// Format an optional statistic to one decimal place, or `-` when there is no data
+fn format_value(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{:.1}", value))
+        .unwrap_or_else(|| "-".to_string())
+}
@@ -1,36 +1,472 @@
 //! Update datafiles command
 //!
 //! Downloads the latest datafiles from the CEDA API.
+//!
+//! `--county`, `--station`, `--year` and `--qcv` download only the data files that match, the
+//! same way `process` filters the files it loads. `--county` also skips crawling the other
+//! counties.
 
-use crate::ceda_client::CedaClient;
-use crate::datastore::DataStore;
+use crate::ceda_client::{
+    self, CedaClient, CountyLink, DataFileLink, DataFolderLink, Download, StationLink,
+};
+use crate::checkpoint::{Checkpoint, CrawlScope};
+use crate::datastore::{FileFilter, Layout};
 use crate::error::{AppError as Error, AppError};
+use crate::manifest::Manifest;
+use crate::metrics;
+use crate::outcome::Outcome;
+use crate::settings::Settings;
+use chrono::NaiveDate;
 use futures::future::join_all;
 use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Instant;
+
+/// Options for an update run
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    /// Crawl only the station links listed in this file
+    pub stations_file: Option<PathBuf>,
+    /// Print each data file URL as it is downloaded
+    pub verbose: bool,
+    /// Only download data files modified on or after this date
+    pub since: Option<NaiveDate>,
+    pub layout: Layout,
+    /// Skip counties alphabetically before this one
+    pub resume_from: Option<String>,
+    /// Crawl every published dataset version, keeping only the files that changed
+    pub all_versions: bool,
+    /// Stop after this long, abandoning the downloads not yet finished
+    pub deadline: Option<Duration>,
+    /// Skip a station whose pages take longer than this to fetch
+    pub station_timeout: Option<Duration>,
+    /// Fetch every page from CEDA instead of the page cache
+    pub no_cache: bool,
+    /// How long fetched pages are served from the page cache
+    pub cache_ttl: Duration,
+    /// Store downloaded data files gzipped
+    pub compress: bool,
+    /// Download each station's capability file instead of its data files
+    pub capability_only: bool,
+    /// Write crawl metrics to this Prometheus textfile
+    pub metrics_file: Option<PathBuf>,
+    /// Write the discovered data file URLs to this file instead of downloading them
+    pub print_urls: Option<PathBuf>,
+    /// Ignore the crawl checkpoint and fetch every page again
+    pub fresh: bool,
+    /// Download only the data files that match
+    pub filter: FileFilter,
+}
+
+/// What an update run did
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UpdateReport {
+    /// Counties discovered
+    pub counties: usize,
+    /// Stations discovered
+    pub stations: usize,
+    pub files_downloaded: usize,
+    /// Files not downloaded because they were already in the datastore
+    pub files_skipped: usize,
+    pub bytes_downloaded: u64,
+    /// Counties, stations and files that failed
+    pub errors: usize,
+    /// Counties, stations and files attempted
+    pub total: usize,
+    pub duration: Duration,
+    /// Links of the stations skipped because they took longer than the station timeout
+    pub timed_out_stations: Vec<String>,
+}
+
+impl UpdateReport {
+    /// Whether every station and file succeeded
+    pub fn outcome(&self) -> Outcome {
+        Outcome::from_counts(self.errors, self.total)
+    }
+}
+
+impl fmt::Display for UpdateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} counties, {} stations", self.counties, self.stations)?;
+        write!(
+            f,
+            ": downloaded {} files ({} bytes), skipped {}, {} errors in {:.0?}",
+            self.files_downloaded,
+            self.bytes_downloaded,
+            self.files_skipped,
+            self.errors,
+            self.duration
+        )
+    }
+}
+
+/// Download the data files, carrying on past stations and files that fail
+pub async fn update(settings: &Settings, options: UpdateOptions) -> Result<UpdateReport, Error> {
+    let UpdateOptions {
+        stations_file,
+        verbose,
+        since,
+        layout,
+        resume_from,
+        all_versions,
+        deadline,
+        station_timeout,
+        no_cache,
+        cache_ttl,
+        compress,
+        capability_only,
+        metrics_file,
+        print_urls,
+        fresh,
+        filter,
+    } = options;
+    let started = Instant::now();
+    let mut report = UpdateReport::default();
+    let client = settings.client()?;
+    let client = if no_cache {
+        client
+    } else {
+        client.with_cache(settings.datastore().cache_dir(), cache_ttl)
+    };
+    let client = if compress {
+        client.with_compression()
+    } else {
+        client
+    };
+    let deadline = deadline.map(|deadline| Instant::now() + deadline);
+    let request_stats = client.request_stats();
+
+    let clients = if all_versions {
+        let versions = client.get_dataset_versions().await?;
+        println!("Found {} dataset versions", versions.len());
+        versions
+            .iter()
+            .map(|version| client.with_dataset_version(version))
+            .collect()
+    } else {
+        vec![client]
+    };
+
+    let scope = CrawlScope {
+        stations_file,
+        resume_from,
+        county: filter.county.clone(),
+    };
+    let mut discovered_urls: Vec<String> = Vec::new();
+    for client in clients {
+        let path = settings
+            .datastore()
+            .checkpoint_path(client.dataset_version());
+        let mut checkpoint = if fresh {
+            Checkpoint::new(path, scope.clone())
+        } else {
+            Checkpoint::load(path, scope.clone())
+        };
+        if let Some(stage) = checkpoint.last_stage() {
+            println!("Resuming from checkpoint: {} already fetched", stage);
+        }
+
+        if capability_only {
+            let discovery =
+                get_all_station_links(&client, &scope, &filter, &mut checkpoint, &mut report);
+            let (station_links, counties) = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, discovery).await {
+                    Ok(links) => links?,
+                    Err(_) => {
+                        println!("Deadline reached while fetching station links, stopping");
+                        report.errors += 1;
+                        report.total += 1;
+                        break;
+                    }
+                },
+                None => discovery.await?,
+            };
+            let downloads =
+                download_capabilities(settings, client, station_links, verbose, deadline).await?;
+            downloads.add_to(&mut report);
+            report.errors += counties.failed;
+            report.total += counties.total;
+            if downloads.not_started() == 0 {
+                checkpoint.remove()?;
+            }
+            continue;
+        }
+
+        let discovery = get_all_data_file_links(
+            &client,
+            &scope,
+            &filter,
+            since,
+            station_timeout,
+            &mut checkpoint,
+            &mut report,
+        );
+        let (all_data_file_links, stations) = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, discovery).await {
+                Ok(links) => links?,
+                Err(_) => {
+                    println!("Deadline reached while fetching data file links, stopping");
+                    report.errors += 1;
+                    report.total += 1;
+                    break;
+                }
+            },
+            None => discovery.await?,
+        };
+        if print_urls.is_some() {
+            discovered_urls.extend(all_data_file_links.into_iter().map(|link| link.url));
+            report.errors += stations.failed;
+            report.total += stations.total;
+            checkpoint.remove()?;
+            continue;
+        }
+        let downloads = download_data(
+            settings,
+            client,
+            all_data_file_links,
+            verbose,
+            layout,
+            all_versions,
+            deadline,
+        )
+        .await?;
+        downloads.add_to(&mut report);
+        report.errors += stations.failed;
+        report.total += stations.total;
+        if downloads.not_started() == 0 {
+            checkpoint.remove()?;
+        }
+    }
+
+    if let Some(path) = &print_urls {
+        write_urls(path, &discovered_urls)?;
+        println!(
+            "Wrote {} data file URLs to {}",
+            discovered_urls.len(),
+            path.display()
+        );
+    }
+
+    println!("{}", request_stats);
+    if !report.timed_out_stations.is_empty() {
+        println!(
+            "Skipped {} stations that timed out:",
+            report.timed_out_stations.len()
+        );
+        for station in &report.timed_out_stations {
+            println!("  {}", station);
+        }
+    }
+
+    report.duration = started.elapsed();
+    if let Some(metrics_file) = metrics_file {
+        metrics::write_textfile(&report, &metrics_file)?;
+    }
+
+    Ok(report)
+}
+
+/// How many of a batch of crawl or download tasks failed
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Failures {
+    failed: usize,
+    total: usize,
+}
+
+/// What a batch of downloads did
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Downloads {
+    failed: usize,
+    total: usize,
+    downloaded: usize,
+    /// Files already in the datastore
+    existing: usize,
+    bytes: u64,
+}
+
+impl Downloads {
+    /// Count the downloads from the results of their tasks, where `None` is a download that was
+    /// never started
+    fn from_results(results: &[Result<Option<Download>, Error>]) -> Self {
+        let mut downloads = Downloads {
+            total: results.len(),
+            ..Default::default()
+        };
+        for result in results {
+            match result {
+                Ok(Some(Download::Downloaded(bytes))) => {
+                    downloads.downloaded += 1;
+                    downloads.bytes += bytes;
+                }
+                Ok(Some(Download::Existing)) => downloads.existing += 1,
+                Ok(None) => {}
+                Err(_) => downloads.failed += 1,
+            }
+        }
+
+        downloads
+    }
+
+    /// Not started because the deadline passed
+    fn not_started(&self) -> usize {
+        self.total - self.downloaded - self.existing - self.failed
+    }
+
+    /// Add to a report, counting downloads that failed or were never started as errors
+    fn add_to(&self, report: &mut UpdateReport) {
+        report.errors += self.failed + self.not_started();
+        report.total += self.total;
+        report.files_downloaded += self.downloaded;
+        report.files_skipped += self.existing;
+        report.bytes_downloaded += self.bytes;
+    }
+}
+
+/// Crawl from the counties, or the listed stations, down to the data file links, counting the
+/// stations that could not be crawled. Stages already in the checkpoint are not crawled again,
+/// and each stage crawled is saved to it.
+async fn get_all_data_file_links(
+    client: &CedaClient,
+    scope: &CrawlScope,
+    filter: &FileFilter,
+    since: Option<NaiveDate>,
+    station_timeout: Option<Duration>,
+    checkpoint: &mut Checkpoint,
+    report: &mut UpdateReport,
+) -> Result<(Vec<DataFileLink>, Failures), Error> {
+    let (station_links, counties) =
+        get_all_station_links(client, scope, filter, checkpoint, report).await?;
+    let station_count = station_links.len();
+    let data_folder_links = match &checkpoint.data_folder_links {
+        Some(data_folder_links) => data_folder_links.clone(),
+        None => {
+            let (data_folder_links, failed_stations) =
+                get_data_folder_links(client, station_links, station_timeout, report).await?;
+            checkpoint.data_folder_links = Some(data_folder_links.clone());
+            checkpoint.failed += failed_stations;
+            checkpoint.save()?;
+            data_folder_links
+        }
+    };
+    let mut all_data_file_links = match &checkpoint.data_file_links {
+        Some(data_file_links) => data_file_links.clone(),
+        None => {
+            let (data_file_links, failed_folders) =
+                get_data_file_links(client, data_folder_links, station_timeout, report).await?;
+            checkpoint.data_file_links = Some(data_file_links.clone());
+            checkpoint.failed += failed_folders;
+            checkpoint.save()?;
+            data_file_links
+        }
+    };
+    if *filter != FileFilter::default() {
+        all_data_file_links = filter_data_file_links(all_data_file_links, filter);
+    }
+    if let Some(since) = since {
+        all_data_file_links = filter_modified_since(client, all_data_file_links, since).await?;
+    }
+
+    let stations = Failures {
+        failed: counties.failed + checkpoint.failed,
+        total: counties.total + station_count,
+    };
+
+    Ok((all_data_file_links, stations))
+}
+
+/// Crawl from the counties down to the station links, or read them from the stations file,
+/// resuming from and saving to the checkpoint, counting the counties that could not be crawled
+async fn get_all_station_links(
+    client: &CedaClient,
+    scope: &CrawlScope,
+    filter: &FileFilter,
+    checkpoint: &mut Checkpoint,
+    report: &mut UpdateReport,
+) -> Result<(Vec<StationLink>, Failures), Error> {
+    if let Some(station_links) = &checkpoint.station_links {
+        let county_count = checkpoint.county_links.as_ref().map_or(0, Vec::len);
+        report.counties += county_count;
+        report.stations += station_links.len();
+        let counties = Failures {
+            failed: checkpoint.failed_counties,
+            total: county_count,
+        };
+        return Ok((station_links.clone(), counties));
+    }
 
-pub async fn update() -> Result<(), Error> {
-    let client = CedaClient::new("202407").map_err(|_| Error::GenericError)?;
+    let (station_links, counties) = match &scope.stations_file {
+        Some(path) => (read_station_links(path)?, Failures::default()),
+        None => {
+            let mut county_links = match &checkpoint.county_links {
+                Some(county_links) => county_links.clone(),
+                None => get_county_links(client).await?,
+            };
+            county_links.retain(|county_link| filter.matches_county(county_link.county_name()));
+            if let Some(resume_from) = &scope.resume_from {
+                county_links
+                    .retain(|county_link| county_link.county_name() >= resume_from.as_str());
+                println!(
+                    "Resuming from {}: {} counties remaining",
+                    resume_from,
+                    county_links.len()
+                );
+            }
+            let county_count = county_links.len();
+            report.counties += county_count;
+            checkpoint.county_links = Some(county_links.clone());
+            checkpoint.save()?;
+            let (station_links, failed_counties) = get_station_links(client, county_links).await?;
+            checkpoint.failed_counties = failed_counties;
+            let counties = Failures {
+                failed: failed_counties,
+                total: county_count,
+            };
+            (station_links, counties)
+        }
+    };
+    report.stations += station_links.len();
+    checkpoint.station_links = Some(station_links.clone());
+    checkpoint.save()?;
+
+    Ok((station_links, counties))
+}
 
-    let county_links = get_county_links(&client).await?;
-    let station_links = get_station_links(&client, county_links).await?;
-    let data_folder_links = get_data_folder_links(&client, station_links).await?;
-    let (all_data_file_links, datalinks_count) = get_data_file_links(&client, data_folder_links).await?;
-    download_data(client, all_data_file_links, datalinks_count).await?;
+/// Read newline-delimited station links from a file, skipping blank lines
+fn read_station_links(path: &Path) -> Result<Vec<StationLink>, AppError> {
+    let contents = std::fs::read_to_string(path).map_err(|_| Error::FileReadError)?;
 
-    Ok(())
+    let station_links: Vec<StationLink> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| StationLink(line.to_string()))
+        .collect();
+
+    println!("Read {} station links from file", station_links.len());
+
+    Ok(station_links)
 }
 
-async fn get_county_links(client: &CedaClient) -> Result<Vec<String>, AppError> {
+/// Write URLs to a file, one per line
+fn write_urls(path: &Path, urls: &[String]) -> Result<(), Error> {
+    let mut contents = urls.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+
+    std::fs::write(path, contents).map_err(|e| Error::FileWriteError(e.to_string()))
+}
+
+async fn get_county_links(client: &CedaClient) -> Result<Vec<CountyLink>, AppError> {
     let sp = create_spinner("Fetching county links...".to_string());
     let client_clone = client.clone();
 
-    let county_links_task = tokio::spawn(async move {
-        client_clone
-            .get_county_links()
-            .await
-            .map_err(|_| Error::GenericError)
-    });
+    let county_links_task = tokio::spawn(async move { client_clone.get_county_links().await });
 
     let county_links = county_links_task.await.map_err(|_| Error::GenericError)??;
     sp.finish_with_message(format!("Fetched {} county links", county_links.len()));
@@ -40,8 +476,8 @@ async fn get_county_links(client: &CedaClient) -> Result<Vec<String>, AppError>
 
 async fn get_station_links(
     client: &CedaClient,
-    county_links: Vec<String>,
-) -> Result<Vec<String>, AppError> {
+    county_links: Vec<CountyLink>,
+) -> Result<(Vec<StationLink>, usize), AppError> {
     let pb = create_progress_bar(
         county_links.len() as u64,
         "Fetching station links...".to_string(),
@@ -53,34 +489,47 @@ async fn get_station_links(
         let pb = pb.clone();
 
         tasks.push(tokio::spawn(async move {
-            let station_links = client
-                .get_station_links(&county_link)
-                .await
-                .map_err(|_| Error::GenericError)?;
+            let result = client.get_station_links(&county_link).await;
+            if let Err(e) = &result {
+                pb.println(format!("{}: {}", county_link.county_name(), e));
+            }
             pb.inc(1);
-            Ok::<Vec<String>, Error>(station_links)
+            result
         }));
     }
 
     let results = join_all(tasks).await;
-    let mut all_station_links: Vec<String> = Vec::new();
+    let mut all_station_links: Vec<StationLink> = Vec::new();
+    let mut failed = 0;
     for result in results {
         match result {
             Ok(Ok(station_links)) => all_station_links.extend(station_links),
-            _ => return Err(Error::GenericError),
+            _ => failed += 1,
         }
     }
 
     pb.finish_with_message(format!("Fetched {} station links", all_station_links.len()));
 
-    Ok(all_station_links)
+    Ok((all_station_links, failed))
 }
 
+/// A client for a station's pages, which gives up once it has spent longer than the station
+/// timeout fetching them
+fn station_client(client: &CedaClient, station_timeout: Option<Duration>) -> CedaClient {
+    match station_timeout {
+        Some(station_timeout) => client.with_fetch_budget(station_timeout),
+        None => client.clone(),
+    }
+}
 
+/// Get the data folder links of each station, and the number of stations that failed,
+/// recording the stations that time out
 async fn get_data_folder_links(
     client: &CedaClient,
-    station_links: Vec<String>,
-) -> Result<Vec<String>, AppError> {
+    station_links: Vec<StationLink>,
+    station_timeout: Option<Duration>,
+    report: &mut UpdateReport,
+) -> Result<(Vec<DataFolderLink>, usize), AppError> {
     let pb = create_progress_bar(
         station_links.len() as u64,
         "Fetching data folder links...".to_string(),
@@ -88,23 +537,32 @@ async fn get_data_folder_links(
     let mut tasks = Vec::new();
 
     for station_link in station_links {
-        let client = client.clone();
+        let client = station_client(client, station_timeout);
         let pb = pb.clone();
 
         tasks.push(tokio::spawn(async move {
-            let data_folder_link = client
-                .get_data_folder_link(&station_link)
-                .await?;
+            let data_folder_links = client
+                .get_data_folder_links(&station_link)
+                .await
+                .map_err(|e| (station_link, e))?;
             pb.inc(1);
-            Ok::<String, Error>(data_folder_link)
+            Ok::<Vec<DataFolderLink>, (StationLink, Error)>(data_folder_links)
         }));
     }
 
     let results = join_all(tasks).await;
 
-    let mut all_data_folder_links: Vec<String> = Vec::new();
-    for result in results.into_iter().filter_map(Result::ok).filter_map(Result::ok) {
-        all_data_folder_links.push(result);
+    let mut all_data_folder_links: Vec<DataFolderLink> = Vec::new();
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(Ok(data_folder_links)) => all_data_folder_links.extend(data_folder_links),
+            Ok(Err((station_link, Error::StationTimeout(_)))) => {
+                report.timed_out_stations.push(station_link.0);
+                failed += 1;
+            }
+            _ => failed += 1,
+        }
     }
 
     pb.finish_with_message(format!(
@@ -112,83 +570,356 @@ async fn get_data_folder_links(
         all_data_folder_links.len()
     ));
 
-    Ok(all_data_folder_links)
+    Ok((all_data_folder_links, failed))
 }
 
-async fn get_data_file_links(client: &CedaClient, data_folder_links: Vec<String>) -> Result<(Vec<String>, u32), Error> {
+/// Get the data file links in each data folder, and the number of folders that failed,
+/// recording the stations that time out
+async fn get_data_file_links(
+    client: &CedaClient,
+    data_folder_links: Vec<DataFolderLink>,
+    station_timeout: Option<Duration>,
+    report: &mut UpdateReport,
+) -> Result<(Vec<DataFileLink>, usize), Error> {
     let pb = create_progress_bar(
         data_folder_links.len() as u64,
         "Fetching data file links...".to_string(),
     );
     let mut tasks = Vec::new();
+    // The folders of a station share its timeout
+    let mut station_clients: HashMap<String, CedaClient> = HashMap::new();
 
     for data_folder_link in data_folder_links {
-        let client = client.clone();
+        let client = station_clients
+            .entry(data_folder_link.station_link().0)
+            .or_insert_with(|| station_client(client, station_timeout))
+            .clone();
         let pb = pb.clone();
 
         tasks.push(tokio::spawn(async move {
             let data_file_links = client
                 .get_data_file_links(&data_folder_link)
                 .await
-                .map_err(|_| Error::GenericError)?;
+                .map_err(|e| (data_folder_link, e))?;
             pb.inc(1);
-            Ok::<Vec<String>, Error>(data_file_links)
+            Ok::<Vec<DataFileLink>, (DataFolderLink, Error)>(data_file_links)
         }));
     }
 
     let results = join_all(tasks).await;
-    let mut all_data_file_links: Vec<String> = Vec::new();
-    for data_file_links in results.into_iter().filter_map(|r| r.ok()).filter_map(|r| r.ok()) {
-        all_data_file_links.extend(data_file_links);
+    let mut all_data_file_links: Vec<DataFileLink> = Vec::new();
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(Ok(data_file_links)) => all_data_file_links.extend(data_file_links),
+            Ok(Err((data_folder_link, Error::StationTimeout(_)))) => {
+                // A station with several data folders may time out in more than one
+                let station_link = data_folder_link.station_link().0;
+                if !report.timed_out_stations.contains(&station_link) {
+                    report.timed_out_stations.push(station_link);
+                }
+                failed += 1;
+            }
+            _ => failed += 1,
+        }
     }
-    let data_file_links_count = all_data_file_links.len() as u32;
-    pb.finish_with_message(format!("Fetched {} data file links", data_file_links_count));
+    let (all_data_file_links, duplicates) = dedupe_data_file_links(all_data_file_links);
+    pb.finish_with_message(format!(
+        "Fetched {} data file links, removed {} duplicates",
+        all_data_file_links.len(),
+        duplicates
+    ));
 
-    Ok((all_data_file_links, data_file_links_count))
+    Ok((all_data_file_links, failed))
 }
 
+/// Remove links to the same data file, keeping the first, as listings can link a file more than
+/// once. Returns the links kept and the number removed.
+fn dedupe_data_file_links(data_file_links: Vec<DataFileLink>) -> (Vec<DataFileLink>, usize) {
+    let count = data_file_links.len();
+    let mut seen = HashSet::new();
+    let unique: Vec<DataFileLink> = data_file_links
+        .into_iter()
+        .filter(|data_file_link| {
+            let key = ceda_client::csv_filename(&data_file_link.url)
+                .unwrap_or_else(|_| data_file_link.url.clone());
+            seen.insert(key)
+        })
+        .collect();
+    let duplicates = count - unique.len();
 
+    (unique, duplicates)
+}
+
+/// Keep only the links to data files that match the filter
+fn filter_data_file_links(
+    data_file_links: Vec<DataFileLink>,
+    filter: &FileFilter,
+) -> Vec<DataFileLink> {
+    let count = data_file_links.len();
+    let matching: Vec<DataFileLink> = data_file_links
+        .into_iter()
+        .filter(|data_file_link| {
+            data_file_link
+                .properties()
+                .is_ok_and(|properties| properties.matches(filter))
+        })
+        .collect();
+    println!(
+        "{} of {} data files match the filter",
+        matching.len(),
+        count
+    );
+
+    matching
+}
+
+/// Keep only the data file links modified on or after a date. Links without a last modified
+/// date are kept.
+async fn filter_modified_since(
+    client: &CedaClient,
+    data_file_links: Vec<DataFileLink>,
+    since: NaiveDate,
+) -> Result<Vec<DataFileLink>, Error> {
+    let pb = create_progress_bar(
+        data_file_links.len() as u64,
+        "Checking data file modification dates...".to_string(),
+    );
+    let mut tasks = Vec::new();
+
+    for data_file_link in data_file_links {
+        let client = client.clone();
+        let pb = pb.clone();
+
+        tasks.push(tokio::spawn(async move {
+            let last_modified = client
+                .last_modified(&data_file_link.url)
+                .await
+                .ok()
+                .flatten();
+            pb.inc(1);
+            let modified = last_modified.is_none_or(|date_time| date_time.date_naive() >= since);
+            (data_file_link, modified)
+        }));
+    }
+
+    let results = join_all(tasks).await;
+    let modified_links: Vec<DataFileLink> = results
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|(_, modified)| *modified)
+        .map(|(data_file_link, _)| data_file_link)
+        .collect();
+    pb.finish_with_message(format!(
+        "Found {} data files modified since {}",
+        modified_links.len(),
+        since
+    ));
+
+    Ok(modified_links)
+}
+
+/// Download the capability file of each station to the capability directory, counting the
+/// stations that fail. Downloads not finished by the deadline are abandoned.
+async fn download_capabilities(
+    settings: &Settings,
+    client: CedaClient,
+    station_links: Vec<StationLink>,
+    verbose: bool,
+    deadline: Option<Instant>,
+) -> Result<Downloads, AppError> {
+    let capability_dir = settings.datastore().capability_dir();
+    let station_count = station_links.len();
+
+    let pb = create_progress_bar(
+        station_count as u64,
+        "Downloading capability files...".to_string(),
+    );
+    let mut tasks = Vec::new();
+
+    for station_link in station_links {
+        let client = client.clone();
+        let capability_dir = capability_dir.clone();
+        let pb = pb.clone();
+
+        tasks.push(tokio::spawn(before_deadline(deadline, async move {
+            let capability_link = client.get_capability_link(&station_link).await?;
+            if verbose {
+                pb.println(format!("Downloading {}", capability_link));
+            }
+            let download = client
+                .download_csv(&capability_link, &capability_dir)
+                .await?;
+            pb.inc(1);
+
+            Ok::<Download, Error>(download)
+        })));
+    }
+
+    let results = join_downloads(tasks, deadline).await;
+
+    let downloads = Downloads::from_results(&results);
+    pb.finish_with_message(format!(
+        "Downloaded {} of {} capability files",
+        downloads.downloaded + downloads.existing,
+        station_count
+    ));
+
+    Ok(downloads)
+}
+
+/// Download the data files, counting those that fail. Downloads not finished by the deadline,
+/// whether waiting for a request slot or part way through, are abandoned and their partial files
+/// removed.
 async fn download_data(
+    settings: &Settings,
     client: CedaClient,
-    all_data_links: Vec<String>,
-    datalinks_count: u32,
-) -> Result<(), AppError> {
-    let datastore = DataStore::new();
+    all_data_links: Vec<DataFileLink>,
+    verbose: bool,
+    layout: Layout,
+    by_version: bool,
+    deadline: Option<Instant>,
+) -> Result<Downloads, AppError> {
+    let datastore = settings.datastore();
+    let datalinks_count = all_data_links.len();
+    let mut manifest = by_version.then(|| Manifest::load(datastore.manifest_path()));
 
     let pb = create_progress_bar(
         datalinks_count as u64,
         "Downloading data files...".to_string(),
     );
     let mut tasks = Vec::new();
+    let mut data_files = Vec::new();
 
-    for data_link in all_data_links.iter() {
+    for data_link in all_data_links.into_iter() {
         let client = client.clone();
-        let rawdata_dir = datastore.rawdata_dir();
+        let filename = ceda_client::csv_filename(&data_link.url)?;
+        let data_file_dir = if by_version {
+            datastore.versioned_data_file_dir(&filename, layout)
+        } else {
+            datastore.data_file_dir(&filename, layout)
+        };
+        let duplicate = manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.is_duplicate(&filename));
+        data_files.push((data_file_dir.clone(), filename));
         let pb = pb.clone();
-        let data_link = data_link.clone();
 
-        tasks.push(tokio::spawn(async move {
-            client
-                .download_csv(&data_link, &rawdata_dir)
+        tasks.push(tokio::spawn(before_deadline(deadline, async move {
+            if duplicate {
+                pb.inc(1);
+                return Ok(Download::Existing);
+            }
+            pb.set_message(format!(
+                "Downloading {} for {}",
+                data_link.year, data_link.county_name
+            ));
+            if verbose {
+                pb.println(format!("Downloading {}", data_link.url));
+            }
+            let download = client
+                .download_csv(&data_link.url, &data_file_dir)
                 .await
-                .map_err(|_| Error::GenericError)?;
+                .inspect_err(|e| pb.println(format!("{}: {}", data_link.url, e)))?;
+            if verbose {
+                pb.println(format!("Downloaded {}", data_link.url));
+            }
             pb.inc(1);
 
-            Ok::<(), Error>(())
-        }));
+            Ok::<Download, Error>(download)
+        })));
+    }
+
+    let results = join_downloads(tasks, deadline).await;
+
+    if let Some(manifest) = &mut manifest {
+        let mut removed = 0;
+        for ((dir, filename), result) in data_files.iter().zip(&results) {
+            let stored = matches!(
+                result,
+                Ok(Some(Download::Downloaded(_))) | Ok(Some(Download::Existing))
+            );
+            if stored && !manifest.contains(filename) && manifest.add(dir, filename)? {
+                removed += 1;
+            }
+        }
+        manifest.save()?;
+        if removed > 0 {
+            pb.println(format!(
+                "Removed {} data files identical to an earlier dataset version",
+                removed
+            ));
+        }
+    }
+
+    let downloads = Downloads::from_results(&results);
+    let skipped = downloads.not_started();
+    let failed = downloads.failed;
+    if skipped > 0 {
+        pb.finish_with_message(format!(
+            "Deadline reached: downloaded {} of {} data files",
+            datalinks_count - skipped - failed,
+            datalinks_count
+        ));
+    } else if failed > 0 {
+        pb.finish_with_message(format!(
+            "Downloaded {} of {} data files, {} failed",
+            datalinks_count - failed,
+            datalinks_count,
+            failed
+        ));
+    } else {
+        pb.finish_with_message("Downloaded data files");
+    }
+
+    Ok(downloads)
+}
+
+/// Run a download unless the deadline passes first, giving `Ok(None)` if it does. Waiting for a
+/// request slot counts towards the deadline, and a download cut short removes its partial file.
+async fn before_deadline<T>(
+    deadline: Option<Instant>,
+    download: impl Future<Output = Result<T, Error>>,
+) -> Result<Option<T>, Error> {
+    match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, download).await {
+            Ok(result) => result.map(Some),
+            Err(_) => Ok(None),
+        },
+        None => download.await.map(Some),
     }
+}
+
+/// Wait for download tasks, aborting any still running once the deadline passes. An aborted
+/// task counts as not started.
+async fn join_downloads<T: Send + 'static>(
+    tasks: Vec<JoinHandle<Result<Option<T>, Error>>>,
+    deadline: Option<Instant>,
+) -> Vec<Result<Option<T>, Error>> {
+    let aborter = deadline.map(|deadline| {
+        let abort_handles: Vec<AbortHandle> = tasks.iter().map(JoinHandle::abort_handle).collect();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            abort_handles.iter().for_each(AbortHandle::abort);
+        })
+    });
 
-    join_all(tasks)
+    let results = join_all(tasks)
         .await
         .into_iter()
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
+        .map(|result| match result {
+            Ok(result) => result,
+            Err(e) if e.is_cancelled() => Ok(None),
+            Err(_) => Err(Error::GenericError),
+        })
+        .collect();
+    if let Some(aborter) = aborter {
+        aborter.abort();
+    }
 
-    pb.finish_with_message("Downloaded data files");
-    Ok(())
+    results
 }
 
-
 fn create_spinner(message: String) -> ProgressBar {
     let bar = ProgressBar::new_spinner().with_message(message);
     bar.enable_steady_tick(Duration::from_millis(100));
@@ -207,9 +938,170 @@ fn create_progress_bar(size: u64, message: String) -> ProgressBar {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
 
     #[tokio::test]
     async fn it_updates() {
-        let _ = update().await;
+        let cli = Cli::parse_from(["rust-ceda", "update"]);
+        let settings = Settings::load(&cli).unwrap();
+        let _ = update(&settings, UpdateOptions::default()).await;
+    }
+
+    #[test]
+    fn it_reports_update() {
+        let report = UpdateReport {
+            counties: 2,
+            stations: 10,
+            files_downloaded: 7,
+            files_skipped: 3,
+            bytes_downloaded: 4096,
+            errors: 1,
+            total: 20,
+            duration: Duration::from_secs(90),
+            timed_out_stations: Vec::new(),
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "2 counties, 10 stations: downloaded 7 files (4096 bytes), skipped 3, 1 errors in 90s"
+        );
+        assert_eq!(
+            report.outcome(),
+            Outcome::Partial {
+                failed: 1,
+                total: 20
+            }
+        );
+    }
+
+    #[test]
+    fn it_counts_downloads_not_started_as_errors() {
+        let results = vec![
+            Ok(Some(Download::Downloaded(512))),
+            Ok(Some(Download::Existing)),
+            Err(Error::GenericError),
+            Ok(None),
+        ];
+        let downloads = Downloads::from_results(&results);
+        let mut report = UpdateReport::default();
+        downloads.add_to(&mut report);
+
+        assert_eq!(downloads.not_started(), 1);
+        assert_eq!(report.files_downloaded, 1);
+        assert_eq!(report.files_skipped, 1);
+        assert_eq!(
+            report.outcome(),
+            Outcome::Partial {
+                failed: 2,
+                total: 4
+            }
+        );
+    }
+
+    #[test]
+    fn it_dedupes_data_file_links() {
+        let filename =
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+        let links: Vec<DataFileLink> = [
+            format!("https://dap.ceda.ac.uk/badc/{}?download=1", filename),
+            format!("https://dap.ceda.ac.uk/badc/sorted/{}", filename),
+            format!(
+                "https://dap.ceda.ac.uk/badc/{}",
+                filename.replace("1994", "1995")
+            ),
+        ]
+        .iter()
+        .filter_map(|url| DataFileLink::parse(url))
+        .collect();
+
+        let (unique, duplicates) = dedupe_data_file_links(links);
+
+        assert_eq!(duplicates, 1);
+        let years: Vec<u32> = unique.iter().map(|link| link.year).collect();
+        assert_eq!(years, vec![1994, 1995]);
+    }
+
+    #[test]
+    fn it_filters_data_file_links() {
+        let filename =
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+        let links: Vec<DataFileLink> = [
+            format!("https://dap.ceda.ac.uk/badc/{}?download=1", filename),
+            format!(
+                "https://dap.ceda.ac.uk/badc/{}",
+                filename.replace("1994", "2001")
+            ),
+            format!(
+                "https://dap.ceda.ac.uk/badc/{}",
+                filename.replace("antrim", "devon")
+            ),
+        ]
+        .iter()
+        .filter_map(|url| DataFileLink::parse(url))
+        .collect();
+        let filter = FileFilter {
+            county: Some("antrim".to_string()),
+            year: Some(1990..=1999),
+            ..Default::default()
+        };
+
+        let matching = filter_data_file_links(links, &filter);
+
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].year, 1994);
+        assert_eq!(matching[0].county_name, "antrim");
+    }
+
+    #[tokio::test]
+    async fn it_abandons_downloads_at_the_deadline() {
+        let deadline = Some(Instant::now() + Duration::from_millis(10));
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(1)
+        };
+        assert!(matches!(before_deadline(deadline, slow).await, Ok(None)));
+
+        let tasks = vec![
+            tokio::spawn(async { Ok(Some(1)) }),
+            tokio::spawn(async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(Some(2))
+            }),
+        ];
+        let results = join_downloads(tasks, deadline).await;
+        assert!(matches!(results[..], [Ok(Some(1)), Ok(None)]));
+    }
+
+    #[test]
+    fn it_writes_urls() {
+        let path = std::env::temp_dir().join("rust-ceda-urls.txt");
+        let urls = vec![
+            "https://dap.ceda.ac.uk/badc/a.csv".to_string(),
+            "https://dap.ceda.ac.uk/badc/b.csv".to_string(),
+        ];
+
+        write_urls(&path, &urls).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "https://dap.ceda.ac.uk/badc/a.csv\nhttps://dap.ceda.ac.uk/badc/b.csv\n"
+        );
+    }
+
+    #[test]
+    fn it_reads_station_links() {
+        let path = std::env::temp_dir().join("rust-ceda-stations.txt");
+        std::fs::write(&path, "/badc/station-a\n\n  /badc/station-b  \n").unwrap();
+
+        let station_links = read_station_links(&path).unwrap();
+
+        assert_eq!(
+            station_links,
+            vec![
+                StationLink("/badc/station-a".to_string()),
+                StationLink("/badc/station-b".to_string())
+            ]
+        );
     }
 }
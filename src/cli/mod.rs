@@ -1,6 +1,15 @@
+pub mod args;
 pub mod command;
 
-use clap::{command, Parser, Subcommand};
+use crate::cli::args::{
+    AuthMode, ExportFormat, FileFilter, Layout, Output, ProcessMode, SortBy, ValidationPolicy,
+};
+use chrono::NaiveDate;
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, Subcommand};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -8,17 +17,269 @@ use clap::{command, Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+    #[arg(long, global = true)]
+    /// Config file [default: ./rust-ceda.toml or ~/.config/rust-ceda/rust-ceda.toml]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    /// Directory for the datastore, overriding DATA_DIR
+    pub data_dir: Option<PathBuf>,
+    #[arg(long, global = true)]
+    /// CEDA dataset version e.g. 202407
+    pub dataset_version: Option<String>,
+    #[arg(long, global = true)]
+    /// CEDA access token, overriding CEDA_ACCESS_TOKEN
+    pub access_token: Option<String>,
+    #[arg(long, global = true)]
+    /// Maximum concurrent requests to CEDA, overriding CEDA_CONCURRENCY
+    pub concurrency: Option<NonZeroUsize>,
+    #[arg(long, global = true)]
+    /// Maximum requests per second to CEDA, overriding CEDA_RATE
+    pub rate: Option<NonZeroU32>,
+    #[arg(long, global = true, default_value_t = false)]
+    /// Crawl conservatively: few requests at once, a low rate and patient retries. Overridden by
+    /// --concurrency and --rate, but not by CEDA_CONCURRENCY and CEDA_RATE
+    pub polite: bool,
+    #[arg(long, global = true)]
+    /// Database connections: one writer and the rest read-only [default: 5]
+    pub db_pool_size: Option<u32>,
+    #[arg(long, global = true)]
+    /// User-Agent sent to CEDA [default: rust-ceda/<version>]
+    pub user_agent: Option<String>,
+    #[arg(long, global = true)]
+    /// Database file, overriding CEDA_DB_PATH [default: <data_dir>/db/weather.sqlite]
+    pub db_path: Option<PathBuf>,
+    #[arg(long, global = true, value_enum)]
+    /// How the access token is sent to CEDA [default: bearer]
+    pub auth_mode: Option<AuthMode>,
+    #[arg(long, global = true)]
+    /// Idle connections kept open to each host for reuse [default: 32]
+    pub pool_max_idle_per_host: Option<usize>,
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    /// How long an idle connection is kept open for reuse e.g. 30s, 2m [default: 90s]
+    pub pool_idle_timeout: Option<Duration>,
+    #[arg(long, global = true, default_value_t = false)]
+    /// Accept invalid and self-signed TLS certificates. UNSAFE: the connection, and the access
+    /// token it carries, can be read and changed by anyone in between. Only for testing and
+    /// TLS-intercepting proxies
+    pub danger_accept_invalid_certs: bool,
 }
 
 #[derive(Subcommand)]
 /// Available commands.
 pub enum Commands {
     /// Update datafiles
-    Update {},
+    Update {
+        #[arg(long)]
+        /// Crawl only the station links listed in this file, one per line
+        stations_file: Option<PathBuf>,
+        #[arg(short, long, default_value_t = false)]
+        /// Print each data file URL as it is downloaded
+        verbose: bool,
+        #[arg(long)]
+        /// Only download data files modified on or after this date (YYYY-MM-DD)
+        since: Option<NaiveDate>,
+        #[arg(long, value_enum, default_value_t = Layout::Flat)]
+        /// How to organise downloaded files under the raw data directory
+        layout: Layout,
+        #[arg(long, conflicts_with = "stations_file")]
+        /// Skip counties alphabetically before this one, to resume an interrupted crawl
+        resume_from: Option<String>,
+        #[arg(long, default_value_t = false, conflicts_with = "stations_file")]
+        /// Crawl every published dataset version, downloading each into its own directory.
+        /// Files identical to an earlier version are not kept.
+        all_versions: bool,
+        #[arg(long, value_parser = humantime::parse_duration)]
+        /// Stop after this long, abandoning unfinished downloads e.g. 30m, 2h
+        deadline: Option<Duration>,
+        #[arg(long, value_parser = humantime::parse_duration)]
+        /// Skip a station whose pages take longer than this to fetch e.g. 2m
+        station_timeout: Option<Duration>,
+        #[arg(long, default_value_t = false)]
+        /// Fetch every page from CEDA instead of the page cache
+        no_cache: bool,
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "1h")]
+        /// How long fetched pages are served from the page cache e.g. 10m, 24h
+        cache_ttl: Duration,
+        #[arg(long, default_value_t = false)]
+        /// Store downloaded data files gzipped, as `.csv.gz`
+        compress: bool,
+        #[arg(long, default_value_t = false)]
+        /// Download only each station's capability file, skipping the data files
+        capability_only: bool,
+        #[arg(long)]
+        /// Write crawl metrics to this file in the Prometheus textfile format
+        metrics_file: Option<PathBuf>,
+        #[arg(long, conflicts_with = "capability_only")]
+        /// Write the discovered data file URLs to this file, one per line, without downloading
+        print_urls: Option<PathBuf>,
+        #[arg(long, default_value_t = false)]
+        /// Ignore the checkpoint of an interrupted crawl and fetch every page again
+        fresh: bool,
+        #[command(flatten)]
+        filter: FileFilter,
+    },
     /// Process datafiles
     Process {
+        #[arg(long, value_enum, conflicts_with = "init")]
+        /// How to treat stored observations: init deletes them, append adds to them, upsert
+        /// replaces them [default: append]
+        mode: Option<ProcessMode>,
         #[arg(short, long, default_value_t = false)]
-        /// Initialise the database WARNING: This will delete all data and cannot be undone
+        /// Same as `--mode init` WARNING: This will delete all data and cannot be undone
         init: bool,
+        #[arg(long, default_value_t = false, conflicts_with = "file")]
+        /// Initialise the database and reparse every downloaded file, e.g. after a parser change
+        rebuild: bool,
+        #[arg(short, long, default_value_t = false)]
+        /// Don't ask for confirmation before deleting data
+        yes: bool,
+        #[arg(long, value_enum, default_value_t = ValidationPolicy::Clamp)]
+        /// How to handle physically impossible observation values
+        validation: ValidationPolicy,
+        #[arg(short, long)]
+        /// Process only these files instead of the whole datastore
+        file: Vec<PathBuf>,
+        #[arg(long, conflicts_with_all = ["file", "rebuild"])]
+        /// Process the data files anywhere under this directory instead of the datastore
+        input_dir: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 4,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        /// Maximum number of files parsed at once
+        parse_concurrency: usize,
+        #[arg(
+            long,
+            default_value_t = 4,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        /// Maximum number of parsed files waiting to be written
+        channel_size: usize,
+        #[arg(long, default_value_t = false, conflicts_with_all = ["init", "rebuild"])]
+        /// Reprocess files, appending only observations newer than those stored
+        incremental: bool,
+        #[arg(long, default_value_t = false)]
+        /// Store the columns that are not modeled as JSON in the observations table
+        capture_extra: bool,
+        #[arg(long, default_value_t = false)]
+        /// Leave out observations without any measured value, e.g. rows with no wind data
+        skip_empty: bool,
+        #[arg(long)]
+        /// Write at most this many observations for each station, to build a small sample
+        /// database
+        limit_per_station: Option<usize>,
+        #[arg(long, default_value_t = false, conflicts_with = "limit_per_station")]
+        /// Delete each data file once its observations are written, keeping files that fail
+        delete_after_process: bool,
+        #[arg(long)]
+        /// Commit every N observations within a file instead of once per file. A failure part
+        /// way through leaves the committed observations, which `--incremental` completes
+        commit_every: Option<NonZeroUsize>,
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with_all = ["mode", "init", "rebuild", "delete_after_process"]
+        )]
+        /// Parse every file, or the files given with --file, and report parse errors,
+        /// observation counts and column coverage, without touching the database
+        validate_only: bool,
+        #[arg(long, default_value_t = false)]
+        /// Write each county to its own database, `weather_<county>.sqlite`, next to the database
+        /// file
+        split_by_county: bool,
+        #[arg(long, value_enum, default_value_t = Output::Db)]
+        /// Where to write the processed observations
+        output: Output,
+        #[arg(short, long)]
+        /// File to write CSV output to [default: stdout]
+        out: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FileFilter,
+    },
+    /// List datafiles
+    List {
+        #[arg(long, default_value_t = false)]
+        /// Only list stations with missing years
+        gaps: bool,
+        #[arg(long, value_enum, default_value_t = SortBy::County)]
+        /// How to order the data files
+        sort_by: SortBy,
+    },
+    /// Export observations as a Parquet dataset partitioned by station and year, or as
+    /// newline-delimited JSON
+    Export {
+        #[arg(short, long)]
+        /// Directory to write the Parquet dataset to, or file to write JSON to [default: stdout]
+        out: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Parquet)]
+        /// Format to export observations in
+        output_format: ExportFormat,
+        #[arg(long, value_delimiter = ',')]
+        /// Export only these columns e.g. `date_time,wind_speed`, from midas_station_id,
+        /// date_time (or ob_time), wind_speed, wind_direction, wind_unit_id and wind_opr_type
+        /// [default: all]
+        columns: Vec<String>,
+    },
+    /// Check the datastore and database are present and consistent
+    Check {},
+    /// Check CEDA accepts the access token, before starting a long crawl
+    CheckToken {},
+    /// Download a single data file by URL, without crawling
+    Download {
+        /// URL of the file, absolute or relative to the CEDA archive
+        url: String,
+        #[arg(short, long, default_value = ".")]
+        /// Directory to download the file to
+        out: PathBuf,
+    },
+    /// List the counties available in the dataset
+    Counties {},
+    /// Vacuum and analyze the database
+    Optimize {},
+    /// Write a zstd-compressed copy of the database, `<name>.sqlite.zst`, for long-term storage
+    Archive {
+        #[arg(short, long)]
+        /// Directory to write the archive to [default: the database's directory]
+        out: Option<PathBuf>,
+        #[arg(long, default_value_t = 19, value_parser = clap::value_parser!(i32).range(1..=22))]
+        /// zstd compression level, from 1 (fastest) to 22 (smallest)
+        level: i32,
+    },
+    /// Delete downloaded data files whose observations are already in the database
+    Prune {
+        #[arg(long, default_value_t = false)]
+        /// List the files that would be deleted without deleting them
+        dry_run: bool,
+    },
+    /// List the stored stations nearest a coordinate
+    Nearest {
+        #[arg(long, allow_negative_numbers = true)]
+        /// Latitude in decimal degrees
+        lat: f64,
+        #[arg(long, allow_negative_numbers = true)]
+        /// Longitude in decimal degrees, negative west of Greenwich
+        lon: f64,
+        #[arg(short = 'n', long, default_value_t = 5)]
+        /// Number of stations to list
+        count: usize,
+        #[arg(long, default_value_t = false)]
+        /// Print a bordered table instead of CSV
+        pretty: bool,
+    },
+    /// Summarise a station's wind speeds and prevailing direction
+    Summary {
+        #[arg(short, long)]
+        /// MIDAS station id
+        station: u32,
+        #[arg(long)]
+        /// First day of the window (YYYY-MM-DD)
+        from: NaiveDate,
+        #[arg(long)]
+        /// Last day of the window (YYYY-MM-DD)
+        to: NaiveDate,
+        #[arg(long, default_value_t = false)]
+        /// Print a bordered table instead of CSV
+        pretty: bool,
     },
 }
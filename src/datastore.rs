@@ -1,7 +1,21 @@
 //! Manages the data store for the application.
 
+use crate::error::AppError as Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use std::path::PathBuf;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// How downloaded data files are organised under the raw data directory
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// All files in `raw/data`
+    #[default]
+    Flat,
+    /// Files in `raw/data/<county>/<station_id>`
+    Nested,
+}
 
 /// Represents a datastore in the file system to assist in managing data files
 pub struct DataStore {
@@ -15,6 +29,11 @@ impl DataStore {
         Self { root }
     }
 
+    /// Create a new instance of the data store in the given directory
+    pub fn with_root(root: PathBuf) -> Self {
+        Self { root }
+    }
+
 
     /// Path to where the data files are stored
     pub fn rawdata_dir(&self) -> PathBuf {
@@ -26,6 +45,69 @@ impl DataStore {
         dir_path
     }
 
+    /// Path to the directory a data file belongs in for the given layout
+    pub fn data_file_dir(&self, filename: &str, layout: Layout) -> PathBuf {
+        DataStore::layout_dir(self.rawdata_dir(), filename, layout)
+    }
+
+    /// Path to the directory a data file belongs in for the given layout, under a directory for
+    /// its dataset version e.g. `raw/data/dv-202407`
+    pub fn versioned_data_file_dir(&self, filename: &str, layout: Layout) -> PathBuf {
+        let rawdata_dir = self.rawdata_dir();
+        let version_dir = match filename.split('_').nth(2) {
+            Some(version) if version.starts_with("dv-") => rawdata_dir.join(version),
+            _ => rawdata_dir,
+        };
+
+        DataStore::layout_dir(version_dir, filename, layout)
+    }
+
+    fn layout_dir(base_dir: PathBuf, filename: &str, layout: Layout) -> PathBuf {
+        let dir_path = match layout {
+            Layout::Flat => base_dir,
+            Layout::Nested => {
+                let parts: Vec<&str> = filename.split('_').collect();
+                match (parts.get(3), parts.get(4)) {
+                    (Some(county_name), Some(station_id)) => {
+                        base_dir.join(county_name).join(station_id)
+                    }
+                    _ => base_dir,
+                }
+            }
+        };
+        if !dir_path.exists() {
+            std::fs::create_dir_all(&dir_path).unwrap();
+        }
+
+        dir_path
+    }
+
+    /// Path to where the station capability files are stored
+    pub fn capability_dir(&self) -> PathBuf {
+        let dir_path = self.root.join("raw/capability");
+        if !dir_path.exists() {
+            std::fs::create_dir_all(&dir_path).unwrap();
+        }
+
+        dir_path
+    }
+
+    /// Path to where fetched CEDA pages are cached
+    pub fn cache_dir(&self) -> PathBuf {
+        self.root.join("cache/html")
+    }
+
+    /// Path to the crawl checkpoint of a dataset version e.g. `checkpoint_202407.json`
+    pub fn checkpoint_path(&self, dataset_version: &str) -> PathBuf {
+        self.root
+            .join(format!("checkpoint_{}.json", dataset_version))
+    }
+
+    /// Path to the manifest of the data files downloaded across dataset versions
+    pub fn manifest_path(&self) -> PathBuf {
+        self.root.join("manifest.json")
+    }
+
     /// Path to where the database is stored
     pub fn db_dir(&self) -> PathBuf {
         let dir_path = self.root.join("db");
@@ -36,27 +118,126 @@ impl DataStore {
         dir_path
     }
 
-    /// Get a list of the data file properties
+    /// Get a list of the data file properties, in either the flat or nested layout
     pub fn list_data_files(&self) -> Vec<FileProperties> {
+        DataStore::list_data_files_in(&self.rawdata_dir())
+    }
+
+    /// Get a list of the data file properties of the MIDAS data files anywhere under a directory,
+    /// whatever its layout, sorted by county, station and year
+    pub fn list_data_files_in(dir_path: &Path) -> Vec<FileProperties> {
         let mut datafiles = Vec::new();
-        let dir_path = self.rawdata_dir();
+        DataStore::collect_data_files(dir_path, &mut datafiles);
+        datafiles.sort_by(|a, b| {
+            (&a.county_name, a.station_id, a.year, &a.path).cmp(&(
+                &b.county_name,
+                b.station_id,
+                b.year,
+                &b.path,
+            ))
+        });
 
+        datafiles
+    }
+
+    fn collect_data_files(dir_path: &Path, datafiles: &mut Vec<FileProperties>) {
         for file_path in std::fs::read_dir(dir_path).unwrap() {
-            let file_path = file_path.unwrap();
-            datafiles.push(FileProperties::new(file_path.path()));
+            let file_path = file_path.unwrap().path();
+            if file_path.is_dir() {
+                DataStore::collect_data_files(&file_path, datafiles);
+            } else if file_path.extension().is_some_and(|ext| ext == "part") {
+                // Skip partial downloads
+                continue;
+            } else if let Ok(datafile) = FileProperties::new(file_path) {
+                datafiles.push(datafile);
+            }
         }
+    }
 
-        datafiles
+    /// Get the years of data held for each station, keyed by station id
+    pub fn coverage(&self) -> BTreeMap<u32, StationCoverage> {
+        let mut coverage: BTreeMap<u32, StationCoverage> = BTreeMap::new();
+
+        for data_file in self.list_data_files() {
+            coverage
+                .entry(data_file.station_id)
+                .or_default()
+                .years
+                .insert(data_file.year);
+        }
+
+        coverage
     }
 
     pub fn get_data_dir() -> PathBuf {
+        DataStore::try_get_data_dir().expect("DATA_DIR must be set")
+    }
+
+    /// Get the data directory from the environment, if set
+    pub fn try_get_data_dir() -> Option<PathBuf> {
         dotenv::dotenv().ok();
-        env::var("DATA_DIR").expect("DATA_DIR must be set").into()
+        env::var("DATA_DIR").ok().map(PathBuf::from)
     }
 }
 
+/// The years of data held for a station
+#[derive(Debug, Default, PartialEq)]
+pub struct StationCoverage {
+    pub years: BTreeSet<u32>,
+}
+
+impl StationCoverage {
+    /// The earliest year held
+    pub fn min_year(&self) -> Option<u32> {
+        self.years.first().copied()
+    }
+
+    /// The latest year held
+    pub fn max_year(&self) -> Option<u32> {
+        self.years.last().copied()
+    }
+
+    /// The years between the earliest and latest that are not held
+    pub fn missing_years(&self) -> Vec<u32> {
+        match (self.min_year(), self.max_year()) {
+            (Some(min), Some(max)) => (min..=max)
+                .filter(|year| !self.years.contains(year))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Which data files to work on, by the properties in their filenames. Every filter given must
+/// match.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FileFilter {
+    /// County name, matched case-insensitively
+    pub county: Option<String>,
+    /// MIDAS station id
+    pub station: Option<u32>,
+    /// Inclusive range of years
+    pub year: Option<RangeInclusive<u32>>,
+    /// Quality control version, given as `qcv-1` or `1`
+    pub qcv: Option<String>,
+}
+
+impl FileFilter {
+    /// Whether files of a county can match
+    pub fn matches_county(&self, county_name: &str) -> bool {
+        self.county
+            .as_ref()
+            .is_none_or(|county| county.eq_ignore_ascii_case(county_name))
+    }
+}
+
+/// The number of a quality control version, given as `qcv-1` or `1`
+fn qcv_number(qcv: &str) -> &str {
+    qcv.strip_prefix("qcv-").unwrap_or(qcv)
+}
+
 /// Represents the properties of a data file, obtqined from the filename
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct FileProperties {
     pub path: PathBuf,
@@ -71,20 +252,33 @@ pub struct FileProperties {
 }
 
 impl FileProperties {
-    /// Create a new instance of the data file
-    pub fn new(path: PathBuf) -> Self {
-        let filename = path.file_name().unwrap().to_str().unwrap();
-        let parts: Vec<&str> = filename.split('_').collect();
+    /// Create a new instance of the data file, failing if the filename is not a MIDAS data filename
+    ///
+    /// e.g. `midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv`
+    pub fn new(path: PathBuf) -> Result<Self, Error> {
+        let invalid = || Error::InvalidFilename(path.to_string_lossy().to_string());
+
+        let filename = path
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .ok_or_else(invalid)?;
+        let filename = filename.strip_suffix(".gz").unwrap_or(filename);
+        let stem = filename.strip_suffix(".csv").ok_or_else(invalid)?;
+        let parts: Vec<&str> = stem.split('_').collect();
+        if parts.len() != 8 {
+            return Err(invalid());
+        }
+
         let collection_name = parts[0].to_string();
         let title = parts[1].to_string();
         let updated = parts[2].to_string();
         let county_name = parts[3].to_string();
-        let station_id: u32 = parts[4].parse().unwrap();
+        let station_id: u32 = parts[4].parse().map_err(|_| invalid())?;
         let station_name = parts[5].to_string();
         let qcv = parts[6].to_string();
-        let year: u32 = parts[7].split('.').next().unwrap().parse().unwrap();
+        let year: u32 = parts[7].parse().map_err(|_| invalid())?;
 
-        Self {
+        Ok(Self {
             path,
             collection_name,
             title,
@@ -94,13 +288,30 @@ impl FileProperties {
             station_name,
             qcv,
             year,
-        }
+        })
+    }
+
+    /// Whether the data file matches every filter given
+    pub fn matches(&self, filter: &FileFilter) -> bool {
+        filter.matches_county(&self.county_name)
+            && filter
+                .station
+                .is_none_or(|station| station == self.station_id)
+            && filter
+                .year
+                .as_ref()
+                .is_none_or(|years| years.contains(&self.year))
+            && filter
+                .qcv
+                .as_ref()
+                .is_none_or(|qcv| qcv_number(qcv) == qcv_number(&self.qcv))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_new() {
@@ -111,7 +322,7 @@ mod tests {
     #[test]
     fn test_new_datafile() {
         let file_path = "/Users/richardlyon/Library/Application Support/CEDA/raw/data/midas-open_uk-hourly-weather-obs_dv-202407_aberdeenshire_00144_corgarff-castle-lodge_qcv-1_1997.csv";
-        let data_file = FileProperties::new(PathBuf::from(file_path));
+        let data_file = FileProperties::new(PathBuf::from(file_path)).unwrap();
 
         assert_eq!(data_file.path.to_string_lossy(), file_path);
         assert_eq!(data_file.collection_name, "midas-open");
@@ -123,4 +334,187 @@ mod tests {
         assert_eq!(data_file.qcv, "qcv-1");
         assert_eq!(data_file.year, 1997);
     }
+
+    #[test]
+    fn test_new_gzipped_datafile() {
+        let file_path = "/tmp/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv.gz";
+        let data_file = FileProperties::new(PathBuf::from(file_path)).unwrap();
+
+        assert_eq!(data_file.station_id, 1448);
+        assert_eq!(data_file.qcv, "qcv-1");
+        assert_eq!(data_file.year, 1994);
+    }
+
+    #[test]
+    fn test_matches_filter() {
+        let file_path = "/tmp/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+        let data_file = FileProperties::new(PathBuf::from(file_path)).unwrap();
+
+        let matching = [
+            FileFilter::default(),
+            FileFilter {
+                county: Some("Antrim".to_string()),
+                station: Some(1448),
+                year: Some(1990..=1999),
+                qcv: Some("1".to_string()),
+            },
+            FileFilter {
+                qcv: Some("qcv-1".to_string()),
+                ..Default::default()
+            },
+        ];
+        for filter in matching {
+            assert!(data_file.matches(&filter), "{:?}", filter);
+        }
+
+        let other = [
+            FileFilter {
+                county: Some("devon".to_string()),
+                ..Default::default()
+            },
+            FileFilter {
+                station: Some(144),
+                ..Default::default()
+            },
+            FileFilter {
+                year: Some(1995..=1995),
+                ..Default::default()
+            },
+            FileFilter {
+                qcv: Some("0".to_string()),
+                ..Default::default()
+            },
+        ];
+        for filter in other {
+            assert!(!data_file.matches(&filter), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_data_file_dir() {
+        let store = DataStore {
+            root: std::env::temp_dir().join("rust-ceda-layout-test"),
+        };
+        let filename =
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+
+        assert_eq!(
+            store.data_file_dir(filename, Layout::Flat),
+            store.rawdata_dir()
+        );
+        assert_eq!(
+            store.data_file_dir(filename, Layout::Nested),
+            store.rawdata_dir().join("antrim").join("01448")
+        );
+        assert_eq!(
+            store.versioned_data_file_dir(filename, Layout::Flat),
+            store.rawdata_dir().join("dv-202407")
+        );
+    }
+
+    #[test]
+    fn test_list_data_files_in() {
+        let dir = std::env::temp_dir().join("rust-ceda-input-dir");
+        let nested = dir.join("from").join("elsewhere");
+        std::fs::create_dir_all(&nested).unwrap();
+        let filename =
+            "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+        std::fs::write(nested.join(filename), "").unwrap();
+        std::fs::write(dir.join("notes.csv"), "").unwrap();
+
+        let data_files = DataStore::list_data_files_in(&dir);
+
+        assert_eq!(data_files.len(), 1);
+        assert_eq!(data_files[0].path, nested.join(filename));
+    }
+
+    #[test]
+    fn test_list_data_files_in_order() {
+        let dir = std::env::temp_dir().join("rust-ceda-sorted-dir");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (county_name, station_id, year) in [
+            ("antrim", "01448", 1995),
+            ("aberdeenshire", "00144", 1997),
+            ("antrim", "01448", 1994),
+            ("antrim", "00999", 2001),
+        ] {
+            let filename = format!(
+                "midas-open_uk-hourly-weather-obs_dv-202407_{}_{}_station_qcv-1_{}.csv",
+                county_name, station_id, year
+            );
+            std::fs::write(dir.join(filename), "").unwrap();
+        }
+
+        let order: Vec<(String, u32, u32)> = DataStore::list_data_files_in(&dir)
+            .into_iter()
+            .map(|data_file| (data_file.county_name, data_file.station_id, data_file.year))
+            .collect();
+
+        assert_eq!(
+            order,
+            vec![
+                ("aberdeenshire".to_string(), 144, 1997),
+                ("antrim".to_string(), 999, 2001),
+                ("antrim".to_string(), 1448, 1994),
+                ("antrim".to_string(), 1448, 1995),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_years() {
+        let coverage = StationCoverage {
+            years: BTreeSet::from([1994, 1995, 1998]),
+        };
+
+        assert_eq!(coverage.min_year(), Some(1994));
+        assert_eq!(coverage.max_year(), Some(1998));
+        assert_eq!(coverage.missing_years(), vec![1996, 1997]);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parses_valid_filenames(
+            updated in "dv-[0-9]{6}",
+            county_name in "[a-z][a-z-]{0,20}",
+            station_id in 0u32..100_000,
+            station_name in "[a-z][a-z-]{0,30}",
+            qcv in "qcv-[01]",
+            year in 1850u32..2100,
+            gz in any::<bool>(),
+        ) {
+            let filename = format!(
+                "midas-open_uk-hourly-weather-obs_{}_{}_{:05}_{}_{}_{}.csv{}",
+                updated,
+                county_name,
+                station_id,
+                station_name,
+                qcv,
+                year,
+                if gz { ".gz" } else { "" }
+            );
+
+            let data_file = FileProperties::new(PathBuf::from("/data").join(filename)).unwrap();
+
+            prop_assert_eq!(data_file.collection_name, "midas-open");
+            prop_assert_eq!(data_file.title, "uk-hourly-weather-obs");
+            prop_assert_eq!(data_file.updated, updated);
+            prop_assert_eq!(data_file.county_name, county_name);
+            prop_assert_eq!(data_file.station_id, station_id);
+            prop_assert_eq!(data_file.station_name, station_name);
+            prop_assert_eq!(data_file.qcv, qcv);
+            prop_assert_eq!(data_file.year, year);
+        }
+
+        #[test]
+        fn prop_never_panics_on_junk(filename in "\\PC*") {
+            let _ = FileProperties::new(PathBuf::from(filename));
+        }
+
+        #[test]
+        fn prop_rejects_junk_without_fields(filename in "[^_/]*") {
+            prop_assert!(FileProperties::new(PathBuf::from(filename)).is_err());
+        }
+    }
 }
@@ -1,43 +1,359 @@
 //! Database handler
 
+use crate::ceda_csv_reader::{CedaCsvReader, Observation, OprType};
 use crate::datastore::DataStore;
 use crate::error::AppError as Error;
-use chrono::NaiveDateTime;
-use sqlx::sqlite::SqlitePoolOptions;
-use sqlx::{Pool, Sqlite};
+use crate::export::{self, Columns};
+use crate::sink::ObservationSink;
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, Utc};
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt, TryStreamExt};
+use indicatif::ProgressBar;
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Connection, FromRow, Pool, Sqlite};
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::Duration;
 
+/// SQLite primary result codes for a database that is busy or locked
+const SQLITE_BUSY: u32 = 5;
+const SQLITE_LOCKED: u32 = 6;
+/// How long a connection waits on a lock held by another connection before failing as busy
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts made at an insert before giving up on a busy or locked database
+const MAX_BUSY_ATTEMPTS: u32 = 5;
+/// Base delay between insert attempts, multiplied by the attempt number
+const BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Tables created by `Database::init`
+pub const TABLES: [&str; 3] = ["stations", "observations", "processed_files"];
+/// Indexes created by `Database::init`
+pub const INDEXES: [&str; 1] = ["idx_observations_station_date_time_id_type"];
+
+/// Schema migrations, where `MIGRATIONS[n]` upgrades a database from schema version `n + 1` to
+/// `n + 2`. Version 1 is the original schema of stations and wind observations. A statement adding
+/// a column that is already there is skipped, so databases from between versions upgrade too.
+const MIGRATIONS: &[&[&str]] = &[
+    // 2: gusts, the observation domain, unmodeled columns and processed files
+    &[
+        "ALTER TABLE observations ADD COLUMN gust_speed REAL",
+        "ALTER TABLE observations ADD COLUMN gust_direction REAL",
+        "ALTER TABLE observations ADD COLUMN gust_time TEXT",
+        "ALTER TABLE observations ADD COLUMN met_domain_name TEXT",
+        "ALTER TABLE observations ADD COLUMN extra JSON",
+        "CREATE TABLE IF NOT EXISTS processed_files (
+            path TEXT PRIMARY KEY,
+            processed_at TEXT NOT NULL
+        )",
+    ],
+    // 3: fractional and negative station heights
+    &[
+        "CREATE TABLE stations_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            midas_station_id INTEGER NOT NULL UNIQUE,
+            historic_county_name TEXT NOT NULL,
+            observation_station TEXT NOT NULL,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            height REAL NOT NULL
+        )",
+        "INSERT INTO stations_new
+            SELECT id, midas_station_id, historic_county_name, observation_station, lat, lon,
+                CAST(height AS REAL)
+            FROM stations",
+        "DROP TABLE stations",
+        "ALTER TABLE stations_new RENAME TO stations",
+    ],
+    // 4: observation id types and versions, unique for a station, time and id type
+    &[
+        "ALTER TABLE observations ADD COLUMN id_type TEXT NOT NULL DEFAULT ''",
+        "ALTER TABLE observations ADD COLUMN version_num INTEGER",
+        "DELETE FROM observations WHERE id NOT IN (
+            SELECT MAX(id) FROM observations GROUP BY midas_station_id, date_time, id_type
+        )",
+        "DROP INDEX IF EXISTS idx_observations_station_date_time",
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_observations_station_date_time_id_type
+            ON observations (midas_station_id, date_time, id_type)",
+    ],
+    // 5: dataset versions
+    &["ALTER TABLE observations ADD COLUMN dataset_version TEXT"],
+    // 6: files processed with observations left out
+    &["ALTER TABLE processed_files ADD COLUMN truncated INTEGER NOT NULL DEFAULT 0"],
+];
+
+/// Version of the schema created by `Database::init`, stored as the database's `user_version`
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64 + 1;
+
+/// An observation as stored in the database
+#[derive(Debug, FromRow, Serialize)]
+pub struct ObservationRow {
+    pub midas_station_id: u32,
+    pub date_time: NaiveDateTime,
+    pub wind_speed: Option<f32>,
+    pub wind_direction: Option<f32>,
+    pub wind_unit_id: Option<u32>,
+    pub wind_opr_type: Option<u32>,
+}
+
+/// A station as stored in the database
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct Station {
+    pub midas_station_id: u32,
+    pub historic_county_name: String,
+    pub observation_station: String,
+    pub lat: f32,
+    pub lon: f32,
+    pub height: f32,
+}
+
+/// Mean radius of the Earth, in kilometres
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+impl Station {
+    /// Great-circle distance from the station to a point, in kilometres
+    pub fn distance_to(&self, lat: f64, lon: f64) -> f64 {
+        let (lat1, lat2) = ((self.lat as f64).to_radians(), lat.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (lon - self.lon as f64).to_radians();
+
+        // Haversine formula
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// An observation as exported, with the operation type decoded
+#[derive(Serialize)]
+struct ObservationJson<'a> {
+    #[serde(flatten)]
+    row: &'a ObservationRow,
+    wind_opr_type_name: Option<String>,
+}
+
+/// Wind statistics for a station over a window
+#[derive(Debug, Default, PartialEq)]
+pub struct WindSummary {
+    /// Number of observations with a wind speed
+    pub count: i64,
+    pub min_speed: Option<f64>,
+    pub max_speed: Option<f64>,
+    pub avg_speed: Option<f64>,
+    /// Centre of the most common direction sector, in degrees, ignoring calms
+    pub prevailing_direction: Option<u32>,
+}
+
+/// Width of the sectors directions are binned into to find the prevailing direction, in degrees
+const DIRECTION_SECTOR: u32 = 30;
+
+/// Conflict clause replacing the stored observation for a station and time
+const UPSERT_OBSERVATION: &str = r#"
+        ON CONFLICT(midas_station_id, date_time, id_type) DO UPDATE SET
+            wind_speed = excluded.wind_speed,
+            wind_direction = excluded.wind_direction,
+            wind_unit_id = excluded.wind_unit_id,
+            wind_opr_type = excluded.wind_opr_type,
+            gust_speed = excluded.gust_speed,
+            gust_direction = excluded.gust_direction,
+            gust_time = excluded.gust_time,
+            met_domain_name = excluded.met_domain_name,
+            version_num = excluded.version_num,
+            dataset_version = excluded.dataset_version,
+            extra = excluded.extra"#;
+
+/// Conflict clause for appending an observation: one from a newer dataset version, or a newer
+/// version within the same dataset version, replaces the stored observation, and anything else is
+/// ignored
+const SUPERSEDE_OBSERVATION: &str = r#"
+        ON CONFLICT(midas_station_id, date_time, id_type) DO UPDATE SET
+            wind_speed = excluded.wind_speed,
+            wind_direction = excluded.wind_direction,
+            wind_unit_id = excluded.wind_unit_id,
+            wind_opr_type = excluded.wind_opr_type,
+            gust_speed = excluded.gust_speed,
+            gust_direction = excluded.gust_direction,
+            gust_time = excluded.gust_time,
+            met_domain_name = excluded.met_domain_name,
+            version_num = excluded.version_num,
+            dataset_version = excluded.dataset_version,
+            extra = excluded.extra
+        WHERE COALESCE(excluded.dataset_version, '') > COALESCE(observations.dataset_version, '')
+            OR (COALESCE(excluded.dataset_version, '') = COALESCE(observations.dataset_version, '')
+                AND COALESCE(excluded.version_num, 0) > COALESCE(observations.version_num, 0))"#;
+
+/// Connections opened by `Database::new`
+pub const DEFAULT_POOL_SIZE: u32 = 5;
+
+/// The SQLite database.
+///
+/// The database is opened in WAL mode with a single-writer model: SQLite allows only one writer
+/// at a time, so all writes go through one connection, and the rest of the pool is read-only
+/// connections that query concurrently without blocking the writer.
 #[derive(Debug)]
 pub struct Database {
-    pool: Pool<Sqlite>,
+    writer: Pool<Sqlite>,
+    reader: Pool<Sqlite>,
+    path: PathBuf,
+    /// Replace stored observations outright, instead of keeping whichever is newest by dataset
+    /// version and then version number
+    upsert: bool,
+    /// Commit a file's observations in transactions of at most this many, instead of all at once
+    commit_every: Option<NonZeroUsize>,
 }
 
 impl Database {
-    pub async fn new() -> Result<Self, Error> {
-        let datastore = DataStore::new();
-        let db_path = datastore.db_dir().join("weather.sqlite");
+    #[allow(dead_code)]
+    pub async fn new(datastore: &DataStore) -> Result<Self, Error> {
+        Database::new_with_pool_size(datastore, DEFAULT_POOL_SIZE).await
+    }
+
+    /// Open the database with `pool_size` connections: one writer and the rest read-only, with
+    /// at least one reader.
+    #[allow(dead_code)]
+    pub async fn new_with_pool_size(datastore: &DataStore, pool_size: u32) -> Result<Self, Error> {
+        Database::new_at(&Database::default_path(datastore), pool_size).await
+    }
+
+    /// Open the database at `path` with `pool_size` connections, creating the file and its
+    /// directory if they are missing.
+    pub async fn new_at(path: &Path, pool_size: u32) -> Result<Self, Error> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|_| Error::FileReadError)?;
+        }
+        let path = path.to_path_buf();
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT);
+
+        // Create the writer first so the database file exists for the readers
+        let writer: Pool<Sqlite> = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+
+        let reader: Pool<Sqlite> = SqlitePoolOptions::new()
+            .max_connections(pool_size.saturating_sub(1).max(1))
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(&path)
+                    .read_only(true)
+                    .busy_timeout(BUSY_TIMEOUT),
+            )
+            .await?;
+
+        let db = Self {
+            writer,
+            reader,
+            path,
+            upsert: false,
+            commit_every: None,
+        };
+        db.migrate().await?;
+
+        Ok(db)
+    }
+
+    /// Open the database read-only with a single connection.
+    ///
+    /// Used by commands that only query, so they don't contend with a running `Process`.
+    #[allow(dead_code)]
+    pub async fn open_readonly(datastore: &DataStore) -> Result<Self, Error> {
+        Database::open_readonly_at(&Database::default_path(datastore)).await
+    }
+
+    /// Open the database at `path` read-only with a single connection.
+    pub async fn open_readonly_at(path: &Path) -> Result<Self, Error> {
+        let path = path.to_path_buf();
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .read_only(true)
+            .busy_timeout(BUSY_TIMEOUT);
 
-        // FIXME: Figure out why it won't create the database
-        // Create the connection pool
-        let database_url = format!("sqlite:{}", db_path.to_str().ok_or(Error::GenericError)?);
         let pool: Pool<Sqlite> = SqlitePoolOptions::new()
-            .max_connections(5)
-            .connect(database_url.as_str())
+            .max_connections(1)
+            .connect_with(options)
             .await?;
 
-        Ok(Self { pool })
+        Ok(Self {
+            writer: pool.clone(),
+            reader: pool,
+            path,
+            upsert: false,
+            commit_every: None,
+        })
+    }
+
+    /// Path to the database in the datastore, used unless another is configured
+    pub fn default_path(datastore: &DataStore) -> PathBuf {
+        datastore.db_dir().join("weather.sqlite")
+    }
+
+    /// Replace observations already stored for a station, time and id type, instead of keeping
+    /// whichever is the newest version
+    pub fn with_upsert(mut self) -> Self {
+        self.upsert = true;
+        self
+    }
+
+    /// Commit every `n` observations of a file, bounding the size of each transaction at the cost
+    /// of a failure leaving a prefix of the file committed
+    pub fn with_commit_every(mut self, n: NonZeroUsize) -> Self {
+        self.commit_every = Some(n);
+        self
+    }
+
+    /// Path to the database file
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
+    /// Drop and recreate the tables in a single transaction, retrying while another connection
+    /// holds a lock
     pub async fn init(&self) -> Result<(), Error> {
+        let mut attempt = 1;
+        loop {
+            match self.recreate_schema().await {
+                Err(e) if is_busy(&e) && attempt < MAX_BUSY_ATTEMPTS => {
+                    tokio::time::sleep(BUSY_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                result => break Ok(result?),
+            }
+        }
+    }
+
+    /// Drop and recreate the tables, so either all of the schema is replaced or none of it
+    async fn recreate_schema(&self) -> Result<(), sqlx::Error> {
+        // Foreign keys can only be switched off outside a transaction
+        let mut conn = self.writer.acquire().await?;
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *conn)
+            .await?;
+        let result = Database::recreate_tables(&mut conn).await;
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut *conn)
+            .await?;
+
+        result
+    }
+
+    async fn recreate_tables(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
         // Drop tables if they exist
         sqlx::query(
             r#"
-        PRAGMA foreign_keys = OFF;
         DROP TABLE IF EXISTS stations;
         DROP TABLE IF EXISTS observations;
-        PRAGMA foreign_keys = ON;
+        DROP TABLE IF EXISTS processed_files;
         "#,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
         // Create tables if they do not exist
@@ -50,7 +366,7 @@ impl Database {
             observation_station TEXT NOT NULL,
             lat REAL NOT NULL,
             lon REAL NOT NULL,
-            height INTEGER NOT NULL
+            height REAL NOT NULL
         );
         CREATE TABLE IF NOT EXISTS observations (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -60,90 +376,1473 @@ impl Database {
             wind_direction REAL,
             wind_unit_id INTEGER,
             wind_opr_type INTEGER,
+            gust_speed REAL,
+            gust_direction REAL,
+            gust_time TEXT,
+            met_domain_name TEXT,
+            id_type TEXT NOT NULL DEFAULT '',
+            version_num INTEGER,
+            dataset_version TEXT,
+            extra JSON,
             FOREIGN KEY (midas_station_id) REFERENCES stations (midas_station_id)
         );
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_observations_station_date_time_id_type
+            ON observations (midas_station_id, date_time, id_type);
+        CREATE TABLE IF NOT EXISTS processed_files (
+            path TEXT PRIMARY KEY,
+            processed_at TEXT NOT NULL,
+            truncated INTEGER NOT NULL DEFAULT 0
+        );
         "#,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    /// Upgrade an existing database to the current schema in place, one migration at a time,
+    /// returning the number of migrations applied. A database without tables is left for `init`,
+    /// and one from before schema versions were recorded is upgraded from version 1.
+    pub async fn migrate(&self) -> Result<usize, Error> {
+        let tables = self.schema_names("table").await?;
+        if !tables.iter().any(|name| name == "stations") {
+            return Ok(0);
+        }
+
+        let mut applied = 0;
+        let mut attempt = 1;
+        loop {
+            match self.apply_next_migration().await {
+                Ok(true) => applied += 1,
+                Ok(false) => return Ok(applied),
+                Err(e) if is_busy(&e) && attempt < MAX_BUSY_ATTEMPTS => {
+                    tokio::time::sleep(BUSY_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Apply the migration after the database's schema version in its own transaction, or
+    /// return `false` if the schema is current
+    async fn apply_next_migration(&self) -> Result<bool, sqlx::Error> {
+        // Foreign keys can only be switched off outside a transaction, and must be off to
+        // rebuild a table other tables refer to
+        let mut conn = self.writer.acquire().await?;
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *conn)
+            .await?;
+        let result = Database::apply_migration(&mut conn).await;
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut *conn)
+            .await?;
+
+        result
+    }
+
+    async fn apply_migration(conn: &mut SqliteConnection) -> Result<bool, sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&mut *tx)
+            .await?;
+        let version = version.max(1);
+        let Some(statements) = MIGRATIONS.get(version as usize - 1) else {
+            return Ok(false);
+        };
+        for statement in *statements {
+            match sqlx::query(statement).execute(&mut *tx).await {
+                Err(e) if is_duplicate_column(&e) => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+        sqlx::query(&format!("PRAGMA user_version = {}", version + 1))
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// The schema version of the database, or 0 if it predates schema versions
+    pub async fn schema_version(&self) -> Result<i64, Error> {
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&self.reader)
+            .await?;
+
+        Ok(version)
+    }
+
+    /// Insert up to `limit` of a file's observations as they are parsed in a single transaction, or
+    /// one every `commit_every` observations, recording the file as processed with the last, and as
+    /// truncated if the limit left observations out. The file's station must already be stored.
+    /// Returns the number of observations inserted.
+    pub async fn insert_record(
+        &self,
+        path: &Path,
+        record: &CedaCsvReader,
+        observations: impl Stream<Item = Result<Observation, Error>>,
+        limit: Option<usize>,
+        progress: &ProgressBar,
+    ) -> Result<usize, Error> {
+        let mut tx = self.writer.begin().await?;
+
+        let mut observations = pin!(observations);
+        let mut written: usize = 0;
+        let mut truncated = false;
+        while let Some(observation) = observations.next().await {
+            if limit == Some(written) {
+                truncated = true;
+                break;
+            }
+            let observation = observation?;
+            if self.upsert {
+                Database::upsert_observation(&mut tx, record.midas_station_id, &observation)
+                    .await?;
+            } else {
+                Database::insert_observation(&mut tx, record.midas_station_id, &observation)
+                    .await?;
+            }
+            progress.inc(1);
+            written += 1;
+
+            if self
+                .commit_every
+                .is_some_and(|n| written.is_multiple_of(n.get()))
+            {
+                tx.commit().await?;
+                tx = self.writer.begin().await?;
+            }
+        }
+
+        Database::mark_processed(&mut tx, path, truncated).await?;
+
+        tx.commit().await?;
+
+        Ok(written)
+    }
+
+    /// Record a file as processed, and whether observations were left out of it
+    async fn mark_processed(
+        conn: &mut SqliteConnection,
+        path: &Path,
+        truncated: bool,
+    ) -> Result<(), Error> {
+        let processed_at = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        sqlx::query(
+            r#"
+        INSERT INTO processed_files (path, processed_at, truncated)
+        VALUES (?, ?, ?)
+        ON CONFLICT(path) DO UPDATE SET
+            processed_at = excluded.processed_at,
+            truncated = excluded.truncated;
+        "#,
+        )
+        .bind(path.to_string_lossy())
+        .bind(processed_at)
+        .bind(truncated)
+        .execute(conn)
         .await?;
 
         Ok(())
     }
 
+    /// Check whether a file has already been processed into the database
+    pub async fn is_processed(&self, path: &Path) -> Result<bool, Error> {
+        let row = sqlx::query("SELECT 1 FROM processed_files WHERE path = ?")
+            .bind(path.to_string_lossy())
+            .fetch_optional(&self.reader)
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Check whether a file was processed with observations left out, e.g. by
+    /// `--limit-per-station`, so not all of them are in the database
+    pub async fn is_truncated(&self, path: &Path) -> Result<bool, Error> {
+        let truncated: Option<(bool,)> =
+            sqlx::query_as("SELECT truncated FROM processed_files WHERE path = ?")
+                .bind(path.to_string_lossy())
+                .fetch_optional(&self.reader)
+                .await?;
+
+        Ok(truncated.is_some_and(|(truncated,)| truncated))
+    }
+
+    /// Insert a station unless it is already stored, retrying while the database is busy
     pub async fn insert_station(
-        &self,
+        conn: &mut SqliteConnection,
         midas_station_id: u32,
         historic_county_name: &str,
         observation_station: &str,
         lat: f32,
         lon: f32,
-        height: u32,
+        height: f32,
     ) -> Result<i64, Error> {
-        let result = sqlx::query(
-            r#"
+        let mut attempt = 1;
+        let result = loop {
+            let result = sqlx::query(
+                r#"
         INSERT INTO stations (midas_station_id, historic_county_name, observation_station, lat, lon, height)
         VALUES (?, ?, ?, ?, ?, ?)
         ON CONFLICT(midas_station_id) DO NOTHING;
         "#
-        )
-            .bind(midas_station_id)
-            .bind(historic_county_name)
-            .bind(observation_station)
-            .bind(lat)
-            .bind(lon)
-            .bind(height)
-            .execute(&self.pool)
-            .await?;
+            )
+                .bind(midas_station_id)
+                .bind(historic_county_name)
+                .bind(observation_station)
+                .bind(lat)
+                .bind(lon)
+                .bind(height)
+                .execute(&mut *conn)
+                .await;
+            match result {
+                Err(e) if is_busy(&e) && attempt < MAX_BUSY_ATTEMPTS => {
+                    tokio::time::sleep(BUSY_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                result => break result?,
+            }
+        };
 
         Ok(result.last_insert_rowid())
     }
 
+    /// Insert an observation. One already stored for the station, time and id type is replaced
+    /// only by a newer version, so the latest version is kept whatever order they arrive in.
     pub async fn insert_observation(
-        &self,
+        conn: &mut SqliteConnection,
         midas_station_id: u32,
-        date_time: NaiveDateTime,
-        wind_speed: Option<f32>,
-        wind_direction: Option<f32>,
-        wind_unit_id: Option<u32>,
-        wind_opr_type: Option<u32>,
+        observation: &Observation,
     ) -> Result<i64, sqlx::Error> {
-        let date_time_str = date_time.format("%Y-%m-%d %H:%M:%S").to_string();
+        Database::write_observation_row(conn, midas_station_id, observation, SUPERSEDE_OBSERVATION)
+            .await
+    }
 
-        let result = sqlx::query(
+    /// Insert an observation, replacing any already stored for the station, time and id type
+    pub async fn upsert_observation(
+        conn: &mut SqliteConnection,
+        midas_station_id: u32,
+        observation: &Observation,
+    ) -> Result<i64, sqlx::Error> {
+        Database::write_observation_row(conn, midas_station_id, observation, UPSERT_OBSERVATION)
+            .await
+    }
+
+    /// Write an observation with the given conflict clause, retrying while the database is busy
+    async fn write_observation_row(
+        conn: &mut SqliteConnection,
+        midas_station_id: u32,
+        observation: &Observation,
+        on_conflict: &str,
+    ) -> Result<i64, sqlx::Error> {
+        let date_time_str = observation
+            .date_time
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let gust_time_str = observation
+            .wind
+            .gust_time
+            .map(|gust_time| gust_time.format("%H:%M").to_string());
+        let extra_str = observation
+            .extra
+            .as_ref()
+            .and_then(|extra| serde_json::to_string(extra).ok());
+
+        let query = format!(
             r#"
-        INSERT INTO observations (midas_station_id, date_time, wind_speed, wind_direction, wind_unit_id, wind_opr_type)
-        VALUES (?, ?, ?, ?, ?, ?)
-        ON CONFLICT(id) DO NOTHING;
-        "#
+        INSERT INTO observations (midas_station_id, date_time, wind_speed, wind_direction, wind_unit_id, wind_opr_type, gust_speed, gust_direction, gust_time, met_domain_name, id_type, version_num, dataset_version, extra)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        {};
+        "#,
+            on_conflict
+        );
+        let mut attempt = 1;
+        loop {
+            let result = sqlx::query(&query)
+                .bind(midas_station_id)
+                .bind(&date_time_str)
+                .bind(observation.wind.speed)
+                .bind(observation.wind.direction)
+                .bind(observation.wind.unit_id)
+                .bind(observation.wind.opr_type)
+                .bind(observation.wind.gust_speed)
+                .bind(observation.wind.gust_direction)
+                .bind(&gust_time_str)
+                .bind(&observation.met_domain_name)
+                .bind(observation.id_type.as_deref().unwrap_or_default())
+                .bind(observation.version_num)
+                .bind(&observation.dataset_version)
+                .bind(&extra_str)
+                .execute(&mut *conn)
+                .await;
+            match result {
+                Err(e) if is_busy(&e) && attempt < MAX_BUSY_ATTEMPTS => {
+                    tokio::time::sleep(BUSY_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                result => return Ok(result?.last_insert_rowid()),
+            }
+        }
+    }
+
+    /// Reclaim unused space and refresh the query planner statistics
+    pub async fn optimize(&self) -> Result<(), Error> {
+        sqlx::query("VACUUM").execute(&self.writer).await?;
+        sqlx::query("ANALYZE").execute(&self.writer).await?;
+
+        Ok(())
+    }
+
+    /// Write a zstd-compressed copy of the database to a directory as `<name>.sqlite.zst`. A
+    /// consistent snapshot is compressed, so the live database is only read.
+    pub async fn archive(&self, dir: &Path, level: i32) -> Result<PathBuf, Error> {
+        let filename = self.path.file_name().ok_or(Error::FileNotFound)?;
+        let mut archive_name = filename.to_owned();
+        archive_name.push(".zst");
+        let archive_path = dir.join(archive_name);
+        let mut snapshot_name = filename.to_owned();
+        snapshot_name.push(".snapshot");
+        let snapshot_path = dir.join(snapshot_name);
+
+        std::fs::create_dir_all(dir).map_err(|e| Error::ArchiveError(e.to_string()))?;
+        let _ = std::fs::remove_file(&snapshot_path);
+        sqlx::query("VACUUM INTO ?")
+            .bind(snapshot_path.to_string_lossy())
+            .execute(&self.reader)
+            .await?;
+
+        let dest = archive_path.clone();
+        let compressed = tokio::task::spawn_blocking(move || {
+            let result = zstd_file(&snapshot_path, &dest, level);
+            let _ = std::fs::remove_file(&snapshot_path);
+            result
+        })
+        .await
+        .map_err(|e| Error::ArchiveError(e.to_string()))?;
+        compressed.map_err(|e| Error::ArchiveError(e.to_string()))?;
+
+        Ok(archive_path)
+    }
+
+    /// Get the names of the schema objects of a type, e.g. "table" or "index"
+    pub async fn schema_names(&self, object_type: &str) -> Result<Vec<String>, Error> {
+        let names: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM sqlite_master WHERE type = ? ORDER BY name")
+                .bind(object_type)
+                .fetch_all(&self.reader)
+                .await?;
+
+        Ok(names.into_iter().map(|(name,)| name).collect())
+    }
+
+    /// Check whether the tables created by `init` are all present
+    pub async fn is_initialized(&self) -> Result<bool, Error> {
+        let tables = self.schema_names("table").await?;
+
+        Ok(TABLES
+            .iter()
+            .all(|table| tables.iter().any(|name| name == table)))
+    }
+
+    /// Get all stored stations, ordered by id
+    #[allow(dead_code)]
+    pub async fn stations(&self) -> Result<Vec<Station>, Error> {
+        let stations = sqlx::query_as(
+            r#"
+        SELECT midas_station_id, historic_county_name, observation_station, lat, lon, height
+        FROM stations
+        ORDER BY midas_station_id;
+        "#,
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        Ok(stations)
+    }
+
+    /// Get the `n` stored stations nearest a point, nearest first, with their distances in
+    /// kilometres
+    pub async fn nearest_stations(
+        &self,
+        lat: f64,
+        lon: f64,
+        n: usize,
+    ) -> Result<Vec<(Station, f64)>, Error> {
+        let mut stations: Vec<(Station, f64)> = self
+            .stations()
+            .await?
+            .into_iter()
+            .map(|station| {
+                let distance = station.distance_to(lat, lon);
+                (station, distance)
+            })
+            .collect();
+        stations.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        stations.truncate(n);
+
+        Ok(stations)
+    }
+
+    /// Get the timestamp of the newest observation stored for a station. A stored timestamp that
+    /// can't be read is an error, rather than no observations.
+    pub async fn latest_observation(
+        &self,
+        midas_station_id: u32,
+    ) -> Result<Option<NaiveDateTime>, Error> {
+        let (latest,): (Option<NaiveDateTime>,) =
+            sqlx::query_as("SELECT MAX(date_time) FROM observations WHERE midas_station_id = ?")
+                .bind(midas_station_id)
+                .fetch_one(&self.reader)
+                .await?;
+
+        Ok(latest)
+    }
+
+    /// Count the observations stored for a station in a calendar year
+    pub async fn observation_count_in_year(
+        &self,
+        midas_station_id: u32,
+        year: u32,
+    ) -> Result<i64, Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+        SELECT COUNT(*) FROM observations
+        WHERE midas_station_id = ? AND date_time >= ? AND date_time < ?;
+        "#,
         )
+        .bind(midas_station_id)
+        .bind(format!("{:04}-01-01", year))
+        .bind(format!("{:04}-01-01", year + 1))
+        .fetch_one(&self.reader)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Count the files recorded as processed
+    pub async fn processed_file_count(&self) -> Result<i64, Error> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM processed_files")
+            .fetch_one(&self.reader)
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Summarise a station's wind speeds and prevailing direction between two dates, inclusive
+    pub async fn wind_summary(
+        &self,
+        midas_station_id: u32,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<WindSummary, Error> {
+        let from = from.format("%Y-%m-%d").to_string();
+        let until = (to + Days::new(1)).format("%Y-%m-%d").to_string();
+
+        let (count, min_speed, max_speed, avg_speed): (i64, Option<f64>, Option<f64>, Option<f64>) =
+            sqlx::query_as(
+                r#"
+        SELECT COUNT(wind_speed), MIN(wind_speed), MAX(wind_speed), AVG(wind_speed)
+        FROM observations
+        WHERE midas_station_id = ? AND date_time >= ? AND date_time < ?;
+        "#,
+            )
             .bind(midas_station_id)
-            .bind(date_time_str)
-            .bind(wind_speed)
-            .bind(wind_direction)
-            .bind(wind_unit_id)
-            .bind(wind_opr_type)
-            .execute(&self.pool)
+            .bind(&from)
+            .bind(&until)
+            .fetch_one(&self.reader)
             .await?;
 
-        Ok(result.last_insert_rowid())
+        // Direction 0 is reported for calm conditions
+        let prevailing_direction: Option<(u32,)> = sqlx::query_as(
+            r#"
+        SELECT (CAST(wind_direction AS INTEGER) + ?1 / 2) % 360 / ?1 * ?1 AS sector
+        FROM observations
+        WHERE midas_station_id = ?2 AND date_time >= ?3 AND date_time < ?4
+            AND wind_direction > 0
+        GROUP BY sector
+        ORDER BY COUNT(*) DESC, sector
+        LIMIT 1;
+        "#,
+        )
+        .bind(DIRECTION_SECTOR)
+        .bind(midas_station_id)
+        .bind(&from)
+        .bind(&until)
+        .fetch_optional(&self.reader)
+        .await?;
+
+        Ok(WindSummary {
+            count,
+            min_speed,
+            max_speed,
+            avg_speed,
+            prevailing_direction: prevailing_direction.map(|(sector,)| sector),
+        })
+    }
+
+    /// Export the selected columns of all observations as newline-delimited JSON, one object per
+    /// observation. The decoded operation type is included with `wind_opr_type`.
+    ///
+    /// Returns the number of observations written.
+    pub async fn export_ndjson<W: Write>(
+        &self,
+        writer: &mut W,
+        columns: &Columns,
+    ) -> Result<usize, Error> {
+        let mut rows = self.observation_rows();
+        let mut count = 0;
+
+        while let Some(row) = rows.try_next().await? {
+            let json = ObservationJson {
+                row: &row,
+                wind_opr_type_name: row
+                    .wind_opr_type
+                    .map(|code| OprType::from(code).to_string()),
+            };
+            let mut json =
+                serde_json::to_value(&json).map_err(|e| Error::ExportError(e.to_string()))?;
+            if let Some(object) = json.as_object_mut() {
+                object.retain(|key, _| match key.as_str() {
+                    "wind_opr_type_name" => columns.contains("wind_opr_type"),
+                    key => columns.contains(key),
+                });
+            }
+            serde_json::to_writer(&mut *writer, &json)
+                .map_err(|e| Error::ExportError(e.to_string()))?;
+            writer
+                .write_all(b"\n")
+                .map_err(|e| Error::ExportError(e.to_string()))?;
+            count += 1;
+        }
+        writer
+            .flush()
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+
+        Ok(count)
+    }
+
+    /// Stream all observations ordered by station and time
+    fn observation_rows(&self) -> BoxStream<'_, Result<ObservationRow, sqlx::Error>> {
+        sqlx::query_as::<_, ObservationRow>(
+            r#"
+        SELECT midas_station_id, date_time, wind_speed, wind_direction, wind_unit_id, wind_opr_type
+        FROM observations
+        ORDER BY midas_station_id, date_time;
+        "#,
+        )
+        .fetch(&self.reader)
+    }
+
+    /// Export the selected columns of all observations as a Parquet dataset partitioned by
+    /// station and year.
+    ///
+    /// Returns the number of partitions written.
+    pub async fn export_parquet(&self, dir: &Path, columns: &Columns) -> Result<usize, Error> {
+        let mut rows = self.observation_rows();
+
+        let mut partition: Vec<ObservationRow> = Vec::new();
+        let mut partitions = 0;
+
+        while let Some(row) = rows.try_next().await? {
+            if let Some(last) = partition.last() {
+                if last.midas_station_id != row.midas_station_id
+                    || last.date_time.year() != row.date_time.year()
+                {
+                    let path =
+                        export::partition_path(dir, last.midas_station_id, last.date_time.year());
+                    export::write_parquet(&path, &partition, columns)?;
+                    partitions += 1;
+                    partition.clear();
+                }
+            }
+            partition.push(row);
+        }
+
+        if let Some(last) = partition.last() {
+            let path = export::partition_path(dir, last.midas_station_id, last.date_time.year());
+            export::write_parquet(&path, &partition, columns)?;
+            partitions += 1;
+        }
+
+        Ok(partitions)
+    }
+}
+
+impl ObservationSink for Database {
+    async fn write_station(&mut self, record: &CedaCsvReader) -> Result<(), Error> {
+        let mut conn = self.writer.acquire().await?;
+        Database::insert_station(
+            &mut conn,
+            record.midas_station_id,
+            &record.historic_county_name,
+            &record.observation_station,
+            record.location.lat,
+            record.location.lon,
+            record.height,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn write_observation(
+        &mut self,
+        midas_station_id: u32,
+        observation: &Observation,
+    ) -> Result<(), Error> {
+        let mut conn = self.writer.acquire().await?;
+        if self.upsert {
+            Database::upsert_observation(&mut conn, midas_station_id, observation).await?;
+        } else {
+            Database::insert_observation(&mut conn, midas_station_id, observation).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self, path: &Path) -> Result<(), Error> {
+        let mut conn = self.writer.acquire().await?;
+        Database::mark_processed(&mut conn, path, false).await
+    }
+
+    async fn is_processed(&self, path: &Path) -> Result<bool, Error> {
+        Database::is_processed(self, path).await
+    }
+
+    /// Write the whole file in one transaction rather than row by row
+    async fn write_record(
+        &mut self,
+        path: &Path,
+        record: &CedaCsvReader,
+        observations: impl Stream<Item = Result<Observation, Error>>,
+        limit: Option<usize>,
+        progress: &ProgressBar,
+    ) -> Result<usize, Error> {
+        self.insert_record(path, record, observations, limit, progress)
+            .await
+    }
+
+    async fn latest_observation(
+        &self,
+        midas_station_id: u32,
+    ) -> Result<Option<NaiveDateTime>, Error> {
+        Database::latest_observation(self, midas_station_id).await
+    }
+}
+
+/// Compress `source` to `dest` with zstd, writing through a partial file so a failed write leaves
+/// no archive
+fn zstd_file(source: &Path, dest: &Path, level: i32) -> std::io::Result<()> {
+    let part_path = dest.with_extension("zst.part");
+    let result = (|| {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(source)?);
+        let file = std::fs::File::create(&part_path)?;
+        let mut encoder = zstd::Encoder::new(file, level)?;
+        std::io::copy(&mut reader, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        std::fs::rename(&part_path, dest)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part_path);
+    }
+
+    result
+}
+
+/// Check whether an error is a transient busy/locked condition that is worth retrying
+pub fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::DatabaseConnectionError(e) => is_busy(e),
+        _ => false,
+    }
+}
+
+/// Check whether a statement failed because it added a column that is already there
+fn is_duplicate_column(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(e) => e.message().starts_with("duplicate column name"),
+        _ => false,
+    }
+}
+
+/// Check whether a query failed because the database was busy or locked
+fn is_busy(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(e) => e
+            .code()
+            .and_then(|code| code.parse::<u32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)),
+        _ => false,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ceda_csv_reader::WindObservation;
+    use futures::stream;
+
+    /// An initialised database held in memory
+    async fn in_memory() -> Database {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let db = Database {
+            writer: pool.clone(),
+            reader: pool,
+            path: PathBuf::from(":memory:"),
+            upsert: false,
+            commit_every: None,
+        };
+        db.init().await.unwrap();
+
+        db
+    }
+
+    fn observation(date_time: &str, speed: f32, direction: f32) -> Observation {
+        Observation {
+            date_time: NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d %H:%M:%S").unwrap(),
+            _id: 1,
+            wind: WindObservation {
+                speed: Some(speed),
+                direction: Some(direction),
+                ..Default::default()
+            },
+            met_domain_name: None,
+            id_type: None,
+            version_num: None,
+            dataset_version: None,
+            extra: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_is_initialized() {
+        let db = in_memory().await;
+        assert!(db.is_initialized().await.unwrap());
+
+        sqlx::query("DROP TABLE processed_files")
+            .execute(&db.writer)
+            .await
+            .unwrap();
+        assert!(!db.is_initialized().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_wind_summary() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        for observation in [
+            observation("1994-10-01 00:00:00", 4.0, 170.0),
+            observation("1994-10-01 01:00:00", 6.0, 180.0),
+            observation("1994-10-01 02:00:00", 2.0, 270.0),
+            observation("1994-10-02 00:00:00", 20.0, 90.0),
+        ] {
+            Database::insert_observation(&mut conn, 1448, &observation)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        let day = NaiveDate::from_ymd_opt(1994, 10, 1).unwrap();
+        let summary = db.wind_summary(1448, day, day).await.unwrap();
+
+        assert_eq!(
+            summary,
+            WindSummary {
+                count: 3,
+                min_speed: Some(2.0),
+                max_speed: Some(6.0),
+                avg_speed: Some(4.0),
+                prevailing_direction: Some(180),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_extra_columns() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        let mut extra = serde_json::Map::new();
+        extra.insert("air_temperature".to_string(), "10.2".into());
+        let observation = Observation {
+            extra: Some(extra),
+            ..observation("1994-10-01 00:00:00", 4.0, 170.0)
+        };
+        Database::insert_observation(&mut conn, 1448, &observation)
+            .await
+            .unwrap();
+
+        let (air_temperature,): (String,) =
+            sqlx::query_as("SELECT extra ->> '$.air_temperature' FROM observations")
+                .fetch_one(&mut *conn)
+                .await
+                .unwrap();
+
+        assert_eq!(air_temperature, "10.2");
+    }
+
+    #[tokio::test]
+    async fn test_upsert_observation() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        Database::insert_observation(
+            &mut conn,
+            1448,
+            &observation("1994-10-01 00:00:00", 4.0, 170.0),
+        )
+        .await
+        .unwrap();
+
+        let duplicate = observation("1994-10-01 00:00:00", 6.0, 180.0);
+        Database::insert_observation(&mut conn, 1448, &duplicate)
+            .await
+            .unwrap();
+
+        let rows: Vec<(f64,)> = sqlx::query_as("SELECT wind_speed FROM observations")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(4.0,)]);
+
+        Database::upsert_observation(&mut conn, 1448, &duplicate)
+            .await
+            .unwrap();
+
+        let rows: Vec<(f64,)> = sqlx::query_as("SELECT wind_speed FROM observations")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(6.0,)]);
+    }
+
+    #[tokio::test]
+    async fn test_newer_version_supersedes() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        let version = |speed: f32, version_num: u32| Observation {
+            id_type: Some("DCNN".to_string()),
+            version_num: Some(version_num),
+            ..observation("1994-10-01 00:00:00", speed, 180.0)
+        };
+
+        for observation in [version(4.0, 0), version(6.0, 1), version(5.0, 0)] {
+            Database::insert_observation(&mut conn, 1448, &observation)
+                .await
+                .unwrap();
+        }
+        let other_id_type = Observation {
+            id_type: Some("WMO".to_string()),
+            ..version(3.0, 0)
+        };
+        Database::insert_observation(&mut conn, 1448, &other_id_type)
+            .await
+            .unwrap();
+
+        let rows: Vec<(String, f64, i64)> = sqlx::query_as(
+            "SELECT id_type, wind_speed, version_num FROM observations ORDER BY id_type",
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![("DCNN".to_string(), 6.0, 1), ("WMO".to_string(), 3.0, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_newer_dataset_version_supersedes() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        let version = |speed: f32, dataset_version: &str, version_num: u32| Observation {
+            version_num: Some(version_num),
+            dataset_version: Some(dataset_version.to_string()),
+            ..observation("1994-10-01 00:00:00", speed, 180.0)
+        };
+
+        for observation in [
+            version(4.0, "202407", 1),
+            version(6.0, "202507", 0),
+            version(5.0, "202407", 2),
+        ] {
+            Database::insert_observation(&mut conn, 1448, &observation)
+                .await
+                .unwrap();
+        }
+
+        let rows: Vec<(f64, String)> =
+            sqlx::query_as("SELECT wind_speed, dataset_version FROM observations")
+                .fetch_all(&mut *conn)
+                .await
+                .unwrap();
+        assert_eq!(rows, vec![(6.0, "202507".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_export_ndjson_columns() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        let observation = Observation {
+            wind: WindObservation {
+                opr_type: Some(1),
+                ..observation("1994-10-01 00:00:00", 4.0, 180.0).wind
+            },
+            ..observation("1994-10-01 00:00:00", 4.0, 180.0)
+        };
+        Database::insert_observation(&mut conn, 1448, &observation)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let mut out = Vec::new();
+        let columns =
+            Columns::select(&["date_time".to_string(), "wind_opr_type".to_string()]).unwrap();
+        db.export_ndjson(&mut out, &columns).await.unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(keys, ["date_time", "wind_opr_type", "wind_opr_type_name"]);
+    }
+
+    #[tokio::test]
+    async fn test_stations() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        Database::insert_station(
+            &mut conn,
+            144,
+            "aberdeenshire",
+            "corgarff",
+            57.15,
+            -3.25,
+            400.0,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        let stations = db.stations().await.unwrap();
+
+        assert_eq!(
+            stations,
+            vec![
+                Station {
+                    midas_station_id: 144,
+                    historic_county_name: "aberdeenshire".to_string(),
+                    observation_station: "corgarff".to_string(),
+                    lat: 57.15,
+                    lon: -3.25,
+                    height: 400.0,
+                },
+                Station {
+                    midas_station_id: 1448,
+                    historic_county_name: "antrim".to_string(),
+                    observation_station: "portglenone".to_string(),
+                    lat: 54.865,
+                    lon: -6.458,
+                    height: 64.0,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_record_marks_truncated_files() {
+        let mut db = in_memory().await;
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+        let mut record = CedaCsvReader::new(path.clone()).unwrap();
+        db.write_station(&record).await.unwrap();
+        let observations = std::mem::take(&mut record.observations);
+        let count = observations.len();
+
+        let written = db
+            .insert_record(
+                &path,
+                &record,
+                stream::iter(observations.into_iter().map(Ok)),
+                Some(count - 1),
+                &ProgressBar::hidden(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(written, count - 1);
+        assert!(db.is_processed(&path).await.unwrap());
+        assert!(db.is_truncated(&path).await.unwrap());
+
+        let observations = CedaCsvReader::new(path.clone()).unwrap().observations;
+        let written = db
+            .insert_record(
+                &path,
+                &record,
+                stream::iter(observations.into_iter().map(Ok)),
+                Some(count),
+                &ProgressBar::hidden(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(written, count);
+        assert!(!db.is_truncated(&path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_commit_every() {
+        let mut db = in_memory()
+            .await
+            .with_commit_every(NonZeroUsize::new(2).unwrap());
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(
+            "tests/fixtures/midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_src-id.csv",
+        );
+        let mut record = CedaCsvReader::new(path.clone()).unwrap();
+        db.write_station(&record).await.unwrap();
+        // Fail the file on an extra last observation, after the first commit
+        sqlx::query(
+            r#"
+        CREATE TRIGGER fail_observation BEFORE INSERT ON observations
+        WHEN NEW.wind_speed = 1.0
+        BEGIN SELECT RAISE(ABORT, 'failed'); END;
+        "#,
+        )
+        .execute(&db.writer)
+        .await
+        .unwrap();
+        record
+            .observations
+            .push(observation("1994-10-02 00:00:00", 1.0, 90.0));
+        let observations = std::mem::take(&mut record.observations);
+
+        let result = db
+            .insert_record(
+                &path,
+                &record,
+                stream::iter(observations.into_iter().map(Ok)),
+                None,
+                &ProgressBar::hidden(),
+            )
+            .await;
+
+        assert!(result.is_err());
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM observations")
+            .fetch_one(&db.reader)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(!db.is_processed(&path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_nearest_stations() {
+        let db = in_memory().await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        Database::insert_station(
+            &mut conn,
+            144,
+            "aberdeenshire",
+            "corgarff",
+            57.15,
+            -3.25,
+            400.0,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        // Belfast
+        let nearest = db.nearest_stations(54.597, -5.930, 1).await.unwrap();
+
+        assert_eq!(nearest.len(), 1);
+        assert_eq!(nearest[0].0.midas_station_id, 1448);
+        assert!((nearest[0].1 - 45.1).abs() < 0.1);
+    }
+
+    #[tokio::test]
+    async fn test_latest_observation() {
+        let db = in_memory().await;
+        assert_eq!(db.latest_observation(1448).await.unwrap(), None);
+
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        for observation in [
+            observation("1994-10-01 01:00:00", 6.0, 180.0),
+            observation("1994-10-01 00:00:00", 4.0, 170.0),
+        ] {
+            Database::insert_observation(&mut conn, 1448, &observation)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        assert_eq!(db.observation_count_in_year(1448, 1994).await.unwrap(), 2);
+        assert_eq!(db.observation_count_in_year(1448, 1995).await.unwrap(), 0);
+        assert_eq!(
+            db.latest_observation(1448).await.unwrap(),
+            Some(
+                NaiveDateTime::parse_from_str("1994-10-01 01:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+            )
+        );
+
+        sqlx::query("INSERT INTO observations (midas_station_id, date_time) VALUES (1448, 'late')")
+            .execute(&db.writer)
+            .await
+            .unwrap();
+        assert!(db.latest_observation(1448).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_new_at() {
+        let path = std::env::temp_dir()
+            .join("rust-ceda-db-path")
+            .join("weather.sqlite");
+        let db = Database::new_at(&path, 2).await.unwrap();
+        db.init().await.unwrap();
+
+        assert_eq!(db.path(), path);
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_insert_retries_while_busy() {
+        use sqlx::{ConnectOptions, Connection};
+
+        let path = std::env::temp_dir().join("rust-ceda-busy.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::new_at(&path, 2).await.unwrap();
+        db.init().await.unwrap();
+
+        // Hold the write lock on one connection, and fail immediately when busy on another so
+        // only the retries can wait for the lock
+        let options = SqliteConnectOptions::new().filename(&path);
+        let mut locker = options.connect().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut locker)
+            .await
+            .unwrap();
+        let mut conn = options
+            .busy_timeout(Duration::ZERO)
+            .connect()
+            .await
+            .unwrap();
+
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sqlx::query("COMMIT").execute(&mut locker).await.unwrap();
+            locker.close().await.unwrap();
+        });
+
+        let result = Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await;
+        release.await.unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(db.stations().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_init() {
+        let path = std::env::temp_dir().join("rust-ceda-concurrent-init.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let first = Database::new_at(&path, 2).await.unwrap();
+        let second = Database::new_at(&path, 2).await.unwrap();
+
+        let (a, b) = tokio::join!(first.init(), second.init());
+
+        assert!(a.is_ok(), "{:?}", a);
+        assert!(b.is_ok(), "{:?}", b);
+        assert!(first.is_initialized().await.unwrap());
+        let indexes = first.schema_names("index").await.unwrap();
+        assert!(INDEXES
+            .iter()
+            .all(|index| indexes.iter().any(|name| name == index)));
+    }
+
+    #[tokio::test]
+    async fn test_archive() {
+        let dir = std::env::temp_dir().join("rust-ceda-archive");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("weather.sqlite");
+        let db = Database::new_at(&path, 2).await.unwrap();
+        db.init().await.unwrap();
+        let mut conn = db.writer.acquire().await.unwrap();
+        Database::insert_station(
+            &mut conn,
+            1448,
+            "antrim",
+            "portglenone",
+            54.865,
+            -6.458,
+            64.0,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        let archive_path = db.archive(&dir.join("archive"), 3).await.unwrap();
+
+        assert_eq!(archive_path, dir.join("archive/weather.sqlite.zst"));
+        assert_eq!(
+            std::fs::read_dir(dir.join("archive")).unwrap().count(),
+            1,
+            "only the archive is left"
+        );
+        let restored_path = dir.join("restored.sqlite");
+        let archive = std::fs::File::open(&archive_path).unwrap();
+        let mut restored = std::fs::File::create(&restored_path).unwrap();
+        zstd::stream::copy_decode(archive, &mut restored).unwrap();
+        let restored = Database::open_readonly_at(&restored_path).await.unwrap();
+        assert_eq!(restored.stations().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_from_v1() {
+        use sqlx::{ConnectOptions, Connection};
+
+        let path = std::env::temp_dir().join("rust-ceda-migrate.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let mut conn = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+        CREATE TABLE stations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            midas_station_id INTEGER NOT NULL UNIQUE,
+            historic_county_name TEXT NOT NULL,
+            observation_station TEXT NOT NULL,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            height INTEGER NOT NULL
+        );
+        CREATE TABLE observations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            midas_station_id INTEGER NOT NULL,
+            date_time TEXT NOT NULL,
+            wind_speed REAL,
+            wind_direction REAL,
+            wind_unit_id INTEGER,
+            wind_opr_type INTEGER,
+            FOREIGN KEY (midas_station_id) REFERENCES stations (midas_station_id)
+        );
+        CREATE INDEX idx_observations_station_date_time
+            ON observations (midas_station_id, date_time);
+        INSERT INTO stations (midas_station_id, historic_county_name, observation_station, lat, lon, height)
+            VALUES (1448, 'antrim', 'portglenone', 54.865, -6.458, 64);
+        INSERT INTO observations (midas_station_id, date_time, wind_speed, wind_direction)
+            VALUES (1448, '1994-10-01 00:00:00', 4.0, 180.0),
+                (1448, '1994-10-01 00:00:00', 5.0, 190.0),
+                (1448, '1994-10-01 01:00:00', 6.0, 200.0);
+        "#,
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        conn.close().await.unwrap();
+
+        let db = Database::new_at(&path, 2).await.unwrap();
+
+        assert_eq!(db.schema_version().await.unwrap(), SCHEMA_VERSION);
+        assert!(db.is_initialized().await.unwrap());
+        let mut indexes = db.schema_names("index").await.unwrap();
+        indexes.retain(|name| !name.starts_with("sqlite_"));
+        assert_eq!(indexes, INDEXES);
+        assert_eq!(db.stations().await.unwrap()[0].height, 64.0);
+        let rows: Vec<(String, f64)> =
+            sqlx::query_as("SELECT id_type, wind_speed FROM observations ORDER BY date_time")
+                .fetch_all(&db.reader)
+                .await
+                .unwrap();
+        assert_eq!(rows, vec![("".to_string(), 5.0), ("".to_string(), 6.0)]);
+
+        // The migrated schema takes new observations, and is not migrated again
+        let mut conn = db.writer.acquire().await.unwrap();
+        let newer = Observation {
+            dataset_version: Some("202507".to_string()),
+            ..observation("1994-10-01 00:00:00", 7.0, 180.0)
+        };
+        Database::insert_observation(&mut conn, 1448, &newer)
+            .await
+            .unwrap();
+        drop(conn);
+        assert_eq!(db.migrate().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_init_sets_schema_version() {
+        let db = in_memory().await;
+
+        assert_eq!(db.schema_version().await.unwrap(), SCHEMA_VERSION);
+        assert_eq!(db.migrate().await.unwrap(), 0);
+    }
 
     #[tokio::test]
     async fn test_new() {
-        let db = Database::new().await;
+        let db = Database::new(&DataStore::new()).await;
 
         assert!(db.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_open_readonly() {
+        let _ = Database::new(&DataStore::new()).await.unwrap();
+        let db = Database::open_readonly(&DataStore::new()).await.unwrap();
+        let result = sqlx::query("CREATE TABLE readonly_check (id INTEGER)")
+            .execute(&db.writer)
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_observation_row_json() {
+        let row = ObservationRow {
+            midas_station_id: 1448,
+            date_time: NaiveDateTime::parse_from_str("1994-10-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            wind_speed: Some(4.0),
+            wind_direction: None,
+            wind_unit_id: None,
+            wind_opr_type: Some(1),
+        };
+
+        let json = serde_json::to_string(&row).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"midas_station_id":1448,"date_time":"1994-10-01T00:00:00","wind_speed":4.0,"wind_direction":null,"wind_unit_id":null,"wind_opr_type":1}"#
+        );
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_init() {
-        let db = Database::new().await.unwrap();
+        let db = Database::new(&DataStore::new()).await.unwrap();
         let result = db.init().await;
 
         assert!(result.is_ok());
@@ -152,9 +1851,11 @@ mod tests {
     #[tokio::test]
     #[ignore]
     async fn test_insert_station() {
-        let db = Database::new().await.unwrap();
+        let db = Database::new(&DataStore::new()).await.unwrap();
+        let mut conn = db.writer.acquire().await.unwrap();
         // let _ = db.init().await;
-        let result = db.insert_station(1, "Dublin", "DUB", 10.0, 180.0, 1).await;
+        let result =
+            Database::insert_station(&mut conn, 1, "Dublin", "DUB", 10.0, 180.0, 1.0).await;
 
         println!("{:?}", result);
 
@@ -164,14 +1865,28 @@ mod tests {
     #[tokio::test]
     #[ignore]
     async fn test_insert_observation() {
-        let db = Database::new().await.unwrap();
-        let datetime =
-            NaiveDateTime::parse_from_str("2021-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let db = Database::new(&DataStore::new()).await.unwrap();
+        let observation = Observation {
+            date_time: NaiveDateTime::parse_from_str("2021-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            _id: 1,
+            wind: WindObservation {
+                speed: Some(10.0),
+                direction: Some(180.0),
+                unit_id: Some(1),
+                opr_type: Some(1),
+                ..Default::default()
+            },
+            met_domain_name: None,
+            id_type: None,
+            version_num: None,
+            dataset_version: None,
+            extra: None,
+        };
         let _ = db.init().await;
-        let _ = db.insert_station(1, "Dublin", "DUB", 10.0, 180.0, 1).await;
-        let result = db
-            .insert_observation(1, datetime, Some(10.0), Some(180.0), Some(1), Some(1))
-            .await;
+        let mut conn = db.writer.acquire().await.unwrap();
+        let _ = Database::insert_station(&mut conn, 1, "Dublin", "DUB", 10.0, 180.0, 1.0).await;
+        let result = Database::insert_observation(&mut conn, 1, &observation).await;
 
         println!("{:?}", result);
 
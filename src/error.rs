@@ -8,15 +8,35 @@ pub enum AppError {
     #[error("A generic error occurred")]
     GenericError,
 
+    // Config errors
+    #[error("Config error: {0}")]
+    ConfigError(String),
+
     // CEDA API errors
     #[error("Document Fetch error: {0}")]
     DocumentFetchError(String),
+    #[error("No links matching {selector} found at {url}; the page markup may have changed")]
+    NoLinksFound { url: String, selector: String },
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("Expected a data file but got {0}; the access token may have expired")]
+    UnexpectedContentType(String),
+    #[error("Timed out after {0:?}")]
+    StationTimeout(std::time::Duration),
+    #[error("CEDA rejected the access token ({0}); check CEDA_ACCESS_TOKEN or --access-token")]
+    AccessTokenRejected(String),
+    #[error("No access token; set CEDA_ACCESS_TOKEN or pass --access-token")]
+    MissingAccessToken,
 
     // File errors
     #[error("File not found")]
     FileNotFound,
     #[error("File read error")]
     FileReadError,
+    #[error("File write error: {0}")]
+    FileWriteError(String),
+    #[error("Not a MIDAS data filename: {0}")]
+    InvalidFilename(String),
 
     // CSV Parse Errors
     #[error("CSV Observation Station parsing error")]
@@ -37,11 +57,41 @@ pub enum AppError {
     CsvDateParseError(#[from] chrono::ParseError),
     #[error("CSV Reading Column not found: {0}")]
     ColumnNotFound(String),
+    #[error("CSV invalid {0} value: {1}")]
+    CsvInvalidValueError(String, String),
+    #[error("CSV {column} field parse error: {value:?}")]
+    CsvFieldParseError { column: String, value: String },
+    #[error("Observation at {date_time} is outside the file's valid dates {from} to {to}")]
+    ObservationOutOfRange {
+        date_time: String,
+        from: String,
+        to: String,
+    },
     #[error("CSV Reading QCV1 Folder Not Found error")]
     QCV1NotFound,
+    #[error("Capability file not found at {0}")]
+    CapabilityNotFound(String),
+
+    // Export errors
+    #[error("Export error: {0}")]
+    ExportError(String),
+
+    // Archive errors
+    #[error("Archive error: {0}")]
+    ArchiveError(String),
+
+    // Metrics errors
+    #[error("Metrics file write error: {0}")]
+    MetricsWriteError(String),
+
+    // Check errors
+    #[error("{0} health checks failed")]
+    CheckFailed(usize),
 
     // Database errors
-    #[error("Database connection error")]
+    #[error("Database connection error: {0}")]
     DatabaseConnectionError(#[from] sqlx::Error),
+    #[error("Database is not initialized, run `process --init` to create it")]
+    DatabaseNotInitialized,
 
 }
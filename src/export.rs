@@ -0,0 +1,224 @@
+//! Writes observations to file formats suited to analysis.
+
+use crate::db::ObservationRow;
+use crate::error::AppError as Error;
+use arrow_array::{ArrayRef, Float32Array, RecordBatch, TimestampSecondArray, UInt32Array};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File format of an export
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// A Parquet dataset partitioned by station and year
+    #[default]
+    Parquet,
+    /// Newline-delimited JSON, one observation per line
+    Ndjson,
+}
+
+/// Columns of an exported observation
+pub const COLUMNS: [&str; 6] = [
+    "midas_station_id",
+    "date_time",
+    "wind_speed",
+    "wind_direction",
+    "wind_unit_id",
+    "wind_opr_type",
+];
+
+/// The columns to export, in the order they are written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Columns(Vec<&'static str>);
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self(COLUMNS.to_vec())
+    }
+}
+
+impl Columns {
+    /// Select columns by name, accepting the MIDAS `ob_time` for `date_time`. An unknown name is
+    /// an error listing the known ones.
+    pub fn select(names: &[String]) -> Result<Self, Error> {
+        let mut columns = Vec::new();
+        for name in names {
+            let name = match name.trim() {
+                "ob_time" => "date_time",
+                name => name,
+            };
+            let column = COLUMNS
+                .iter()
+                .find(|column| **column == name)
+                .ok_or_else(|| {
+                    Error::ExportError(format!(
+                        "unknown column `{}`, expected one of: {}",
+                        name,
+                        COLUMNS.join(", ")
+                    ))
+                })?;
+            if !columns.contains(column) {
+                columns.push(*column);
+            }
+        }
+        if columns.is_empty() {
+            return Err(Error::ExportError("no columns selected".to_string()));
+        }
+
+        Ok(Self(columns))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(&name)
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        &self.0
+    }
+}
+
+/// The Arrow field of an exported column
+fn field(column: &str) -> Field {
+    match column {
+        "midas_station_id" => Field::new(column, DataType::UInt32, false),
+        "date_time" => Field::new(column, DataType::Timestamp(TimeUnit::Second, None), false),
+        "wind_speed" | "wind_direction" => Field::new(column, DataType::Float32, true),
+        _ => Field::new(column, DataType::UInt32, true),
+    }
+}
+
+/// The values of an exported column
+fn column_array(column: &str, rows: &[ObservationRow]) -> ArrayRef {
+    match column {
+        "midas_station_id" => Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|row| row.midas_station_id),
+        )),
+        "date_time" => Arc::new(TimestampSecondArray::from_iter_values(
+            rows.iter().map(|row| row.date_time.and_utc().timestamp()),
+        )),
+        "wind_speed" => Arc::new(Float32Array::from_iter(
+            rows.iter().map(|row| row.wind_speed),
+        )),
+        "wind_direction" => Arc::new(Float32Array::from_iter(
+            rows.iter().map(|row| row.wind_direction),
+        )),
+        "wind_unit_id" => Arc::new(UInt32Array::from_iter(
+            rows.iter().map(|row| row.wind_unit_id),
+        )),
+        _ => Arc::new(UInt32Array::from_iter(
+            rows.iter().map(|row| row.wind_opr_type),
+        )),
+    }
+}
+
+/// Path of the Parquet file for a station-year partition within a dataset directory
+pub fn partition_path(dir: &Path, midas_station_id: u32, year: i32) -> PathBuf {
+    dir.join(format!("station={:05}", midas_station_id))
+        .join(format!("year={}", year))
+        .join("observations.parquet")
+}
+
+/// Write the selected columns of observations to a Parquet file, creating parent directories as
+/// needed
+pub fn write_parquet(path: &Path, rows: &[ObservationRow], columns: &Columns) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::ExportError(e.to_string()))?;
+    }
+
+    let schema = Arc::new(Schema::new(
+        columns
+            .names()
+            .iter()
+            .map(|column| field(column))
+            .collect::<Vec<_>>(),
+    ));
+    let arrays: Vec<ArrayRef> = columns
+        .names()
+        .iter()
+        .map(|column| column_array(column, rows))
+        .collect();
+    let batch = RecordBatch::try_new(schema.clone(), arrays)
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+
+    let file = File::create(path).map_err(|e| Error::ExportError(e.to_string()))?;
+    let mut writer =
+        ArrowWriter::try_new(file, schema, None).map_err(|e| Error::ExportError(e.to_string()))?;
+    writer
+        .write(&batch)
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    writer
+        .close()
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn it_writes_parquet() {
+        let dir = std::env::temp_dir().join("rust-ceda-export-test");
+        let path = partition_path(&dir, 1448, 1994);
+        let date_time =
+            NaiveDateTime::parse_from_str("1994-10-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let rows = vec![
+            ObservationRow {
+                midas_station_id: 1448,
+                date_time,
+                wind_speed: Some(4.0),
+                wind_direction: Some(170.0),
+                wind_unit_id: None,
+                wind_opr_type: None,
+            },
+            ObservationRow {
+                midas_station_id: 1448,
+                date_time,
+                wind_speed: None,
+                wind_direction: None,
+                wind_unit_id: None,
+                wind_opr_type: None,
+            },
+        ];
+
+        write_parquet(&path, &rows, &Columns::default()).unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+
+        assert!(path.ends_with("station=01448/year=1994/observations.parquet"));
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        assert_eq!(
+            reader
+                .metadata()
+                .file_metadata()
+                .schema_descr()
+                .num_columns(),
+            COLUMNS.len()
+        );
+
+        let columns = Columns::select(&["ob_time".to_string(), "wind_speed".to_string()]).unwrap();
+        write_parquet(&path, &rows, &columns).unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+
+        assert_eq!(schema.num_columns(), 2);
+        assert_eq!(schema.column(0).name(), "date_time");
+        assert_eq!(schema.column(1).name(), "wind_speed");
+    }
+
+    #[test]
+    fn it_rejects_unknown_columns() {
+        let result = Columns::select(&["date_time".to_string(), "wind_sped".to_string()]);
+
+        assert!(matches!(
+            result,
+            Err(Error::ExportError(message))
+                if message.starts_with("unknown column `wind_sped`, expected one of:")
+        ));
+        assert!(Columns::select(&[]).is_err());
+    }
+}
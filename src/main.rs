@@ -1,20 +1,182 @@
 mod ceda_client;
 mod ceda_csv_reader;
+mod checkpoint;
 mod cli;
 mod datastore;
 mod db;
 mod error;
+mod export;
+mod manifest;
+mod metrics;
+mod outcome;
+mod settings;
+mod sink;
+#[cfg(test)]
+#[path = "../tests/support/mod.rs"]
+mod support;
+mod table;
 
+use crate::cli::command::{ProcessMode, ProcessOptions, UpdateOptions};
 use crate::cli::{command, Cli, Commands};
 use clap::Parser;
 use error::AppError as Error;
+use outcome::{Outcome, FAILURE_EXIT_CODE, PARTIAL_FAILURE_EXIT_CODE};
+use settings::Settings;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let cli = Cli::parse();
+    let settings = Settings::load(&cli)?;
 
-    match &cli.command {
-        Commands::Update {} => command::update().await,
-        Commands::Process { init } => command::process(*init).await,
+    let outcome = match &cli.command {
+        Commands::Update {
+            stations_file,
+            verbose,
+            since,
+            layout,
+            resume_from,
+            all_versions,
+            deadline,
+            station_timeout,
+            no_cache,
+            cache_ttl,
+            compress,
+            capability_only,
+            metrics_file,
+            print_urls,
+            fresh,
+            filter,
+        } => {
+            let options = UpdateOptions {
+                stations_file: stations_file.clone(),
+                verbose: *verbose,
+                since: *since,
+                layout: (*layout).into(),
+                resume_from: resume_from.clone(),
+                all_versions: *all_versions,
+                deadline: *deadline,
+                station_timeout: *station_timeout,
+                no_cache: *no_cache,
+                cache_ttl: *cache_ttl,
+                compress: *compress,
+                capability_only: *capability_only,
+                metrics_file: metrics_file.clone(),
+                print_urls: print_urls.clone(),
+                fresh: *fresh,
+                filter: filter.clone().into(),
+            };
+            command::update(&settings, options).await.map(|report| {
+                println!("{}", report);
+                report.outcome()
+            })
+        }
+        Commands::Process {
+            mode,
+            init,
+            rebuild,
+            yes,
+            validation,
+            file,
+            input_dir,
+            parse_concurrency,
+            channel_size,
+            incremental,
+            capture_extra,
+            skip_empty,
+            limit_per_station,
+            delete_after_process,
+            commit_every,
+            split_by_county,
+            validate_only,
+            output,
+            out,
+            filter,
+        } => {
+            let options = ProcessOptions {
+                mode: if *init {
+                    ProcessMode::Init
+                } else {
+                    mode.map(ProcessMode::from).unwrap_or_default()
+                },
+                rebuild: *rebuild,
+                yes: *yes,
+                validation: (*validation).into(),
+                files: file.clone(),
+                input_dir: input_dir.clone(),
+                parse_concurrency: *parse_concurrency,
+                channel_size: *channel_size,
+                incremental: *incremental,
+                capture_extra: *capture_extra,
+                skip_empty: *skip_empty,
+                limit_per_station: *limit_per_station,
+                delete_after_process: *delete_after_process,
+                commit_every: *commit_every,
+                split_by_county: *split_by_county,
+                validate_only: *validate_only,
+                output: (*output).into(),
+                out: out.clone(),
+                filter: filter.clone().into(),
+            };
+            command::process(&settings, options).await
+        }
+        Commands::List { gaps, sort_by } => command::list(&settings, *gaps, (*sort_by).into())
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Export {
+            out,
+            output_format,
+            columns,
+        } => command::export(&settings, out.clone(), (*output_format).into(), columns)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Check {} => command::check(&settings).await.map(|_| Outcome::Complete),
+        Commands::CheckToken {} => command::check_token(&settings)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Counties {} => command::counties(&settings)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Download { url, out } => command::download(&settings, url, out)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Optimize {} => command::optimize(&settings)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Archive { out, level } => command::archive(&settings, out.clone(), *level)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Prune { dry_run } => command::prune(&settings, *dry_run)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Nearest {
+            lat,
+            lon,
+            count,
+            pretty,
+        } => command::nearest(&settings, *lat, *lon, *count, *pretty)
+            .await
+            .map(|_| Outcome::Complete),
+        Commands::Summary {
+            station,
+            from,
+            to,
+            pretty,
+        } => command::summary(&settings, *station, *from, *to, *pretty)
+            .await
+            .map(|_| Outcome::Complete),
+    }?;
+
+    match outcome {
+        Outcome::Complete => {}
+        Outcome::Partial { failed, total } => {
+            eprintln!("Completed with failures: {} of {} failed", failed, total);
+            std::process::exit(PARTIAL_FAILURE_EXIT_CODE);
+        }
+        Outcome::Failed { total } => {
+            eprintln!("Failed: all {} failed", total);
+            std::process::exit(FAILURE_EXIT_CODE);
+        }
     }
+
+    Ok(())
 }
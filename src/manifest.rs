@@ -0,0 +1,187 @@
+//! Download manifest
+//!
+//! Records the SHA-256 of each data file downloaded by an `--all-versions` update, so a file that
+//! is identical to the same file in an earlier dataset version is removed instead of being stored
+//! twice, and is not downloaded again.
+
+use crate::error::AppError as Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A data file recorded in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub sha256: String,
+    /// The file in an earlier dataset version this one is identical to, if it was removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+}
+
+/// The data files downloaded across dataset versions, by filename
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(skip)]
+    path: PathBuf,
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Load the manifest saved at a path, or start an empty one if there is none
+    pub fn load(path: PathBuf) -> Self {
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Self {
+                path,
+                ..Default::default()
+            };
+        };
+
+        match serde_json::from_str::<Manifest>(&contents) {
+            Ok(manifest) => Self { path, ..manifest },
+            Err(e) => {
+                println!("Ignoring unreadable manifest {}: {}", path.display(), e);
+                Self {
+                    path,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, filename: &str) -> bool {
+        self.files.contains_key(filename)
+    }
+
+    /// Whether a data file was removed as identical to one in an earlier dataset version
+    pub fn is_duplicate(&self, filename: &str) -> bool {
+        self.files
+            .get(filename)
+            .is_some_and(|entry| entry.duplicate_of.is_some())
+    }
+
+    /// Record a data file stored in a directory, compressed or not. If it is identical to the
+    /// same file of another dataset version it is removed, and `true` is returned.
+    pub fn add(&mut self, dir: &Path, filename: &str) -> Result<bool, Error> {
+        let gz_path = dir.join(format!("{}.gz", filename));
+        let path = if gz_path.exists() {
+            gz_path
+        } else {
+            dir.join(filename)
+        };
+        let sha256 = sha256(&path)?;
+
+        let key = unversioned(filename);
+        let original = self
+            .files
+            .iter()
+            .find(|(other, entry)| {
+                entry.duplicate_of.is_none()
+                    && entry.sha256 == sha256
+                    && unversioned(other) == key
+                    && other.as_str() != filename
+            })
+            .map(|(other, _)| other.clone());
+        if original.is_some() {
+            std::fs::remove_file(&path).map_err(|e| Error::FileWriteError(e.to_string()))?;
+        }
+
+        let removed = original.is_some();
+        self.files.insert(
+            filename.to_string(),
+            ManifestEntry {
+                sha256,
+                duplicate_of: original,
+            },
+        );
+
+        Ok(removed)
+    }
+
+    /// Save the manifest, replacing the file in one step
+    pub fn save(&self) -> Result<(), Error> {
+        let json =
+            serde_json::to_string_pretty(self).map_err(|e| Error::FileWriteError(e.to_string()))?;
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::FileWriteError(e.to_string()))?;
+        }
+        std::fs::write(&tmp_path, json)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path))
+            .map_err(|e| Error::FileWriteError(e.to_string()))
+    }
+}
+
+/// A data filename without its dataset version, which is the same in every version
+fn unversioned(filename: &str) -> String {
+    filename
+        .split('_')
+        .filter(|part| !part.starts_with("dv-"))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// The hex SHA-256 of a file
+fn sha256(path: &Path) -> Result<String, Error> {
+    let mut file = std::fs::File::open(path).map_err(|_| Error::FileReadError)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|_| Error::FileReadError)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str =
+        "midas-open_uk-hourly-weather-obs_dv-202308_antrim_01448_portglenone_qcv-1_1994.csv";
+    const NEW: &str =
+        "midas-open_uk-hourly-weather-obs_dv-202407_antrim_01448_portglenone_qcv-1_1994.csv";
+
+    #[test]
+    fn it_removes_files_identical_across_versions() {
+        let dir = std::env::temp_dir().join("rust-ceda-manifest-test");
+        let (old_dir, new_dir) = (dir.join("dv-202308"), dir.join("dv-202407"));
+        std::fs::create_dir_all(&old_dir).unwrap();
+        std::fs::create_dir_all(&new_dir).unwrap();
+        std::fs::write(old_dir.join(OLD), "ob_time\n1994-01-01\n").unwrap();
+        std::fs::write(new_dir.join(NEW), "ob_time\n1994-01-01\n").unwrap();
+
+        let path = dir.join("manifest.json");
+        let mut manifest = Manifest::load(path.clone());
+        assert!(!manifest.add(&old_dir, OLD).unwrap());
+        assert!(manifest.add(&new_dir, NEW).unwrap());
+        manifest.save().unwrap();
+
+        assert!(old_dir.join(OLD).exists());
+        assert!(!new_dir.join(NEW).exists());
+        let loaded = Manifest::load(path);
+        assert!(loaded.contains(OLD));
+        assert!(!loaded.is_duplicate(OLD));
+        assert!(loaded.is_duplicate(NEW));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_keeps_files_changed_between_versions() {
+        let dir = std::env::temp_dir().join("rust-ceda-manifest-changed-test");
+        let (old_dir, new_dir) = (dir.join("dv-202308"), dir.join("dv-202407"));
+        std::fs::create_dir_all(&old_dir).unwrap();
+        std::fs::create_dir_all(&new_dir).unwrap();
+        std::fs::write(old_dir.join(OLD), "ob_time\n1994-01-01\n").unwrap();
+        std::fs::write(new_dir.join(NEW), "ob_time\n1994-01-02\n").unwrap();
+
+        let mut manifest = Manifest::load(dir.join("manifest.json"));
+        assert!(!manifest.add(&old_dir, OLD).unwrap());
+        assert!(!manifest.add(&new_dir, NEW).unwrap());
+
+        assert!(new_dir.join(NEW).exists());
+        assert!(!manifest.is_duplicate(NEW));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
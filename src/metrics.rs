@@ -0,0 +1,110 @@
+//! Crawl metrics in the Prometheus text format, for a node_exporter textfile collector.
+
+use crate::cli::command::UpdateReport;
+use crate::error::AppError as Error;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Render an update report in the Prometheus text exposition format
+pub fn to_prometheus(report: &UpdateReport) -> String {
+    let metrics: [(&str, &str, String); 7] = [
+        (
+            "counties_discovered",
+            "Counties discovered by the last update",
+            report.counties.to_string(),
+        ),
+        (
+            "stations_discovered",
+            "Stations discovered by the last update",
+            report.stations.to_string(),
+        ),
+        (
+            "files_downloaded",
+            "Data files downloaded by the last update",
+            report.files_downloaded.to_string(),
+        ),
+        (
+            "files_skipped",
+            "Data files already in the datastore at the last update",
+            report.files_skipped.to_string(),
+        ),
+        (
+            "bytes_downloaded",
+            "Bytes downloaded by the last update",
+            report.bytes_downloaded.to_string(),
+        ),
+        (
+            "errors",
+            "Stations and files that failed in the last update",
+            report.errors.to_string(),
+        ),
+        (
+            "duration_seconds",
+            "Duration of the last update",
+            report.duration.as_secs_f64().to_string(),
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value) in metrics {
+        let _ = writeln!(text, "# HELP rust_ceda_update_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE rust_ceda_update_{} gauge", name);
+        let _ = writeln!(text, "rust_ceda_update_{} {}", name, value);
+    }
+
+    text
+}
+
+/// Write an update report to a Prometheus textfile. The file is replaced in one step, so a
+/// collector never reads it half written.
+pub fn write_textfile(report: &UpdateReport, path: &Path) -> Result<(), Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    std::fs::write(&tmp_path, to_prometheus(report))
+        .and_then(|_| std::fs::rename(&tmp_path, path))
+        .map_err(|e| Error::MetricsWriteError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn it_renders_prometheus_text() {
+        let report = UpdateReport {
+            counties: 2,
+            stations: 10,
+            files_downloaded: 7,
+            files_skipped: 3,
+            bytes_downloaded: 4096,
+            errors: 1,
+            total: 20,
+            duration: Duration::from_millis(1500),
+            timed_out_stations: Vec::new(),
+        };
+
+        let text = to_prometheus(&report);
+
+        assert!(text.contains(
+            "# HELP rust_ceda_update_files_downloaded Data files downloaded by the last update\n"
+        ));
+        assert!(text.contains("# TYPE rust_ceda_update_files_downloaded gauge\n"));
+        assert!(text.contains("rust_ceda_update_counties_discovered 2\n"));
+        assert!(text.contains("rust_ceda_update_files_downloaded 7\n"));
+        assert!(text.contains("rust_ceda_update_bytes_downloaded 4096\n"));
+        assert!(text.contains("rust_ceda_update_duration_seconds 1.5\n"));
+        assert_eq!(text.lines().count(), 7 * 3);
+    }
+
+    #[test]
+    fn it_writes_metrics_file() {
+        let path = std::env::temp_dir().join("rust-ceda-metrics-test.prom");
+
+        write_textfile(&UpdateReport::default(), &path).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("rust_ceda_update_errors 0\n"));
+    }
+}
@@ -0,0 +1,48 @@
+//! How completely a command did its work.
+
+/// Exit code for a run that finished but failed some of its work
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 2;
+/// Exit code for a run that finished but failed all of its work
+pub const FAILURE_EXIT_CODE: i32 = 3;
+
+/// The result of a command that carried on past individual failures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Everything was done
+    Complete,
+    /// `failed` of `total` units of work, e.g. files, failed
+    Partial { failed: usize, total: usize },
+    /// All `total` units of work failed
+    Failed { total: usize },
+}
+
+impl Outcome {
+    /// The outcome of a run where `failed` of `total` units of work failed
+    pub fn from_counts(failed: usize, total: usize) -> Self {
+        if failed == 0 {
+            Outcome::Complete
+        } else if failed == total {
+            Outcome::Failed { total }
+        } else {
+            Outcome::Partial { failed, total }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_gets_outcome_from_counts() {
+        assert_eq!(Outcome::from_counts(0, 10), Outcome::Complete);
+        assert_eq!(
+            Outcome::from_counts(3, 10),
+            Outcome::Partial {
+                failed: 3,
+                total: 10
+            }
+        );
+        assert_eq!(Outcome::from_counts(10, 10), Outcome::Failed { total: 10 });
+    }
+}
@@ -0,0 +1,369 @@
+//! Application settings
+//!
+//! Settings are merged from, in order of precedence, the command line, a `rust-ceda.toml` config
+//! file and the environment, e.g. `CEDA_CONCURRENCY` and `CEDA_RATE`.
+
+use crate::ceda_client::{
+    AuthMode, CedaClient, CrawlPolicy, SecretString, DEFAULT_POOL_IDLE_TIMEOUT,
+    DEFAULT_POOL_MAX_IDLE_PER_HOST,
+};
+use crate::cli::Cli;
+use crate::datastore::DataStore;
+use crate::db::{self, Database};
+use crate::error::AppError as Error;
+use serde::{Deserialize, Deserializer};
+use std::env;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+const CONFIG_FILE_NAME: &str = "rust-ceda.toml";
+const DEFAULT_DATASET_VERSION: &str = "202407";
+
+/// Settings read from a config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileSettings {
+    pub data_dir: Option<PathBuf>,
+    pub dataset_version: Option<String>,
+    pub access_token: Option<SecretString>,
+    pub concurrency: Option<NonZeroUsize>,
+    pub rate: Option<NonZeroU32>,
+    pub polite: Option<bool>,
+    pub db_pool_size: Option<u32>,
+    pub user_agent: Option<String>,
+    pub db_path: Option<PathBuf>,
+    pub auth_mode: Option<AuthMode>,
+    pub pool_max_idle_per_host: Option<usize>,
+    /// e.g. "90s"
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub pool_idle_timeout: Option<Duration>,
+}
+
+impl FileSettings {
+    /// Read settings from a config file
+    pub fn read(path: &PathBuf) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path).map_err(|_| Error::FileReadError)?;
+
+        toml::from_str(&contents).map_err(|e| Error::ConfigError(e.to_string()))
+    }
+
+    /// Find the config file in the working directory or the user config directory
+    fn find() -> Option<PathBuf> {
+        let local = PathBuf::from(CONFIG_FILE_NAME);
+        if local.is_file() {
+            return Some(local);
+        }
+
+        let config_dir = env::var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .ok()?;
+        let user = config_dir.join("rust-ceda").join(CONFIG_FILE_NAME);
+
+        user.is_file().then_some(user)
+    }
+}
+
+/// Settings consumed by the commands
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Data directory, or `None` to use the `DATA_DIR` environment variable
+    pub data_dir: Option<PathBuf>,
+    pub dataset_version: String,
+    /// CEDA access token, or `None` to use the `CEDA_ACCESS_TOKEN` environment variable
+    pub access_token: Option<SecretString>,
+    /// Maximum concurrent requests to CEDA, or `None` for no limit
+    pub concurrency: Option<NonZeroUsize>,
+    /// Maximum requests per second to CEDA, or `None` for no limit
+    pub rate: Option<NonZeroU32>,
+    /// Start from the polite crawl policy rather than the default
+    pub polite: bool,
+    /// Database connections: one writer and the rest read-only
+    pub db_pool_size: u32,
+    /// `User-Agent` sent to CEDA, or `None` for the default
+    pub user_agent: Option<String>,
+    /// Database file, or `None` for `CEDA_DB_PATH` or the datastore's database
+    pub db_path: Option<PathBuf>,
+    /// How the access token is sent to CEDA
+    pub auth_mode: AuthMode,
+    /// Skip TLS certificate verification, which is unsafe
+    pub danger_accept_invalid_certs: bool,
+    /// Idle HTTP connections kept open to each host, or `None` for the client's default
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long an idle HTTP connection is kept open, or `None` for the client's default
+    pub pool_idle_timeout: Option<Duration>,
+}
+
+impl Settings {
+    /// Load the settings for a command line invocation
+    pub fn load(cli: &Cli) -> Result<Self, Error> {
+        let file_settings = match cli.config.as_ref().cloned().or_else(FileSettings::find) {
+            Some(path) => FileSettings::read(&path)?,
+            None => FileSettings::default(),
+        };
+
+        dotenv::dotenv().ok();
+        Settings::merge(cli, file_settings).with_env_limits(
+            env::var("CEDA_CONCURRENCY").ok(),
+            env::var("CEDA_RATE").ok(),
+        )
+    }
+
+    /// Take the crawl limits nothing else set from the values of `CEDA_CONCURRENCY` and
+    /// `CEDA_RATE`. The environment ranks lowest, so it doesn't loosen the polite preset either.
+    fn with_env_limits(
+        mut self,
+        concurrency: Option<String>,
+        rate: Option<String>,
+    ) -> Result<Self, Error> {
+        if self.polite {
+            return Ok(self);
+        }
+        if self.concurrency.is_none() {
+            self.concurrency = parse_env_setting("CEDA_CONCURRENCY", concurrency)?;
+        }
+        if self.rate.is_none() {
+            self.rate = parse_env_setting("CEDA_RATE", rate)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Merge the command line over the config file settings
+    pub fn merge(cli: &Cli, file_settings: FileSettings) -> Self {
+        Self {
+            data_dir: cli.data_dir.clone().or(file_settings.data_dir),
+            dataset_version: cli
+                .dataset_version
+                .clone()
+                .or(file_settings.dataset_version)
+                .unwrap_or_else(|| DEFAULT_DATASET_VERSION.to_string()),
+            access_token: cli
+                .access_token
+                .clone()
+                .map(SecretString::from)
+                .or(file_settings.access_token),
+            concurrency: cli.concurrency.or(file_settings.concurrency),
+            rate: cli.rate.or(file_settings.rate),
+            polite: cli.polite || file_settings.polite.unwrap_or_default(),
+            db_pool_size: cli
+                .db_pool_size
+                .or(file_settings.db_pool_size)
+                .unwrap_or(db::DEFAULT_POOL_SIZE),
+            user_agent: cli.user_agent.clone().or(file_settings.user_agent),
+            db_path: cli.db_path.clone().or(file_settings.db_path),
+            auth_mode: cli
+                .auth_mode
+                .map(AuthMode::from)
+                .or(file_settings.auth_mode)
+                .unwrap_or_default(),
+            danger_accept_invalid_certs: cli.danger_accept_invalid_certs,
+            pool_max_idle_per_host: cli
+                .pool_max_idle_per_host
+                .or(file_settings.pool_max_idle_per_host),
+            pool_idle_timeout: cli.pool_idle_timeout.or(file_settings.pool_idle_timeout),
+        }
+    }
+
+    /// The data directory, falling back to the environment
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        self.data_dir.clone().or_else(DataStore::try_get_data_dir)
+    }
+
+    /// The datastore in the configured data directory
+    pub fn datastore(&self) -> DataStore {
+        match &self.data_dir {
+            Some(root) => DataStore::with_root(root.clone()),
+            None => DataStore::new(),
+        }
+    }
+
+    /// The crawl policy: the polite or default preset, with any concurrency and rate set over it
+    pub fn crawl_policy(&self) -> CrawlPolicy {
+        let policy = if self.polite {
+            CrawlPolicy::polite()
+        } else {
+            CrawlPolicy::default()
+        };
+
+        CrawlPolicy {
+            concurrency: self.concurrency.or(policy.concurrency),
+            rate: self.rate.or(policy.rate),
+            ..policy
+        }
+    }
+
+    /// The database file, falling back to the environment and then the datastore
+    pub fn db_path(&self) -> PathBuf {
+        self.db_path
+            .clone()
+            .or_else(|| env::var("CEDA_DB_PATH").ok().map(PathBuf::from))
+            .unwrap_or_else(|| Database::default_path(&self.datastore()))
+    }
+
+    /// A CEDA client for the configured dataset version
+    pub fn client(&self) -> Result<CedaClient, Error> {
+        let access_token = match &self.access_token {
+            Some(access_token) => access_token.clone(),
+            None => SecretString::new(CedaClient::get_access_token()?),
+        };
+        let client =
+            CedaClient::with_auth(&self.dataset_version, access_token.expose(), self.auth_mode)?
+                .with_policy(self.crawl_policy());
+
+        let client = match &self.user_agent {
+            Some(user_agent) => client.with_user_agent(user_agent)?,
+            None => client,
+        };
+        let client = match (self.pool_max_idle_per_host, self.pool_idle_timeout) {
+            (None, None) => client,
+            (max_idle_per_host, idle_timeout) => client.with_pool(
+                max_idle_per_host.unwrap_or(DEFAULT_POOL_MAX_IDLE_PER_HOST),
+                idle_timeout.unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT),
+            )?,
+        };
+
+        if self.danger_accept_invalid_certs {
+            println!("Warning: TLS certificate verification is disabled");
+            client.with_invalid_certs_accepted()
+        } else {
+            Ok(client)
+        }
+    }
+}
+
+/// Deserialize a duration written in the humantime format e.g. "90s", "2m"
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| humantime::parse_duration(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Parse a setting from the value of an environment variable, if it is set
+fn parse_env_setting<T: FromStr>(name: &str, value: Option<String>) -> Result<Option<T>, Error> {
+    value
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| Error::ConfigError(format!("invalid {}: {}", name, value)))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn it_parses_file_settings() {
+        let file_settings: FileSettings =
+            toml::from_str("data_dir = \"/data\"\nconcurrency = 8\nrate = 2\n").unwrap();
+
+        assert_eq!(file_settings.data_dir, Some(PathBuf::from("/data")));
+        assert_eq!(file_settings.concurrency, NonZeroUsize::new(8));
+        assert_eq!(file_settings.rate, NonZeroU32::new(2));
+        assert_eq!(file_settings.dataset_version, None);
+
+        assert!(toml::from_str::<FileSettings>("concurrency = 0\n").is_err());
+    }
+
+    #[test]
+    fn it_parses_pool_settings() {
+        let file_settings: FileSettings =
+            toml::from_str("pool_max_idle_per_host = 4\npool_idle_timeout = \"2m\"\n").unwrap();
+        assert_eq!(
+            file_settings.pool_idle_timeout,
+            Some(Duration::from_secs(120))
+        );
+        assert!(toml::from_str::<FileSettings>("pool_idle_timeout = \"soon\"\n").is_err());
+
+        let cli = Cli::parse_from(["rust-ceda", "--pool-idle-timeout", "30s", "list"]);
+        let settings = Settings::merge(&cli, file_settings);
+
+        assert_eq!(settings.pool_max_idle_per_host, Some(4));
+        assert_eq!(settings.pool_idle_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn it_prefers_cli_over_file() {
+        let cli = Cli::parse_from(["rust-ceda", "--dataset-version", "202507", "list"]);
+        let file_settings = FileSettings {
+            dataset_version: Some("202407".to_string()),
+            concurrency: NonZeroUsize::new(8),
+            ..Default::default()
+        };
+
+        let settings = Settings::merge(&cli, file_settings);
+
+        assert_eq!(settings.dataset_version, "202507");
+        assert_eq!(settings.concurrency, NonZeroUsize::new(8));
+        assert_eq!(settings.data_dir, None);
+    }
+
+    #[test]
+    fn it_sets_limits_over_polite_policy() {
+        let cli = Cli::parse_from(["rust-ceda", "--polite", "--concurrency", "8", "list"]);
+
+        let settings = Settings::merge(&cli, FileSettings::default());
+        let policy = settings.crawl_policy();
+
+        assert_eq!(policy.concurrency, NonZeroUsize::new(8));
+        assert_eq!(policy.rate, CrawlPolicy::polite().rate);
+        assert_eq!(policy.max_retries, CrawlPolicy::polite().max_retries);
+
+        let cli = Cli::parse_from(["rust-ceda", "list"]);
+        let settings = Settings::merge(&cli, FileSettings::default());
+        assert_eq!(settings.crawl_policy(), CrawlPolicy::default());
+    }
+
+    #[test]
+    fn it_parses_env_settings() {
+        assert_eq!(
+            parse_env_setting::<NonZeroUsize>("CEDA_CONCURRENCY", Some("8".to_string())).unwrap(),
+            NonZeroUsize::new(8)
+        );
+        assert!(
+            parse_env_setting::<NonZeroUsize>("CEDA_CONCURRENCY", Some("0".to_string())).is_err()
+        );
+        assert_eq!(
+            parse_env_setting::<NonZeroU32>("CEDA_RATE", None).unwrap(),
+            None
+        );
+        assert!(parse_env_setting::<NonZeroU32>("CEDA_RATE", Some("0".to_string())).is_err());
+    }
+
+    #[test]
+    fn it_ranks_env_limits_below_polite_policy() {
+        let env_limits = || (Some("50".to_string()), Some("20".to_string()));
+
+        let cli = Cli::parse_from(["rust-ceda", "--polite", "list"]);
+        let (concurrency, rate) = env_limits();
+        let settings = Settings::merge(&cli, FileSettings::default())
+            .with_env_limits(concurrency, rate)
+            .unwrap();
+        assert_eq!(settings.crawl_policy(), CrawlPolicy::polite());
+
+        let cli = Cli::parse_from(["rust-ceda", "--rate", "5", "list"]);
+        let (concurrency, rate) = env_limits();
+        let settings = Settings::merge(&cli, FileSettings::default())
+            .with_env_limits(concurrency, rate)
+            .unwrap();
+        assert_eq!(settings.concurrency, NonZeroUsize::new(50));
+        assert_eq!(settings.rate, NonZeroU32::new(5));
+    }
+
+    #[test]
+    fn it_defaults_dataset_version() {
+        let cli = Cli::parse_from(["rust-ceda", "list"]);
+
+        let settings = Settings::merge(&cli, FileSettings::default());
+
+        assert_eq!(settings.dataset_version, DEFAULT_DATASET_VERSION);
+    }
+}
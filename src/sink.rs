@@ -0,0 +1,197 @@
+//! Destinations for parsed observations.
+
+use crate::ceda_csv_reader::{CedaCsvReader, Observation};
+use crate::error::AppError as Error;
+use chrono::NaiveDateTime;
+use futures::{Stream, StreamExt};
+use indicatif::ProgressBar;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::pin::pin;
+
+/// Where processed observations are written
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// The SQLite database in the datastore
+    #[default]
+    Db,
+    /// A single normalized CSV file
+    Csv,
+}
+
+/// A destination for the stations and observations parsed from data files
+pub trait ObservationSink {
+    /// Write the station a data file belongs to, before its observations
+    async fn write_station(&mut self, record: &CedaCsvReader) -> Result<(), Error>;
+
+    /// Write a single observation for a station
+    async fn write_observation(
+        &mut self,
+        midas_station_id: u32,
+        observation: &Observation,
+    ) -> Result<(), Error>;
+
+    /// Finish writing a data file
+    async fn flush(&mut self, path: &Path) -> Result<(), Error>;
+
+    /// Check whether a data file has already been written to the sink
+    async fn is_processed(&self, _path: &Path) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Get the newest observation already written for a station, if the sink can tell
+    async fn latest_observation(
+        &self,
+        _midas_station_id: u32,
+    ) -> Result<Option<NaiveDateTime>, Error> {
+        Ok(None)
+    }
+
+    /// Write up to `limit` of a data file's observations as they are parsed, after its station,
+    /// advancing the progress bar as each observation is written. Returns the number of
+    /// observations written.
+    async fn write_record(
+        &mut self,
+        path: &Path,
+        record: &CedaCsvReader,
+        observations: impl Stream<Item = Result<Observation, Error>>,
+        limit: Option<usize>,
+        progress: &ProgressBar,
+    ) -> Result<usize, Error> {
+        let mut observations = pin!(observations);
+        let mut written = 0;
+        while let Some(observation) = observations.next().await {
+            if limit == Some(written) {
+                break;
+            }
+            self.write_observation(record.midas_station_id, &observation?)
+                .await?;
+            progress.inc(1);
+            written += 1;
+        }
+        self.flush(path).await?;
+
+        Ok(written)
+    }
+}
+
+/// Writes observations as rows of a single CSV file, or stdout
+pub struct CsvSink {
+    writer: csv::Writer<Box<dyn Write>>,
+}
+
+impl CsvSink {
+    /// Create a CSV sink writing to a file, or stdout when no path is given
+    pub fn new(path: Option<&Path>) -> Result<Self, Error> {
+        let out: Box<dyn Write> = match path {
+            Some(path) => {
+                Box::new(File::create(path).map_err(|e| Error::ExportError(e.to_string()))?)
+            }
+            None => Box::new(std::io::stdout()),
+        };
+
+        let mut writer = csv::Writer::from_writer(out);
+        writer
+            .write_record([
+                "midas_station_id",
+                "date_time",
+                "wind_speed",
+                "wind_direction",
+                "wind_unit_id",
+                "wind_opr_type",
+                "gust_speed",
+                "gust_direction",
+                "gust_time",
+            ])
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+
+        Ok(Self { writer })
+    }
+}
+
+impl ObservationSink for CsvSink {
+    async fn write_station(&mut self, _record: &CedaCsvReader) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn write_observation(
+        &mut self,
+        midas_station_id: u32,
+        observation: &Observation,
+    ) -> Result<(), Error> {
+        self.writer
+            .write_record([
+                midas_station_id.to_string(),
+                observation
+                    .date_time
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                optional(observation.wind.speed),
+                optional(observation.wind.direction),
+                optional(observation.wind.unit_id),
+                optional(observation.wind.opr_type),
+                optional(observation.wind.gust_speed),
+                optional(observation.wind.gust_direction),
+                optional(
+                    observation
+                        .wind
+                        .gust_time
+                        .map(|gust_time| gust_time.format("%H:%M")),
+                ),
+            ])
+            .map_err(|e| Error::ExportError(e.to_string()))
+    }
+
+    async fn flush(&mut self, _path: &Path) -> Result<(), Error> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::ExportError(e.to_string()))
+    }
+}
+
+/// Format an optional value as a CSV field, empty when missing
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ceda_csv_reader::WindObservation;
+
+    #[tokio::test]
+    async fn it_writes_csv() {
+        let path = std::env::temp_dir().join("rust-ceda-sink.csv");
+        let observation = Observation {
+            date_time: NaiveDateTime::parse_from_str("1994-10-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            _id: 3915,
+            wind: WindObservation {
+                speed: Some(4.0),
+                direction: Some(170.0),
+                unit_id: None,
+                opr_type: Some(1),
+                ..Default::default()
+            },
+            met_domain_name: None,
+            id_type: None,
+            version_num: None,
+            dataset_version: None,
+            extra: None,
+        };
+
+        let mut sink = CsvSink::new(Some(&path)).unwrap();
+        sink.write_observation(1448, &observation).await.unwrap();
+        sink.flush(&path).await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "midas_station_id,date_time,wind_speed,wind_direction,wind_unit_id,wind_opr_type,gust_speed,gust_direction,gust_time"
+        );
+        assert_eq!(lines[1], "1448,1994-10-01 00:00:00,4,170,,1,,,");
+    }
+}
@@ -0,0 +1,118 @@
+//! Tabular command output, as CSV for piping or as a bordered table for the terminal.
+
+use crate::error::AppError as Error;
+use comfy_table::presets::UTF8_FULL_CONDENSED;
+use comfy_table::{CellAlignment, ContentArrangement};
+
+/// Rows of text under a header, rendered as CSV or as a table
+#[derive(Debug, Default)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Self {
+        Self {
+            header: header.iter().map(|column| column.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// Render as a bordered table when `pretty`, and otherwise as CSV
+    pub fn render(&self, pretty: bool) -> Result<String, Error> {
+        if pretty {
+            Ok(self.to_pretty())
+        } else {
+            self.to_csv()
+        }
+    }
+
+    /// Render as CSV with a header row
+    pub fn to_csv(&self) -> Result<String, Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer
+            .write_record(&self.header)
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+        for row in &self.rows {
+            writer
+                .write_record(row)
+                .map_err(|e| Error::ExportError(e.to_string()))?;
+        }
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+
+        String::from_utf8(bytes).map_err(|e| Error::ExportError(e.to_string()))
+    }
+
+    /// Render as a bordered table fitted to the terminal width, with numeric columns right
+    /// aligned
+    pub fn to_pretty(&self) -> String {
+        let mut table = comfy_table::Table::new();
+        table
+            .load_preset(UTF8_FULL_CONDENSED)
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(&self.header)
+            .add_rows(&self.rows);
+
+        for (index, column) in table.column_iter_mut().enumerate() {
+            if self.is_numeric(index) {
+                column.set_cell_alignment(CellAlignment::Right);
+            }
+        }
+
+        table.to_string()
+    }
+
+    /// Whether every value in a column is a number or `-` for a missing one
+    fn is_numeric(&self, index: usize) -> bool {
+        !self.rows.is_empty()
+            && self.rows.iter().all(|row| {
+                row.get(index)
+                    .is_some_and(|value| value == "-" || value.parse::<f64>().is_ok())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations() -> Table {
+        let mut table = Table::new(&["id", "station", "km"]);
+        table.add_row(vec!["01448".into(), "portglenone".into(), "3.2".into()]);
+        table.add_row(vec![
+            "00144".into(),
+            "corgarff, castle lodge".into(),
+            "120.5".into(),
+        ]);
+        table
+    }
+
+    #[test]
+    fn it_renders_csv() {
+        assert_eq!(
+            stations().render(false).unwrap(),
+            "id,station,km\n01448,portglenone,3.2\n00144,\"corgarff, castle lodge\",120.5\n"
+        );
+    }
+
+    #[test]
+    fn it_renders_pretty_table() {
+        let text = stations().render(true).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert!(lines[0].starts_with('┌'));
+        assert!(text.contains("┆ corgarff, castle lodge ┆"));
+        // Numeric columns are right aligned
+        assert!(text.contains("┆   3.2 │"));
+        // Every line is as wide as the border
+        let width = lines[0].chars().count();
+        assert!(lines.iter().all(|line| line.chars().count() == width));
+    }
+}
@@ -0,0 +1,239 @@
+//! Synthetic MIDAS CSV files for tests
+//!
+//! Builds data files in the BADC-CSV layout `ceda_csv_reader` expects, with configurable
+//! columns, row counts and header lines, and lets tests inject malformed rows.
+
+// Each test uses only part of the builder
+#![allow(dead_code)]
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Columns of an hourly weather observation file, in the order CEDA publishes them
+pub const DEFAULT_COLUMNS: [&str; 13] = [
+    "ob_time",
+    "id_type",
+    "met_domain_name",
+    "version_num",
+    "src_id",
+    "rec_st_ind",
+    "wind_speed_unit_id",
+    "src_opr_type",
+    "wind_direction",
+    "wind_speed",
+    "max_gust_dir",
+    "max_gust_speed",
+    "max_gust_ctime",
+];
+
+/// A synthetic MIDAS data file
+#[derive(Debug, Clone)]
+pub struct MidasCsv {
+    pub station_id: u32,
+    pub county_name: String,
+    pub station_name: String,
+    pub year: u32,
+    columns: Vec<String>,
+    row_count: usize,
+    /// Header lines added after the standard ones
+    header_lines: Vec<String>,
+    /// Rows added verbatim after the generated ones
+    raw_rows: Vec<String>,
+    quoted: bool,
+}
+
+impl Default for MidasCsv {
+    fn default() -> Self {
+        Self {
+            station_id: 1448,
+            county_name: "antrim".to_string(),
+            station_name: "portglenone".to_string(),
+            year: 1994,
+            columns: DEFAULT_COLUMNS
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+            row_count: 3,
+            header_lines: Vec::new(),
+            raw_rows: Vec::new(),
+            quoted: false,
+        }
+    }
+}
+
+impl MidasCsv {
+    /// A file of three hourly observations at Portglenone in 1994
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write these columns instead of the default ones
+    pub fn with_columns(mut self, columns: &[&str]) -> Self {
+        self.columns = columns.iter().map(|column| column.to_string()).collect();
+        self
+    }
+
+    /// Leave out a column
+    pub fn without_column(mut self, column: &str) -> Self {
+        self.columns.retain(|name| name != column);
+        self
+    }
+
+    /// Date the file and its observations in this year
+    pub fn with_year(mut self, year: u32) -> Self {
+        self.year = year;
+        self
+    }
+
+    /// Generate this many hourly rows. Rows past the first 336 days run on into the following
+    /// years, outside the file's valid dates.
+    pub fn with_rows(mut self, row_count: usize) -> Self {
+        self.row_count = row_count;
+        self
+    }
+
+    /// Add a metadata line after the standard header lines e.g. `met_domain_name,G,WMHOUR`
+    pub fn with_header_line(mut self, line: &str) -> Self {
+        self.header_lines.push(line.to_string());
+        self
+    }
+
+    /// Add a row verbatim after the generated rows, e.g. a malformed one
+    pub fn with_raw_row(mut self, row: &str) -> Self {
+        self.raw_rows.push(row.to_string());
+        self
+    }
+
+    /// Quote every value of the generated rows
+    pub fn quoted(mut self) -> Self {
+        self.quoted = true;
+        self
+    }
+
+    /// The MIDAS filename of the file
+    pub fn filename(&self) -> String {
+        format!(
+            "midas-open_uk-hourly-weather-obs_dv-202407_{}_{:05}_{}_qcv-1_{}.csv",
+            self.county_name, self.station_id, self.station_name, self.year
+        )
+    }
+
+    /// The contents of the file
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec![
+            "Conventions,G,BADC-CSV,1".to_string(),
+            "title,G,uk-hourly-weather-obs".to_string(),
+            "source,G,Met Office MIDAS database".to_string(),
+            "creator,G,Met Office".to_string(),
+            "activity,G,Met Office MIDAS Open: UK Land Surface Stations Data".to_string(),
+            "feature_type,G,point collection".to_string(),
+            "collection_name,G,midas-open".to_string(),
+            "collection_version_number,G,dataset-version-202407".to_string(),
+            "history,G,Created 2024-08-05".to_string(),
+            "last_revised_date,G,2024-07-04".to_string(),
+            format!("observation_station,G,{}", self.station_name),
+            format!("historic_county_name,G,{}", self.county_name),
+            format!("src_id,G,{:05}", self.station_id),
+            format!("midas_station_id,G,{}", self.station_id),
+            "location,G,54.865,-6.458".to_string(),
+            "height,G,64,m".to_string(),
+            format!(
+                "date_valid,G,{0}-01-01 00:00:00,{0}-12-31 23:59:59",
+                self.year
+            ),
+            "coordinate_variable,1,x".to_string(),
+        ];
+        lines.extend(self.header_lines.iter().cloned());
+        lines.push("data".to_string());
+        lines.push(self.columns.join(","));
+        for row in 0..self.row_count {
+            let values: Vec<String> = self
+                .columns
+                .iter()
+                .map(|column| self.value(column, row))
+                .map(|value| {
+                    if self.quoted {
+                        format!("\"{}\"", value)
+                    } else {
+                        value
+                    }
+                })
+                .collect();
+            lines.push(values.join(","));
+        }
+        lines.extend(self.raw_rows.iter().cloned());
+        lines.push("end data".to_string());
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Write the file to a directory under its MIDAS filename
+    pub fn write(&self, dir: &Path) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join(self.filename());
+        std::fs::write(&path, self.to_csv()).unwrap();
+
+        path
+    }
+
+    /// Write the file gzipped to a directory, as `<filename>.gz`
+    pub fn write_gz(&self, dir: &Path) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join(format!("{}.gz", self.filename()));
+        let mut encoder =
+            GzEncoder::new(std::fs::File::create(&path).unwrap(), Compression::fast());
+        encoder.write_all(self.to_csv().as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        path
+    }
+
+    /// A plausible value of a column in a row, hourly from the start of the year with every month
+    /// cut to 28 days so each date is valid, and every year to 336 days
+    fn value(&self, column: &str, row: usize) -> String {
+        match column {
+            "ob_time" => {
+                let day = row / 24;
+                format!(
+                    "{}-{:02}-{:02} {:02}:00:00",
+                    self.year as usize + day / 336,
+                    1 + day / 28 % 12,
+                    1 + day % 28,
+                    row % 24
+                )
+            }
+            "id_type" => "DCNN".to_string(),
+            "met_domain_name" => "SYNOP".to_string(),
+            "version_num" => "1".to_string(),
+            "src_id" | "id" | "ob_id" => self.station_id.to_string(),
+            "rec_st_ind" => "1011".to_string(),
+            "wind_speed_unit_id" => "4".to_string(),
+            "src_opr_type" => "1".to_string(),
+            "wind_direction" | "max_gust_dir" => (row * 10 % 360).to_string(),
+            "wind_speed" => (row % 20).to_string(),
+            "max_gust_speed" => (row % 20 + 5).to_string(),
+            "max_gust_ctime" => format!("{:02}00", row % 24),
+            _ => "1".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn it_never_repeats_an_observation_time() {
+        let csv = MidasCsv::new().with_rows(2 * 336 * 24 + 1);
+
+        let times: HashSet<String> = (0..csv.row_count)
+            .map(|row| csv.value("ob_time", row))
+            .collect();
+
+        assert_eq!(times.len(), csv.row_count);
+        assert_eq!(csv.value("ob_time", 336 * 24), "1995-01-01 00:00:00");
+    }
+}